@@ -0,0 +1,144 @@
+#![feature(allocator_api)]
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::alloc::{Allocator as _, Layout};
+use std::hint::black_box;
+use std::ptr::NonNull;
+use std::thread;
+
+const STRATEGIES: [FitStrategy; 3] = [
+    FitStrategy::FirstFit,
+    FitStrategy::BestFit,
+    FitStrategy::NextFit,
+];
+
+// xorshift64, so the churn pattern is identical for every strategy
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn layout(&mut self) -> Layout {
+        let size = 8 + (self.next() % 505) as usize;
+        let align = 1 << (self.next() % 4);
+        Layout::from_size_align(size, align).unwrap()
+    }
+}
+
+fn alloc(allocator: &Allocator, layout: Layout) -> NonNull<u8> {
+    allocator.allocate(layout).unwrap().cast()
+}
+
+fn free(allocator: &Allocator, ptr: NonNull<u8>, layout: Layout) {
+    unsafe { allocator.deallocate(ptr, layout) }
+}
+
+fn sequential_small(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sequential_small");
+    let layout = Layout::new::<[u64; 4]>();
+    for strategy in STRATEGIES {
+        let allocator = Allocator::with_strategy(strategy);
+        group.bench_function(BenchmarkId::from_parameter(format!("{strategy:?}")), |b| {
+            b.iter(|| {
+                let ptrs: Vec<_> = (0..1000).map(|_| alloc(&allocator, layout)).collect();
+                for ptr in black_box(ptrs) {
+                    free(&allocator, ptr, layout);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn churn(allocator: &Allocator) {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut slots: [Option<(NonNull<u8>, Layout)>; 256] = [None; 256];
+    for _ in 0..4096 {
+        let slot = &mut slots[(rng.next() % 256) as usize];
+        match slot.take() {
+            Some((ptr, layout)) => free(allocator, ptr, layout),
+            None => {
+                let layout = rng.layout();
+                *slot = Some((alloc(allocator, layout), layout));
+            }
+        }
+    }
+    for (ptr, layout) in slots.into_iter().flatten() {
+        free(allocator, ptr, layout);
+    }
+}
+
+fn random_churn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("random_churn");
+    for strategy in STRATEGIES {
+        let allocator = Allocator::with_strategy(strategy);
+        group.bench_function(BenchmarkId::from_parameter(format!("{strategy:?}")), |b| {
+            b.iter(|| churn(&allocator))
+        });
+    }
+    // the baseline for what coalescing costs and saves
+    let allocator = Allocator::without_coalescing();
+    group.bench_function(BenchmarkId::from_parameter("FirstFit without coalescing"), |b| {
+        b.iter(|| churn(&allocator))
+    });
+    group.finish();
+}
+
+fn large_then_small(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large_then_small");
+    let large = Layout::from_size_align(64 * 1024, 8).unwrap();
+    let small = Layout::new::<[u64; 2]>();
+    for strategy in STRATEGIES {
+        let allocator = Allocator::with_strategy(strategy);
+        group.bench_function(BenchmarkId::from_parameter(format!("{strategy:?}")), |b| {
+            b.iter(|| {
+                let big = alloc(&allocator, large);
+                let mut ptrs: Vec<_> = (0..256).map(|_| alloc(&allocator, small)).collect();
+                free(&allocator, big, large);
+                ptrs.extend((0..256).map(|_| alloc(&allocator, small)));
+                for ptr in black_box(ptrs) {
+                    free(&allocator, ptr, small);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn contention(c: &mut Criterion) {
+    let mut group = c.benchmark_group("contention");
+    let layout = Layout::new::<[u64; 8]>();
+    for strategy in STRATEGIES {
+        let allocator = Allocator::with_strategy(strategy);
+        group.bench_function(BenchmarkId::from_parameter(format!("{strategy:?}")), |b| {
+            b.iter(|| {
+                thread::scope(|s| {
+                    for _ in 0..4 {
+                        s.spawn(|| {
+                            for _ in 0..256 {
+                                let ptr = alloc(&allocator, layout);
+                                free(&allocator, black_box(ptr), layout);
+                            }
+                        });
+                    }
+                });
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    sequential_small,
+    random_churn,
+    large_then_small,
+    contention
+);
+criterion_main!(benches);
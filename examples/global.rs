@@ -0,0 +1,52 @@
+//! Runs an ordinary program on top of the allocator and prints its stats.
+
+use allocator_speedrun::allocator::Allocator;
+use std::collections::HashMap;
+use std::thread;
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator::new();
+
+fn fib(n: u64, memo: &mut HashMap<u64, u64>) -> u64 {
+    if n < 2 {
+        return n;
+    }
+    if let Some(&value) = memo.get(&n) {
+        return value;
+    }
+    let value = fib(n - 1, memo) + fib(n - 2, memo);
+    memo.insert(n, value);
+    value
+}
+
+fn main() {
+    let mut words: HashMap<String, usize> = HashMap::new();
+    let text = "the quick brown fox jumps over the lazy dog ".repeat(200);
+    for word in text.split_whitespace() {
+        *words.entry(word.to_string()).or_default() += 1;
+    }
+    assert_eq!(words["the"], 400);
+
+    let mut squares = Vec::new();
+    for i in 0..10_000u64 {
+        squares.push(i * i);
+    }
+    assert_eq!(squares.iter().sum::<u64>(), 333_283_335_000);
+
+    let mut memo = HashMap::new();
+    assert_eq!(fib(90, &mut memo), 2_880_067_194_370_816_120);
+
+    let handles: Vec<_> = (0..4)
+        .map(|i| thread::spawn(move || (0..1000).map(|j| format!("{i}-{j}")).collect::<Vec<_>>().len()))
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), 1000);
+    }
+
+    drop((words, squares, memo));
+    let stats = ALLOCATOR.stats();
+    println!("allocations: {}", stats.total_allocations);
+    println!("frees: {}", stats.total_frees);
+    println!("live bytes: {}", stats.live_bytes);
+    println!("peak bytes: {}", stats.peak_bytes);
+}
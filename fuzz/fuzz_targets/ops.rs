@@ -0,0 +1,150 @@
+//! Runs arbitrary bytes as a sequence of allocations, frees and reallocs
+//! against a mock heap, checking the heap after every one of them.
+//!
+//! The first byte picks the fit strategy, then each op is an opcode byte
+//! followed by its operands, little endian:
+//!
+//! - `0`: alloc, a u16 size and a u8 alignment shift
+//! - `1`: free, a u16 index into the live allocations
+//! - `2`: realloc, a u16 index and a u16 size
+//!
+//! Opcodes, indices and shifts wrap around, sizes are taken modulo 4096
+//! plus one, and a truncated op at the end is dropped.
+//!
+//! Run it with `cargo fuzz run ops` from the repository root; the seeds in
+//! `corpus/ops` walk through the cases the tests single out, like front
+//! padding, odd sizes and growing into a freed neighbour.
+
+#![no_main]
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use libfuzzer_sys::fuzz_target;
+use std::alloc::{GlobalAlloc, Layout};
+
+const HEAP_SIZE: usize = 1 << 22;
+
+struct Live {
+    ptr: *mut u8,
+    layout: Layout,
+    // what the allocation is filled with
+    pattern: u8,
+}
+
+struct Input<'a>(&'a [u8]);
+
+impl Input<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(byte)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn size(&mut self) -> Option<usize> {
+        Some(self.u16()? as usize % 4096 + 1)
+    }
+}
+
+fn fill(live: &Live) {
+    unsafe { live.ptr.write_bytes(live.pattern, live.layout.size()) };
+}
+
+fn check_contents(ptr: *mut u8, len: usize, pattern: u8) {
+    let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+    assert!(bytes.iter().all(|&byte| byte == pattern), "contents of {ptr:?} were clobbered");
+}
+
+fn check_heap(allocator: &Allocator<MockSource>, live: &[Live]) {
+    allocator.check_integrity().unwrap();
+
+    let mut spans: Vec<_> = live
+        .iter()
+        .map(|live| (live.ptr as usize, live.ptr as usize + live.layout.size()))
+        .collect();
+    spans.sort_unstable();
+    for pair in spans.windows(2) {
+        assert!(pair[0].1 <= pair[1].0, "allocation at {:#x} overlaps the next one", pair[0].0);
+    }
+
+    let used = allocator.blocks().into_iter().filter(|block| !block.free).count();
+    assert_eq!(used, live.len());
+    let live_bytes: usize = live.iter().map(|live| live.layout.size()).sum();
+    assert_eq!(allocator.stats().live_bytes, live_bytes);
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = Input(data);
+    let strategy = match input.u8().map(|byte| byte % 3) {
+        Some(0) => FitStrategy::FirstFit,
+        Some(1) => FitStrategy::BestFit,
+        Some(_) => FitStrategy::NextFit,
+        None => return,
+    };
+    let allocator = Allocator::with_source(MockSource::new(HEAP_SIZE), strategy);
+    let mut live: Vec<Live> = Vec::new();
+    let mut next_pattern = 0u8;
+
+    while let Some(opcode) = input.u8() {
+        match opcode % 3 {
+            0 => {
+                let (Some(size), Some(shift)) = (input.size(), input.u8()) else {
+                    break;
+                };
+                let align = 1 << (shift % 13);
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = unsafe { allocator.alloc(layout) };
+                if ptr.is_null() {
+                    continue;
+                }
+                assert!((ptr as usize).is_multiple_of(align), "{ptr:?} isn't aligned to {align}");
+                next_pattern = next_pattern.wrapping_add(1);
+                let allocation = Live { ptr, layout, pattern: next_pattern };
+                fill(&allocation);
+                live.push(allocation);
+            }
+            1 => {
+                let Some(index) = input.u16() else {
+                    break;
+                };
+                if live.is_empty() {
+                    continue;
+                }
+                let allocation = live.swap_remove(index as usize % live.len());
+                check_contents(allocation.ptr, allocation.layout.size(), allocation.pattern);
+                unsafe { allocator.dealloc(allocation.ptr, allocation.layout) };
+            }
+            _ => {
+                let (Some(index), Some(size)) = (input.u16(), input.size()) else {
+                    break;
+                };
+                if live.is_empty() {
+                    continue;
+                }
+                let len = live.len();
+                let allocation = &mut live[index as usize % len];
+                let new = unsafe { allocator.realloc(allocation.ptr, allocation.layout, size) };
+                if new.is_null() {
+                    continue;
+                }
+                let align = allocation.layout.align();
+                assert!((new as usize).is_multiple_of(align), "{new:?} isn't aligned to {align}");
+                check_contents(new, allocation.layout.size().min(size), allocation.pattern);
+                allocation.ptr = new;
+                allocation.layout = Layout::from_size_align(size, align).unwrap();
+                fill(allocation);
+            }
+        }
+        check_heap(&allocator, &live);
+    }
+
+    for allocation in live.drain(..) {
+        check_contents(allocation.ptr, allocation.layout.size(), allocation.pattern);
+        unsafe { allocator.dealloc(allocation.ptr, allocation.layout) };
+    }
+    check_heap(&allocator, &live);
+    assert!(allocator.blocks().iter().all(|block| block.free));
+});
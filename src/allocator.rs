@@ -1,137 +1,3207 @@
-use nix::libc::sbrk;
+use crate::source::{MemorySource, MockSource, SbrkSource, StaticSource};
 
-use spin::Mutex;
+use crate::thread_cache::{self, Depot};
+#[cfg(feature = "record")]
+use crate::record::{Recorder, TraceEvent, TraceOp, RECORD_CAPACITY};
+#[cfg(feature = "trace")]
+use crate::trace::Traces;
+
+use nix::libc::{write, STDERR_FILENO};
+use spin::{Mutex, MutexGuard};
 use std::alloc::{AllocError, Allocator as AllocatorTrait, GlobalAlloc, Layout};
-use std::mem::{align_of, size_of};
+use std::cell::{Cell, UnsafeCell};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::mem::{align_of, offset_of, size_of};
 use std::process::abort;
 
-use std::ptr::{NonNull, null_mut};
+use std::ops::{Deref, DerefMut};
+use std::ptr::{NonNull, copy_nonoverlapping, null, null_mut, without_provenance_mut};
+#[cfg(feature = "debug-checks")]
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FitStrategy {
+    /// Use the first free block large enough for the request.
+    FirstFit,
+    /// Use the smallest free block large enough for the request.
+    BestFit,
+    /// Like first-fit, but resume scanning from the last block handed out.
+    NextFit,
+}
+
+/// What to do when asked to free a pointer the allocator never handed out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FreePolicy {
+    /// Do nothing.
+    Ignore,
+    /// Print a warning to stderr and carry on.
+    #[default]
+    Warn,
+    /// Print what happened and abort.
+    Abort,
+}
+
+/// The settings an allocator was made with, minus its memory source, see
+/// `Allocator::config`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AllocatorConfig {
+    pub strategy: FitStrategy,
+    /// See `Allocator::with_quarantine`.
+    pub quarantine_bytes: usize,
+    /// See `Allocator::with_growth_factor`.
+    pub growth_factor: f64,
+    /// See `Allocator::with_min_alignment`.
+    pub min_alignment: usize,
+    /// See `Allocator::with_prefault`.
+    pub prefault: bool,
+    pub free_policy: FreePolicy,
+    /// See `Allocator::with_size_class_rounding`.
+    pub size_class_rounding: bool,
+    /// See `Allocator::with_decommit_threshold`.
+    pub decommit_threshold: usize,
+    /// See `Allocator::with_warn_threshold`, `usize::MAX` for never.
+    pub warn_threshold: usize,
+    /// See `Allocator::with_lazy_frees`.
+    pub lazy_frees: bool,
+    /// See `Allocator::with_min_alloc_size`.
+    pub min_alloc_size: usize,
+    /// See `Allocator::without_coalescing`.
+    pub coalesce: bool,
+    /// See `Allocator::with_scan_limit`, 0 for no limit.
+    pub scan_limit: usize,
+}
+
+impl AllocatorConfig {
+    /// What `Allocator::new` uses, for filling in the rest of a config.
+    pub const DEFAULT: AllocatorConfig = AllocatorConfig {
+        strategy: FitStrategy::FirstFit,
+        quarantine_bytes: 0,
+        growth_factor: 1.0,
+        min_alignment: 1,
+        prefault: false,
+        free_policy: FreePolicy::Warn,
+        size_class_rounding: false,
+        decommit_threshold: 0,
+        warn_threshold: usize::MAX,
+        lazy_frees: false,
+        min_alloc_size: MIN_BLOCK_SIZE,
+        coalesce: true,
+        scan_limit: 0,
+    };
+}
+
+impl Default for AllocatorConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Sets up an allocator one option at a time, see `Allocator::builder`.
+///
+/// The setters are const, but `build` isn't, since it may have to grow
+/// the heap for `capacity`. A `static` can get every other option from
+/// `Allocator::with_config` instead.
+pub struct AllocatorBuilder<S: MemorySource = SbrkSource> {
+    source: S,
+    config: AllocatorConfig,
+    capacity: usize,
+}
+
+impl<S: MemorySource> AllocatorBuilder<S> {
+    /// A builder for an allocator over `source`, with the defaults
+    /// `Allocator::new` has.
+    pub const fn new(source: S) -> Self {
+        Self {
+            source,
+            config: AllocatorConfig::DEFAULT,
+            capacity: 0,
+        }
+    }
+
+    pub const fn strategy(mut self, strategy: FitStrategy) -> Self {
+        self.config.strategy = strategy;
+        self
+    }
+
+    /// See `Allocator::with_quarantine`.
+    pub const fn quarantine(mut self, bytes: usize) -> Self {
+        self.config.quarantine_bytes = bytes;
+        self
+    }
+
+    /// See `Allocator::with_growth_factor`.
+    pub const fn growth_factor(mut self, factor: f64) -> Self {
+        self.config.growth_factor = clamp_growth_factor(factor);
+        self
+    }
+
+    /// See `Allocator::with_min_alignment`.
+    ///
+    /// # Panics
+    ///
+    /// Under the same conditions as `Allocator::with_min_alignment`.
+    pub const fn min_alignment(mut self, align: usize) -> Self {
+        self.config.min_alignment = check_min_alignment(align);
+        self
+    }
+
+    /// See `Allocator::with_prefault`.
+    pub const fn prefault(mut self, prefault: bool) -> Self {
+        self.config.prefault = prefault;
+        self
+    }
+
+    pub const fn free_policy(mut self, policy: FreePolicy) -> Self {
+        self.config.free_policy = policy;
+        self
+    }
+
+    /// See `Allocator::with_size_class_rounding`.
+    pub const fn size_class_rounding(mut self, rounding: bool) -> Self {
+        self.config.size_class_rounding = rounding;
+        self
+    }
+
+    /// See `Allocator::with_decommit_threshold`.
+    pub const fn decommit_threshold(mut self, bytes: usize) -> Self {
+        self.config.decommit_threshold = bytes;
+        self
+    }
+
+    /// See `Allocator::with_warn_threshold`.
+    pub const fn warn_threshold(mut self, bytes: usize) -> Self {
+        self.config.warn_threshold = bytes;
+        self
+    }
+
+    /// See `Allocator::with_lazy_frees`.
+    pub const fn lazy_frees(mut self, lazy: bool) -> Self {
+        self.config.lazy_frees = lazy;
+        self
+    }
+
+    /// See `Allocator::with_min_alloc_size`.
+    pub const fn min_alloc_size(mut self, bytes: usize) -> Self {
+        self.config.min_alloc_size = clamp_min_alloc_size(bytes);
+        self
+    }
+
+    /// See `Allocator::without_coalescing`.
+    pub const fn coalesce(mut self, coalesce: bool) -> Self {
+        self.config.coalesce = coalesce;
+        self
+    }
+
+    /// See `Allocator::with_scan_limit`.
+    pub const fn scan_limit(mut self, blocks: usize) -> Self {
+        self.config.scan_limit = blocks;
+        self
+    }
+
+    /// See `Allocator::with_capacity`.
+    pub const fn capacity(mut self, bytes: usize) -> Self {
+        self.capacity = bytes;
+        self
+    }
+
+    pub fn build(self) -> Allocator<S> {
+        let mut allocator_impl = AllocatorImpl::with_config(self.source, self.config);
+        if self.capacity > 0 {
+            allocator_impl.reserve(self.capacity);
+        }
+        let mut allocator = Allocator::from_impl(allocator_impl);
+        *allocator.warn_threshold.get_mut() = self.config.warn_threshold;
+        *allocator.lazy_frees.get_mut() = self.config.lazy_frees;
+        allocator
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AllocStats {
+    /// Bytes requested by allocations that haven't been freed yet. Freeing
+    /// with more than was asked for, which `Allocator::allocate` allows up to
+    /// the length it returned, takes the extra off too.
+    pub live_bytes: usize,
+    /// Highest `live_bytes` seen so far.
+    pub peak_bytes: usize,
+    pub total_allocations: u64,
+    pub total_frees: u64,
+}
+
+impl AllocStats {
+    const EMPTY: AllocStats = AllocStats {
+        live_bytes: 0,
+        peak_bytes: 0,
+        total_allocations: 0,
+        total_frees: 0,
+    };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockInfo {
+    /// Address of the block header.
+    pub addr: usize,
+    /// Address of the first byte handed out to the user.
+    pub data: usize,
+    pub size: usize,
+    pub free: bool,
+}
+
+/// The block behind an allocation made with `Allocator::allocate_handle`,
+/// so `Allocator::free_handle` doesn't have to look it up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Handle {
+    block: NonNull<Block>,
+    layout: Layout,
+    // whether the block is a mapped region of its own rather than in the chain
+    mapped: bool,
+}
+
+impl Handle {
+    /// The pointer the allocation was handed out with.
+    pub fn ptr(&self) -> NonNull<u8> {
+        // SAFETY: a handle is only made for a live block.
+        NonNull::new(unsafe { self.block.as_ref() }.data).unwrap()
+    }
+
+    fn usable(&self) -> NonNull<[u8]> {
+        // SAFETY: a handle is only made for a live block.
+        unsafe { self.block.as_ref() }.usable()
+    }
+
+}
+
+// what an allocation made by allocate_tagged, allocate_secure or
+// allocate_handle marks its block with
+#[derive(Clone, Copy, Default)]
+struct Marks {
+    tag: u32,
+    secure: bool,
+}
+
+// an allocation and how much of it is usable, which can be more than asked
+// for, whether all of that is known to be zeroed already, and the block
+// behind it, unless it came from a thread cache or the scratch buffer
+struct Allocation {
+    ptr: NonNull<[u8]>,
+    zeroed: bool,
+    handle: Option<Handle>,
+}
+
+impl Allocation {
+    fn loose(ptr: NonNull<[u8]>) -> Self {
+        Self {
+            ptr,
+            zeroed: false,
+            handle: None,
+        }
+    }
+
+    fn from_heap(handle: Handle, zeroed: bool) -> Self {
+        Self {
+            ptr: handle.usable(),
+            zeroed,
+            handle: Some(handle),
+        }
+    }
+}
+
+/// Smallest block the heap hands out, whatever the requested size, unless
+/// `Allocator::with_min_alloc_size` asks for more.
+pub const MIN_BLOCK_SIZE: usize = 16;
+
+/// Granularity at which fresh heap is faulted in, see `Allocator::with_prefault`.
+const PAGE_SIZE: usize = 4096;
+
+// the owner the next allocator to make a block takes, see Block::OWNER_BITS
+#[cfg(feature = "debug-checks")]
+static NEXT_OWNER: AtomicU32 = AtomicU32::new(1);
+
+/// Largest alignment the allocator will pad a block for.
+pub const MAX_ALIGN: usize = 2 << 20;
+
+/// What the `poison` feature fills free memory with. Reads of freed memory
+/// stand out, and writes to it are caught when it's handed out again.
+#[cfg(feature = "poison")]
+pub const POISON: u8 = 0xdd;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocFailure {
+    /// The size of the block, including header and padding, doesn't fit in a `usize`.
+    SizeOverflow,
+    /// Neither the heap nor a mapped region could provide the memory.
+    OutOfMemory,
+    /// The alignment is larger than `MAX_ALIGN`.
+    UnsupportedAlignment,
+}
+
+impl fmt::Display for AllocFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocFailure::SizeOverflow => f.write_str("allocation size overflows usize"),
+            AllocFailure::OutOfMemory => f.write_str("out of memory"),
+            AllocFailure::UnsupportedAlignment => f.write_str("unsupported alignment"),
+        }
+    }
+}
+
+impl Error for AllocFailure {}
+
+/// The first thing `Allocator::check_integrity` found wrong with the block
+/// chain, along with the address of the block header in question.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntegrityError {
+    /// The header's magic number was overwritten.
+    Corrupted(usize),
+    /// The header isn't aligned for a block header.
+    MisalignedHeader(usize),
+    /// The block comes before the one linking to it, which also means the
+    /// chain would have looped back on itself.
+    OutOfOrder(usize),
+    /// The block starts inside the previous one.
+    Overlap(usize),
+    /// `data` is inside the header, or not aligned for a block header.
+    BadData(usize),
+    /// The block and the one before it are both free and back to back,
+    /// which isn't checked with coalescing turned off.
+    Uncoalesced(usize),
+    /// The block ends past the current break.
+    PastBreak(usize),
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityError::Corrupted(addr) => write!(f, "corrupted header at {addr:#x}"),
+            IntegrityError::MisalignedHeader(addr) => write!(f, "misaligned header at {addr:#x}"),
+            IntegrityError::OutOfOrder(addr) => write!(f, "block at {addr:#x} is out of order"),
+            IntegrityError::Overlap(addr) => write!(f, "block at {addr:#x} overlaps the previous one"),
+            IntegrityError::BadData(addr) => write!(f, "block at {addr:#x} has a bad data pointer"),
+            IntegrityError::Uncoalesced(addr) => write!(f, "free block at {addr:#x} wasn't coalesced"),
+            IntegrityError::PastBreak(addr) => write!(f, "block at {addr:#x} ends past the break"),
+        }
+    }
+}
+
+impl Error for IntegrityError {}
+
+/// What `Allocator::recover` found while rebuilding the block chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Blocks with intact headers, all linked back into the chain.
+    pub recovered_blocks: usize,
+    /// Recovered blocks the chain didn't lead to from the block before.
+    pub relinked_blocks: usize,
+    /// Stretches of heap the scan found no intact header for, which are
+    /// left out of the chain for good.
+    pub lost_blocks: usize,
+    pub lost_bytes: usize,
+}
+
+/// Number of size classes; class `i` holds sizes up to `16 << i` bytes,
+/// and the last one everything bigger than that.
+pub const SIZE_CLASSES: usize = 10;
+
+/// Returns the size class of an allocation of `size` bytes.
+pub fn size_class(size: usize) -> usize {
+    if size <= 16 {
+        0
+    } else {
+        ((size - 1).ilog2() as usize - 3).min(SIZE_CLASSES - 1)
+    }
+}
+
+/// Rounds `size` up to the class `Allocator::with_size_class_rounding`
+/// puts it in: multiples of 16 up to 128 bytes, then four evenly spaced
+/// classes per doubling, so rounding never takes more than a fifth of a
+/// block. `None` if that overflows.
+pub fn class_size(size: usize) -> Option<usize> {
+    if size <= 128 {
+        return Some(size.max(1).next_multiple_of(16));
+    }
+    size.checked_next_multiple_of(1 << ((size - 1).ilog2() - 2))
+}
+
+pub struct Allocator<S: MemorySource = SbrkSource> {
+    allocator_impl: Mutex<AllocatorImpl<S>>,
+    lock_acquisitions: AtomicU64,
+    relaxed_stats: RelaxedStats,
+    thread_cache: AtomicBool,
+    // see Allocator::with_warn_threshold, checked before taking the lock
+    warn_threshold: AtomicUsize,
+    // see Allocator::with_lazy_frees
+    lazy_frees: AtomicBool,
+    pending_frees: PendingFrees,
+    depot: Depot,
+    #[cfg(feature = "trace")]
+    traces: Traces,
+}
+
+impl Allocator {
+    pub const fn new() -> Self {
+        Self::with_strategy(FitStrategy::FirstFit)
+    }
+
+    pub const fn with_strategy(strategy: FitStrategy) -> Self {
+        Self::with_source(SbrkSource, strategy)
+    }
+
+    /// Reserves `bytes` of heap up front, so allocations can be carved out of
+    /// it without growing the heap. If the heap can't grow, the allocator
+    /// starts out empty instead.
+    pub fn with_capacity(bytes: usize) -> Self {
+        Self::with_capacity_in(bytes, SbrkSource)
+    }
+
+    /// Starts setting up an allocator over the process break.
+    pub const fn builder() -> AllocatorBuilder {
+        AllocatorBuilder::new(SbrkSource)
+    }
+
+    /// Holds freed blocks back from reuse until more than `bytes` of them
+    /// have piled up, so use-after-free bugs don't land in a fresh allocation.
+    pub const fn with_quarantine(bytes: usize) -> Self {
+        let mut allocator_impl = AllocatorImpl::new(SbrkSource, FitStrategy::FirstFit);
+        allocator_impl.quarantine_budget = bytes;
+        Self::from_impl(allocator_impl)
+    }
+
+    /// Grows the heap by at least `factor - 1` times its current size each
+    /// time it runs out, so a growing workload takes a logarithmic number
+    /// of trips to the OS. Factors below 1 count as 1, i.e. growing by only
+    /// as much as needed.
+    pub const fn with_growth_factor(factor: f64) -> Self {
+        let mut allocator_impl = AllocatorImpl::new(SbrkSource, FitStrategy::FirstFit);
+        allocator_impl.growth_factor = clamp_growth_factor(factor);
+        Self::from_impl(allocator_impl)
+    }
+
+    /// Aligns every block to at least `align` bytes and rounds its size up
+    /// to a multiple of it, e.g. 64 to keep allocations on separate cache
+    /// lines.
+    ///
+    /// # Panics
+    ///
+    /// If `align` isn't a power of two or is larger than `MAX_ALIGN`.
+    pub const fn with_min_alignment(align: usize) -> Self {
+        let mut allocator_impl = AllocatorImpl::new(SbrkSource, FitStrategy::FirstFit);
+        allocator_impl.min_align = check_min_alignment(align);
+        Self::from_impl(allocator_impl)
+    }
+
+    /// Touches every page the heap grows by while still holding the lock,
+    /// so the page faults happen then rather than on the caller's first
+    /// write.
+    pub const fn with_prefault() -> Self {
+        let mut allocator_impl = AllocatorImpl::new(SbrkSource, FitStrategy::FirstFit);
+        allocator_impl.prefault = true;
+        Self::from_impl(allocator_impl)
+    }
+
+    /// Decides what freeing a pointer the allocator never handed out does,
+    /// instead of the default `FreePolicy::Warn`.
+    pub const fn with_free_policy(policy: FreePolicy) -> Self {
+        let mut allocator_impl = AllocatorImpl::new(SbrkSource, FitStrategy::FirstFit);
+        allocator_impl.free_policy = policy;
+        Self::from_impl(allocator_impl)
+    }
+
+    /// Rounds every block up to its `class_size`, so a block freed by one
+    /// request is far more likely to fit a later one of a similar size.
+    pub const fn with_size_class_rounding() -> Self {
+        let mut allocator_impl = AllocatorImpl::new(SbrkSource, FitStrategy::FirstFit);
+        allocator_impl.round_to_class = true;
+        Self::from_impl(allocator_impl)
+    }
+
+    /// Makes every block at least `bytes` long, so lots of tiny allocations
+    /// don't each get a block barely bigger than nothing, and their holes
+    /// are more likely to fit the next one. Unlike size-class rounding this
+    /// is only a floor. Sizes below the default of `MIN_BLOCK_SIZE` count
+    /// as that.
+    pub const fn with_min_alloc_size(bytes: usize) -> Self {
+        let mut allocator_impl = AllocatorImpl::new(SbrkSource, FitStrategy::FirstFit);
+        allocator_impl.min_block_size = clamp_min_alloc_size(bytes);
+        Self::from_impl(allocator_impl)
+    }
+
+    /// Frees only mark blocks free, without merging them with their free
+    /// neighbours, as a baseline for what coalescing buys. `coalesce_all`
+    /// still merges them when asked to.
+    pub const fn without_coalescing() -> Self {
+        let mut allocator_impl = AllocatorImpl::new(SbrkSource, FitStrategy::FirstFit);
+        allocator_impl.coalesce = false;
+        Self::from_impl(allocator_impl)
+    }
+
+    /// Looks at no more than `blocks` free blocks for one that fits before
+    /// growing the heap instead, trading memory for a bound on how long an
+    /// allocation takes when the heap is badly fragmented. 0, the default,
+    /// means no limit.
+    pub const fn with_scan_limit(blocks: usize) -> Self {
+        let mut allocator_impl = AllocatorImpl::new(SbrkSource, FitStrategy::FirstFit);
+        allocator_impl.scan_limit = blocks;
+        Self::from_impl(allocator_impl)
+    }
+
+    /// Only gives the free tail of the heap back to the OS once it's more
+    /// than `bytes`, so a workload that keeps freeing and reallocating the
+    /// tail doesn't move the break every time.
+    pub const fn with_decommit_threshold(bytes: usize) -> Self {
+        let mut allocator_impl = AllocatorImpl::new(SbrkSource, FitStrategy::FirstFit);
+        allocator_impl.decommit_threshold = bytes;
+        Self::from_impl(allocator_impl)
+    }
+
+    /// Prints a warning, and a backtrace with the `trace` feature, for
+    /// every single allocation of more than `bytes`, which still goes
+    /// ahead. Meant to catch runaway allocations before they run the
+    /// heap out.
+    pub const fn with_warn_threshold(bytes: usize) -> Self {
+        let mut allocator = Self::new();
+        allocator.warn_threshold = AtomicUsize::new(bytes);
+        allocator
+    }
+
+    /// Frees without taking the lock: a freed block is pushed onto a
+    /// lock-free stack, and only goes back into the heap, coalescing and
+    /// all, once an allocation finds no room without it. Until then it
+    /// counts as in use, as it does for `defer_free`, and `flush_deferred`
+    /// frees it right away.
+    ///
+    /// The block is written to before anything checks it was allocated
+    /// here, so freeing a pointer that wasn't is undefined behaviour rather
+    /// than a `FreePolicy` matter.
+    pub const fn with_lazy_frees() -> Self {
+        let mut allocator = Self::new();
+        allocator.lazy_frees = AtomicBool::new(true);
+        allocator
+    }
+}
+
+// the aligned allocation that fits `layout` bytes at `offset` short of an
+// aligned address, and how far into it they start
+fn offset_layout(layout: Layout, offset: usize) -> Option<(Layout, usize)> {
+    let pad = (layout.align() - offset % layout.align()) % layout.align();
+    let size = layout.size().checked_add(pad)?;
+    Some((Layout::from_size_align(size, layout.align()).ok()?, pad))
+}
+
+const fn check_min_alignment(align: usize) -> usize {
+    assert!(
+        align.is_power_of_two() && align <= MAX_ALIGN,
+        "min alignment must be a power of two no larger than MAX_ALIGN"
+    );
+    align
+}
+
+const fn clamp_min_alloc_size(bytes: usize) -> usize {
+    // thread caches and lazy frees write into every block they hold
+    if bytes > MIN_BLOCK_SIZE {
+        bytes
+    } else {
+        MIN_BLOCK_SIZE
+    }
+}
+
+const fn clamp_growth_factor(factor: f64) -> f64 {
+    // NaN ends up as 1 as well
+    if factor >= 1.0 {
+        factor
+    } else {
+        1.0
+    }
+}
+
+impl<S: MemorySource> Allocator<S> {
+    pub const fn with_source(source: S, strategy: FitStrategy) -> Self {
+        Self::from_impl(AllocatorImpl::new(source, strategy))
+    }
+
+    /// An empty allocator over `source`, set up like `config` says.
+    ///
+    /// # Panics
+    ///
+    /// Under the same conditions as `Allocator::with_min_alignment`.
+    pub const fn with_config(source: S, config: AllocatorConfig) -> Self {
+        let mut allocator = Self::from_impl(AllocatorImpl::with_config(source, config));
+        allocator.warn_threshold = AtomicUsize::new(config.warn_threshold);
+        allocator.lazy_frees = AtomicBool::new(config.lazy_frees);
+        allocator
+    }
+
+    pub fn with_capacity_in(bytes: usize, source: S) -> Self {
+        AllocatorBuilder::new(source).capacity(bytes).build()
+    }
+
+    const fn from_impl(allocator_impl: AllocatorImpl<S>) -> Self {
+        Self {
+            allocator_impl: Mutex::new(allocator_impl),
+            lock_acquisitions: AtomicU64::new(0),
+            relaxed_stats: RelaxedStats {
+                live_bytes: AtomicUsize::new(0),
+                total_allocations: AtomicU64::new(0),
+                secure_blocks: AtomicUsize::new(0),
+            },
+            thread_cache: AtomicBool::new(false),
+            warn_threshold: AtomicUsize::new(usize::MAX),
+            lazy_frees: AtomicBool::new(false),
+            pending_frees: PendingFrees::new(),
+            depot: Depot::new(),
+            #[cfg(feature = "trace")]
+            traces: Traces::new(),
+        }
+    }
+
+    pub(crate) fn depot(&self) -> &Depot {
+        &self.depot
+    }
+
+    pub(crate) fn lock(&self) -> HeapLock<'_, S> {
+        self.lock_acquisitions.fetch_add(1, Ordering::Relaxed);
+        HeapLock {
+            allocator_impl: self.allocator_impl.lock(),
+            relaxed_stats: &self.relaxed_stats,
+        }
+    }
+
+    // for holding the lock around code that may allocate, like printing or
+    // growing a Vec, which would deadlock if we're the global allocator
+    fn lock_diagnostics(&self) -> DiagnosticLock<'_, S> {
+        DiagnosticLock::new(self, self.lock())
+    }
+
+    fn try_lock_diagnostics(&self) -> Option<DiagnosticLock<'_, S>> {
+        let allocator_impl = self.allocator_impl.try_lock()?;
+        self.lock_acquisitions.fetch_add(1, Ordering::Relaxed);
+        let allocator_impl = HeapLock {
+            allocator_impl,
+            relaxed_stats: &self.relaxed_stats,
+        };
+        Some(DiagnosticLock::new(self, allocator_impl))
+    }
+
+    fn is_diagnosing(&self) -> bool {
+        DIAGNOSING.with(Cell::get) == self as *const Self as *const ()
+    }
+
+    /// Number of times the allocator lock has been taken.
+    pub fn lock_acquisitions(&self) -> u64 {
+        self.lock_acquisitions.load(Ordering::Relaxed)
+    }
+
+    /// Number of times a block had to be found by walking the heap from
+    /// the start, e.g. to free or resize it.
+    pub fn pointer_lookups(&self) -> u64 {
+        self.lock().lookups
+    }
+
+    /// `stats().live_bytes` as of the last time the lock was let go of, read
+    /// without taking it, e.g. from a watchdog thread. It may be a little
+    /// stale, and misses whatever thread caches haven't handed in yet.
+    pub fn live_bytes_relaxed(&self) -> usize {
+        self.relaxed_stats.live_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Like `live_bytes_relaxed`, for `stats().total_allocations`.
+    pub fn total_allocations_relaxed(&self) -> u64 {
+        self.relaxed_stats.total_allocations.load(Ordering::Relaxed)
+    }
+
+    /// Lets each thread keep a small cache of recently freed small blocks,
+    /// so most small allocations and frees don't take the allocator lock.
+    ///
+    /// Blocks and counts move between a cache and the heap in batches, so
+    /// `stats` lags behind what the caches have done in the meantime, and
+    /// blocks sitting in a cache bypass the quarantine. A cache can hold on
+    /// to blocks until its thread exits, which is why this needs an
+    /// allocator that lives forever.
+    ///
+    /// Blocks that don't fit in a full cache go to a lock-free depot shared
+    /// by all threads, and only go back to the heap when it runs out of
+    /// memory.
+    pub fn enable_thread_cache(&'static self) {
+        self.thread_cache.store(true, Ordering::Release);
+    }
+
+    // with `marks`, the allocation goes straight to the heap, past the
+    // thread cache, and its block is marked with them
+    fn allocate_ptr(&self, layout: Layout, marks: Option<Marks>) -> Result<Allocation, AllocFailure> {
+        if self.is_diagnosing() {
+            let ptr = SCRATCH.allocate(layout).ok_or(AllocFailure::OutOfMemory)?;
+            return Ok(Allocation::loose(NonNull::slice_from_raw_parts(ptr, layout.size())));
+        }
+        if layout.size() > self.warn_threshold.load(Ordering::Relaxed) {
+            warn_large(layout.size());
+        }
+        let result = self.allocate_from_heap(layout, marks);
+        #[cfg(feature = "trace")]
+        match result {
+            Ok(ref allocation) => self.traces.record(allocation.ptr.cast(), layout.size()),
+            Err(_) => crate::trace::allocation_failed(),
+        }
+        result
+    }
+
+    fn allocate_from_heap(&self, layout: Layout, marks: Option<Marks>) -> Result<Allocation, AllocFailure> {
+        if marks.is_none() && self.thread_cache.load(Ordering::Acquire) {
+            if let Some(ptr) = thread_cache::allocate(self, layout) {
+                return Ok(Allocation::loose(ptr));
+            }
+        }
+        let mut allocator_impl = self.lock();
+        // lazily freed blocks only go back into the heap once it would
+        // otherwise have to grow
+        if !self.pending_frees.is_empty() {
+            if let Some(handle) = allocator_impl.place_no_grow(layout) {
+                return Ok(Allocation::from_heap(allocator_impl.mark(handle, marks), false));
+            }
+            self.pending_frees.drain(&mut allocator_impl);
+        }
+        let handle = match allocator_impl.place(layout) {
+            // blocks parked in the depot might coalesce into something usable
+            Err(AllocFailure::OutOfMemory) if self.depot.reclaim(&mut allocator_impl) => {
+                allocator_impl.place(layout)
+            }
+            result => result,
+        }?;
+        Ok(Allocation::from_heap(allocator_impl.mark(handle, marks), allocator_impl.fresh))
+    }
+
+    /// Allocates like `GlobalAlloc::alloc`, marking the block with `tag`
+    /// so `bytes_by_tag` can tell who it belongs to. Goes straight to the
+    /// heap, bypassing the thread cache. A realloc that has to move the
+    /// allocation tags the new block the same way.
+    pub fn allocate_tagged(&self, layout: Layout, tag: u32) -> *mut u8 {
+        let marks = Marks { tag, secure: false };
+        self.allocate_ptr(layout, Some(marks)).map_or(null_mut(), |allocation| allocation.ptr.cast().as_ptr())
+    }
+
+    /// Allocates like `allocate_tagged`, straight from the heap, and also
+    /// returns a handle to the block so `free_handle` can free it without
+    /// walking the heap to find it.
+    pub fn allocate_handle(&self, layout: Layout) -> Option<(NonNull<u8>, Handle)> {
+        // the scratch buffer has no blocks to hand out handles to
+        if self.is_diagnosing() {
+            return None;
+        }
+        let handle = self.allocate_ptr(layout, Some(Marks::default())).ok()?.handle?;
+        Some((handle.ptr(), handle))
+    }
+
+    /// Frees an allocation made with `allocate_handle`. The block merges
+    /// with free blocks after it as usual, but not with a free block right
+    /// in front of it, since finding that one would take the very walk the
+    /// handle saves; `coalesce_all` merges those later.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must come from `allocate_handle` on this allocator, and the
+    /// allocation not have been freed or resized since.
+    pub unsafe fn free_handle(&self, handle: Handle) {
+        // like dealloc, leaked rather than deadlocking while diagnosing
+        if self.is_diagnosing() {
+            return;
+        }
+        #[cfg(feature = "trace")]
+        self.traces.forget(handle.ptr().as_ptr());
+        let mut allocator_impl = self.lock();
+        // trim would walk the whole chain to find the last block, so only
+        // when it's this one
+        if unsafe { allocator_impl.free_handle(handle) } && !self.depot.is_popping() {
+            allocator_impl.trim();
+        }
+    }
+
+    /// Allocates like `GlobalAlloc::alloc_zeroed`, and has the block wiped
+    /// again when it's freed, or when realloc shrinks or moves it, so a
+    /// secret kept there isn't left for the next allocation to find. Goes
+    /// straight to the heap, and while one is live frees skip the thread
+    /// cache and lazy frees.
+    pub fn allocate_secure(&self, layout: Layout) -> *mut u8 {
+        let marks = Marks { tag: 0, secure: true };
+        let Ok(Allocation { ptr, zeroed, .. }) = self.allocate_ptr(layout, Some(marks)) else {
+            return null_mut();
+        };
+        if !zeroed {
+            unsafe { ptr.cast::<u8>().write_bytes(0, ptr.len()) };
+        }
+        ptr.cast().as_ptr()
+    }
+
+    /// Allocates `layout.size()` bytes at a `ptr` for which `ptr + offset`,
+    /// rather than `ptr` itself, is aligned to `layout.align()`. Has to be
+    /// freed with `deallocate_with_offset` and the same arguments.
+    pub fn allocate_with_offset(&self, layout: Layout, offset: usize) -> *mut u8 {
+        let Some((outer, pad)) = offset_layout(layout, offset) else {
+            return null_mut();
+        };
+        let base = unsafe { self.alloc(outer) };
+        if base.is_null() {
+            return base;
+        }
+        base.wrapping_add(pad)
+    }
+
+    /// Frees what `allocate_with_offset` returned.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` has to come from `allocate_with_offset` on this allocator with
+    /// the same `layout` and `offset`, and not have been freed already.
+    pub unsafe fn deallocate_with_offset(&self, ptr: *mut u8, layout: Layout, offset: usize) {
+        // SAFETY: allocate_with_offset succeeded with these, so they're valid.
+        let (outer, pad) = unsafe { offset_layout(layout, offset).unwrap_unchecked() };
+        unsafe { self.dealloc(ptr.wrapping_sub(pad), outer) };
+    }
+
+    /// The bytes of every block in use, summed by the tag it was allocated
+    /// with; untagged allocations count under 0. Blocks held by a thread
+    /// cache are in use as far as the heap knows, and keep their old tag.
+    pub fn bytes_by_tag(&self) -> HashMap<u32, usize> {
+        self.lock_diagnostics().bytes_by_tag()
+    }
+
+    /// How big the block for an allocation of `layout` would be, after the
+    /// same rounding `alloc` does, without touching the heap. Reusing a
+    /// free block can still hand out a little more than this, when what's
+    /// left over is too small to split off. A layout that could never be
+    /// allocated yields its own size.
+    pub fn usable_size(&self, layout: Layout) -> usize {
+        self.lock().block_size(layout.size()).unwrap_or(layout.size())
+    }
+
+    pub fn strategy(&self) -> FitStrategy {
+        self.lock().strategy
+    }
+
+    /// Allocates only out of free space the heap already has, returning
+    /// `None` rather than growing it, e.g. where a syscall can't be risked.
+    /// Bypasses the thread cache.
+    pub fn allocate_no_grow(&self, layout: Layout) -> Option<NonNull<[u8]>> {
+        let mut allocator_impl = self.lock();
+        if let Some(ptr) = allocator_impl.allocate_no_grow(layout) {
+            return Some(ptr);
+        }
+        // lazily freed blocks are free space too
+        if !self.pending_frees.drain(&mut allocator_impl) {
+            return None;
+        }
+        allocator_impl.allocate_no_grow(layout)
+    }
+
+    /// Like `GlobalAlloc::alloc`, but with `None` for a failed allocation.
+    pub fn allocate_raw(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let ptr = self.allocate_ptr(layout, None).ok()?.ptr.cast::<u8>();
+        assert!((ptr.as_ptr() as usize).is_multiple_of(layout.align()));
+        Some(ptr)
+    }
+
+    /// Like `Allocator::allocate`, but says why an allocation failed.
+    ///
+    /// The slice covers the whole block, so it can be longer than
+    /// `layout.size()`, and any size in between can be used to free it.
+    pub fn allocate_checked(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocFailure> {
+        let ptr = self.allocate_ptr(layout, None)?.ptr;
+        assert!(ptr.cast::<u8>().is_aligned());
+        Ok(ptr)
+    }
+
+    /// Like `allocate_checked`, with the whole slice zeroed. Memory the
+    /// heap just got from a source that hands it out zeroed is left as is,
+    /// so only reused blocks are cleared.
+    pub fn allocate_zeroed_checked(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocFailure> {
+        let Allocation { ptr, zeroed, .. } = self.allocate_ptr(layout, None)?;
+        if !zeroed {
+            unsafe { ptr.cast::<u8>().write_bytes(0, ptr.len()) };
+        }
+        Ok(ptr)
+    }
+
+    /// Tries to grow the allocation at `ptr` to `new_size` bytes without
+    /// moving it, by taking over a free block right behind it or growing the
+    /// heap if it's the last block. Never copies or allocates anything.
+    pub fn try_extend(&self, ptr: *mut u8, old: Layout, new_size: usize) -> bool {
+        self.lock().try_extend(ptr, old.size(), new_size).is_some()
+    }
+
+    /// Whether the block for `ptr` ends right at the break, so it can grow
+    /// by growing the heap and hand its tail straight back to the source.
+    /// Always false for mapped regions and pointers that weren't allocated
+    /// here.
+    pub fn is_tail(&self, ptr: *mut u8) -> bool {
+        self.lock().is_tail(ptr)
+    }
+
+    /// Where the source's break is now.
+    pub fn current_break(&self) -> usize {
+        self.lock().source.current_break()
+    }
+
+    /// How far the break has moved since this allocator first grew it, or 0
+    /// if it never has. With `SbrkSource` that includes whatever the rest
+    /// of the process grew the break by in the meantime.
+    pub fn bytes_from_os(&self) -> usize {
+        let allocator_impl = self.lock();
+        let initial = allocator_impl.initial_break;
+        initial.map_or(0, |initial| allocator_impl.source.current_break().saturating_sub(initial.addr().get()))
+    }
+
+    /// Whether `ptr` is the start of an allocation this allocator handed
+    /// out, e.g. to tell which of several allocators to free it with.
+    pub fn owns(&self, ptr: *mut u8) -> bool {
+        // the heap can't be looked at while diagnosing, and dealloc leaks
+        // whatever is freed in the meantime anyway
+        if SCRATCH.contains(ptr) || self.is_diagnosing() {
+            return true;
+        }
+        self.lock().find_block(ptr).is_some()
+    }
+
+    /// Runs `f` with the memory source, e.g. to read a mock's counters.
+    pub fn inspect_source<R>(&self, f: impl FnOnce(&S) -> R) -> R {
+        f(&self.lock_diagnostics().source)
+    }
+
+    pub(crate) fn with_source_mut<R>(&self, f: impl FnOnce(&mut S) -> R) -> R {
+        f(&mut self.lock().source)
+    }
+
+    /// See `Allocator::with_growth_factor`.
+    pub fn set_growth_factor(&self, factor: f64) {
+        self.lock().growth_factor = clamp_growth_factor(factor);
+    }
+
+    /// Frees `ptr` on the next `flush_deferred` rather than now, for when
+    /// other threads may still be reading it. Until then it stays live.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `dealloc`, with the block not used by anyone once
+    /// `flush_deferred` is called.
+    pub unsafe fn defer_free(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().defer_free(ptr, layout);
+    }
+
+    /// Frees everything passed to `defer_free` so far, and everything
+    /// `with_lazy_frees` left pending.
+    pub fn flush_deferred(&self) {
+        let mut allocator_impl = self.lock();
+        allocator_impl.flush_deferred();
+        self.pending_frees.drain(&mut allocator_impl);
+        if !self.depot.is_popping() {
+            allocator_impl.trim();
+        }
+    }
+
+    /// See `Allocator::with_prefault`.
+    pub fn set_prefault(&self, prefault: bool) {
+        self.lock().prefault = prefault;
+    }
+
+    /// See `Allocator::with_free_policy`. Pointers freed through a thread
+    /// cache aren't checked.
+    pub fn set_free_policy(&self, policy: FreePolicy) {
+        self.lock().free_policy = policy;
+    }
+
+    /// See `Allocator::with_size_class_rounding`. Blocks allocated before
+    /// this is changed keep their size.
+    pub fn set_size_class_rounding(&self, enabled: bool) {
+        self.lock().round_to_class = enabled;
+    }
+
+    /// See `Allocator::with_min_alloc_size`. Blocks allocated before this is
+    /// changed keep their size.
+    pub fn set_min_alloc_size(&self, bytes: usize) {
+        self.lock().min_block_size = clamp_min_alloc_size(bytes);
+    }
+
+    /// See `Allocator::without_coalescing`. Blocks freed while it was off
+    /// stay apart until `coalesce_all`.
+    pub fn set_coalescing(&self, coalesce: bool) {
+        self.lock().coalesce = coalesce;
+    }
+
+    /// See `Allocator::with_scan_limit`.
+    pub fn set_scan_limit(&self, blocks: usize) {
+        self.lock().scan_limit = blocks;
+    }
+
+    /// See `Allocator::with_decommit_threshold`. Takes effect on the next
+    /// free.
+    pub fn set_decommit_threshold(&self, bytes: usize) {
+        self.lock().decommit_threshold = bytes;
+    }
+
+    /// See `Allocator::with_warn_threshold`.
+    pub fn set_warn_threshold(&self, bytes: usize) {
+        self.warn_threshold.store(bytes, Ordering::Relaxed);
+    }
+
+    /// See `Allocator::with_lazy_frees`. Turning them off frees whatever
+    /// is still pending.
+    pub fn set_lazy_frees(&self, lazy: bool) {
+        self.lazy_frees.store(lazy, Ordering::Relaxed);
+        if !lazy {
+            self.flush_deferred();
+        }
+    }
+
+    /// See `Allocator::with_min_alignment`.
+    ///
+    /// # Panics
+    ///
+    /// If anything has been allocated already, or under the same conditions
+    /// as `Allocator::with_min_alignment`.
+    pub fn set_min_alignment(&self, align: usize) {
+        let mut allocator_impl = self.lock();
+        assert_eq!(
+            allocator_impl.stats.total_allocations, 0,
+            "min alignment must be set before allocating"
+        );
+        allocator_impl.min_align = check_min_alignment(align);
+    }
+
+    pub fn set_quarantine(&self, bytes: usize) {
+        let mut allocator_impl = self.lock();
+        allocator_impl.quarantine_budget = bytes;
+        allocator_impl.drain_quarantine();
+    }
+
+    pub fn stats(&self) -> AllocStats {
+        self.lock().stats
+    }
+
+    pub fn config(&self) -> AllocatorConfig {
+        let allocator_impl = self.lock();
+        AllocatorConfig {
+            strategy: allocator_impl.strategy,
+            quarantine_bytes: allocator_impl.quarantine_budget,
+            growth_factor: allocator_impl.growth_factor,
+            min_alignment: allocator_impl.min_align,
+            prefault: allocator_impl.prefault,
+            free_policy: allocator_impl.free_policy,
+            size_class_rounding: allocator_impl.round_to_class,
+            decommit_threshold: allocator_impl.decommit_threshold,
+            warn_threshold: self.warn_threshold.load(Ordering::Relaxed),
+            lazy_frees: self.lazy_frees.load(Ordering::Relaxed),
+            min_alloc_size: allocator_impl.min_block_size,
+            coalesce: allocator_impl.coalesce,
+            scan_limit: allocator_impl.scan_limit,
+        }
+    }
+
+    /// A fresh, empty allocator over `source` with the same configuration
+    /// as this one, e.g. one arena per subsystem. The heaps are entirely
+    /// separate, so it only makes sense with a source other than the
+    /// process break.
+    pub fn new_arena<T: MemorySource>(&self, source: T) -> Allocator<T> {
+        Allocator::with_config(source, self.config())
+    }
+
+    /// Starts `peak_bytes` over from the current `live_bytes`, so it tracks
+    /// the peak since this call.
+    pub fn reset_peak(&self) {
+        let mut allocator_impl = self.lock();
+        allocator_impl.stats.peak_bytes = allocator_impl.stats.live_bytes;
+    }
+
+    /// Bytes in the heap's blocks that are handed out, including any slack
+    /// at the end of them. Quarantined and mapped blocks don't count.
+    pub fn total_allocated(&self) -> usize {
+        let allocator_impl = self.lock();
+        allocator_impl.used_bytes - allocator_impl.quarantine_bytes
+    }
+
+    /// Bytes in the heap's free blocks.
+    pub fn total_free(&self) -> usize {
+        self.lock().free_bytes
+    }
+
+    /// Size of the biggest free block in the heap, the largest allocation
+    /// that can be made without growing it, give or take alignment.
+    pub fn largest_free_block(&self) -> usize {
+        self.lock().largest_free()
+    }
+
+    /// Grows the heap by `bytes` and adds them to the free block at its end,
+    /// or makes one of them, like `with_capacity` does but at any time, to
+    /// top the reserve up. Reserved memory is never trimmed. Returns false
+    /// if the heap can't grow.
+    pub fn reserve(&self, bytes: usize) -> bool {
+        self.lock().reserve(bytes)
+    }
+
+    /// Number of allocations made so far in each size class.
+    pub fn size_class_histogram(&self) -> [u64; SIZE_CLASSES] {
+        self.lock().histogram
+    }
+
+    /// Describes every allocation that hasn't been freed yet, along with
+    /// the backtrace of where it was made.
+    ///
+    /// Nothing is traced while a thread panics, nor on a thread after one
+    /// of its allocations has failed, since std may be busy reporting that
+    /// with its backtrace lock held.
+    #[cfg(feature = "trace")]
+    pub fn leak_report(&self) -> String {
+        self.traces.report()
+    }
+
+    /// Prints `leak_report` to stderr.
+    #[cfg(feature = "trace")]
+    pub fn report_leaks(&self) {
+        eprint!("{}", self.leak_report());
+    }
+
+    /// The last `RECORD_CAPACITY` operations on the heap, oldest first, for
+    /// `record::replay`.
+    ///
+    /// Only what goes through the allocator lock is recorded, so a trace
+    /// with allocations served by the thread cache, or frees made with
+    /// `defer_free`, won't replay.
+    #[cfg(feature = "record")]
+    pub fn recorded_trace(&self) -> Vec<TraceEvent> {
+        // copy the events out first, since the Vec may come from this allocator
+        let (events, len) = self.lock().recorder.events();
+        let start = len.saturating_sub(RECORD_CAPACITY);
+        (start..len).map(|i| events[i % RECORD_CAPACITY]).collect()
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn stats_json(&self) -> String {
+        serde_json::to_string(&self.stats()).unwrap()
+    }
+
+    /// The stats in Prometheus' text format, for serving from a metrics
+    /// endpoint. `allocator_fragmentation_ratio` is how much of the free
+    /// space lies outside the largest free block, which takes a walk of
+    /// the chain under the lock.
+    #[cfg(feature = "metrics")]
+    pub fn export_metrics(&self) -> String {
+        let live_bytes = self.live_bytes_relaxed();
+        let total_allocations = self.total_allocations_relaxed();
+        let (stats, free_bytes, largest_free) = {
+            let allocator_impl = self.lock();
+            (allocator_impl.stats, allocator_impl.free_bytes, allocator_impl.largest_free())
+        };
+        let fragmentation = if free_bytes == 0 {
+            0.0
+        } else {
+            1.0 - largest_free as f64 / free_bytes as f64
+        };
+
+        // formatted once the lock is let go of, since this allocates
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}");
+        };
+        metric("allocator_live_bytes", "gauge", "Bytes requested by live allocations.", &live_bytes);
+        metric("allocator_peak_bytes", "gauge", "Highest live bytes seen.", &stats.peak_bytes);
+        metric("allocator_free_bytes", "gauge", "Bytes in free blocks.", &free_bytes);
+        metric("allocator_total_allocations_total", "counter", "Allocations made.", &total_allocations);
+        metric("allocator_total_frees_total", "counter", "Allocations freed.", &stats.total_frees);
+        metric(
+            "allocator_fragmentation_ratio",
+            "gauge",
+            "Share of free bytes outside the largest free block.",
+            &fragmentation,
+        );
+        out
+    }
+
+    /// Every block in the chain, in address order, not counting the sentinel.
+    pub fn blocks(&self) -> Vec<BlockInfo> {
+        self.iter_blocks().collect()
+    }
+
+    /// How many blocks in the chain are in use and how many are free, not
+    /// counting the sentinel. Quarantined blocks count as in use.
+    pub fn block_counts(&self) -> (usize, usize) {
+        self.lock().block_counts()
+    }
+
+    /// Walks the block chain like `blocks`, but without allocating. The
+    /// lock is held until the iterator is dropped, so don't use the
+    /// allocator in the meantime.
+    pub fn iter_blocks(&self) -> BlockIter<'_, S> {
+        BlockIter::new(self.lock_diagnostics())
+    }
+
+    /// Like `iter_blocks`, but only the free blocks. Free blocks are kept
+    /// in the chain rather than a list of their own, so this still walks
+    /// every block, in address order.
+    pub fn iter_free_blocks(&self) -> impl Iterator<Item = BlockInfo> + '_ {
+        self.iter_blocks().filter(|block| block.free)
+    }
+
+    pub fn dump_blocks(&self) {
+        self.lock_diagnostics().dump_blocks();
+    }
+
+    /// Slides every block in use toward the start of the heap, closing the
+    /// free holes between them, and gives the free space that ends up at
+    /// the back to the source. `relocate` is called with the old and new
+    /// data pointers and the block's size for every block that moved. A
+    /// block keeps all the alignment its address has, since what it was
+    /// allocated with isn't known, so it can stay put if the hole in front
+    /// of it is too small for that. Does nothing while the thread cache is
+    /// on or frees are deferred, since those hold on to blocks by address.
+    ///
+    /// # Safety
+    ///
+    /// Every pointer into a moved block is invalid afterwards, so the
+    /// caller has to be able to fix them all up in `relocate`. `relocate`
+    /// must not use the allocator.
+    pub unsafe fn compact(&self, mut relocate: impl FnMut(*mut u8, *mut u8, usize)) {
+        if self.thread_cache.load(Ordering::Acquire) {
+            return;
+        }
+        let mut allocator_impl = self.lock();
+        if allocator_impl.deferred.is_some() {
+            return;
+        }
+        self.pending_frees.drain(&mut allocator_impl);
+        allocator_impl.compact(&mut relocate);
+        allocator_impl.trim();
+    }
+
+    /// Merges every run of adjacent free blocks into one, returning how
+    /// many blocks were merged away. Frees already coalesce with their
+    /// neighbours, so this only finds anything to do when something left
+    /// free blocks side by side.
+    pub fn coalesce_all(&self) -> usize {
+        let mut allocator_impl = self.lock();
+        let merges = allocator_impl.coalesce_all();
+        if !self.depot.is_popping() {
+            allocator_impl.trim();
+        }
+        merges
+    }
+
+    /// Walks the block chain looking for anything that shouldn't be there,
+    /// like overlapping or unsorted blocks or free blocks that weren't merged.
+    pub fn check_integrity(&self) -> Result<(), IntegrityError> {
+        self.lock().check_integrity()
+    }
+
+    /// Rebuilds a corrupted block chain by scanning the heap for intact
+    /// headers and linking those back up in address order. Anything in
+    /// between is left out, so it's never handed out again. Pending
+    /// deferred frees are dropped, leaving those blocks in use.
+    ///
+    /// This is best effort, for looking at a heap after the fact in tests
+    /// and tools: a stale header inside a lost stretch looks as intact as
+    /// any other.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may be freed or reallocated afterwards unless it's in a
+    /// recovered block, and every byte between the first and the current
+    /// break has to be readable.
+    pub unsafe fn recover(&self) -> RecoveryReport {
+        unsafe { self.lock().recover() }
+    }
+
+    /// Renders the block chain as a graphviz digraph, with free blocks filled.
+    pub fn to_dot(&self) -> String {
+        let blocks = self.blocks();
+        let mut dot = String::from("digraph blocks {\n    node [shape=record];\n");
+        for (i, block) in blocks.iter().enumerate() {
+            let _ = write!(
+                dot,
+                "    b{i} [label=\"{:#x}|size={}|free={}\"",
+                block.data, block.size, block.free
+            );
+            if block.free {
+                dot.push_str(", style=filled, fillcolor=palegreen");
+            }
+            dot.push_str("];\n");
+        }
+        for i in 1..blocks.len() {
+            let _ = writeln!(dot, "    b{} -> b{i};", i - 1);
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl Allocator<StaticSource> {
+    /// An allocator that only ever carves from `buf`, and fails once it's
+    /// used up rather than asking the OS for more.
+    pub const fn from_static(buf: &'static mut [u8]) -> Self {
+        Self::with_source(StaticSource::new(buf), FitStrategy::FirstFit)
+    }
+}
+
+impl Allocator<MockSource> {
+    /// An allocator over a fresh 1 MiB mock, for tests that need to be
+    /// reproducible. Nothing in the allocator is random yet, but anything
+    /// that ever is will draw from `seed`, so the seed is all a failure
+    /// report needs.
+    #[cfg(feature = "test-util")]
+    pub fn new_seeded(seed: u64) -> Self {
+        let mut allocator_impl = AllocatorImpl::new(MockSource::new(1 << 20), FitStrategy::FirstFit);
+        allocator_impl.seed = seed;
+        Self::from_impl(allocator_impl)
+    }
+
+    /// The seed passed to `new_seeded`.
+    #[cfg(feature = "test-util")]
+    pub fn seed(&self) -> u64 {
+        self.lock().seed
+    }
+
+    /// Captures the whole heap, relative to the start of the mock, so it can
+    /// be put back with `restore` later, e.g. to replay a fuzzer's input.
+    pub fn snapshot(&self) -> HeapSnapshot {
+        let allocator_impl = self.lock_diagnostics();
+        let base = allocator_impl.source.base();
+        let mut blocks = Vec::new();
+        let mut current = allocator_impl.head.next;
+        while let Some(block) = current {
+            // SAFETY: block.next is a valid pointer to an instance of Block.
+            let block_ref = unsafe { block.as_ref() };
+            blocks.push(SnapshotBlock {
+                addr: block.as_ptr() as usize - base,
+                data: block_ref.data as usize - base,
+                size: block_ref.size,
+                free: block_ref.free,
+                quarantined: block_ref.quarantined,
+                tag: block_ref.tag,
+                secure: block_ref.secure,
+                lead: block_ref.lead,
+            });
+            current = block_ref.next;
+        }
+        let mut quarantine = Vec::new();
+        let mut current = allocator_impl.quarantine_head;
+        while let Some(block) = current {
+            quarantine.push(block.as_ptr() as usize - base);
+            // SAFETY: the quarantine only links blocks in the chain.
+            current = unsafe { block.as_ref() }.quarantine_next;
+        }
+
+        HeapSnapshot {
+            len: allocator_impl.source.current_break() - base,
+            blocks,
+            cursor: allocator_impl
+                .cursor
+                .filter(|&block| block != NonNull::from(&allocator_impl.head))
+                .map(|block| block.as_ptr() as usize - base),
+            quarantine,
+            quarantine_bytes: allocator_impl.quarantine_bytes,
+            stats: allocator_impl.stats,
+            histogram: allocator_impl.histogram,
+        }
+    }
+
+    /// Puts the heap back the way it was when `snap` was taken.
+    ///
+    /// # Safety
+    ///
+    /// Nothing allocated since the snapshot may be used afterwards, and
+    /// everything freed since then has to be treated as allocated again.
+    /// The thread cache must not be enabled.
+    pub unsafe fn restore(&self, snap: HeapSnapshot) {
+        let mut allocator_impl = self.lock();
+        // like deferred frees, pending ones belong to the heap being replaced
+        self.pending_frees.clear();
+        assert!(snap.len <= allocator_impl.source.capacity(), "snapshot doesn't fit the mock");
+        assert!(allocator_impl.regions.is_none(), "can't restore over mapped regions");
+        let base = allocator_impl.source.base();
+        let len = allocator_impl.source.current_break() - base;
+        if snap.len < len {
+            allocator_impl.source.shrink(len - snap.len);
+        } else if snap.len > len {
+            allocator_impl.source.grow(snap.len - len);
+        }
+        // whatever the snapshot's heap wrote is in there now
+        allocator_impl.high_water = allocator_impl.high_water.max(base + snap.len);
+
+        let base_ptr = allocator_impl.source.base_ptr();
+        let at = |offset: usize| unsafe { base_ptr.add(offset) }.cast::<Block>();
+        let mut next = None;
+        for block in snap.blocks.iter().rev() {
+            let addr = at(block.addr);
+            // SAFETY: the header lies within the mock, and the caller
+            // promises nobody uses the memory it overwrites.
+            unsafe {
+                addr.as_ptr().write(Block {
+                    magic: allocator_impl.magic(),
+                    data: base_ptr.add(block.data).as_ptr(),
+                    size: block.size,
+                    next,
+                    free: block.free,
+                    quarantined: block.quarantined,
+                    quarantine_next: None,
+                    tag: block.tag,
+                    secure: block.secure,
+                    lead: block.lead,
+                });
+            }
+            next = Some(addr);
+        }
+        allocator_impl.head.next = next;
+        #[cfg(feature = "poison")]
+        for block in snap.blocks.iter().filter(|block| block.free) {
+            unsafe { at(block.addr).as_ref() }.poison();
+        }
+        let (free, used): (Vec<&SnapshotBlock>, Vec<_>) = snap.blocks.iter().partition(|block| block.free);
+        allocator_impl.free_bytes = free.iter().map(|block| block.size).sum();
+        allocator_impl.used_bytes = used.iter().map(|block| block.size).sum();
+        allocator_impl.secure_blocks = used.iter().filter(|block| block.secure).count();
+
+        allocator_impl.quarantine_head = snap.quarantine.first().map(|&offset| at(offset));
+        allocator_impl.quarantine_tail = snap.quarantine.last().map(|&offset| at(offset));
+        for pair in snap.quarantine.windows(2) {
+            unsafe { at(pair[0]).as_mut() }.quarantine_next = Some(at(pair[1]));
+        }
+        allocator_impl.quarantine_bytes = snap.quarantine_bytes;
+        // pending deferred frees belong to the heap being replaced
+        allocator_impl.deferred = None;
+        allocator_impl.deferred_bytes = 0;
+        allocator_impl.heap_bytes = snap.len;
+        allocator_impl.cursor = snap.cursor.map(at);
+        allocator_impl.stats = snap.stats;
+        allocator_impl.histogram = snap.histogram;
+    }
+}
+
+/// The state of an `Allocator<MockSource>`, see `Allocator::snapshot`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeapSnapshot {
+    // the break, as an offset into the mock like all addresses in here
+    len: usize,
+    blocks: Vec<SnapshotBlock>,
+    cursor: Option<usize>,
+    // oldest first
+    quarantine: Vec<usize>,
+    quarantine_bytes: usize,
+    stats: AllocStats,
+    histogram: [u64; SIZE_CLASSES],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SnapshotBlock {
+    addr: usize,
+    data: usize,
+    size: usize,
+    free: bool,
+    quarantined: bool,
+    tag: u32,
+    secure: bool,
+    lead: u8,
+}
+
+impl Default for Allocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// a local allocator going away while something still points into it is
+// almost always a bug, and with debug-checks fatal if the blocks are left
+// in a heap the next allocator may hand out again; the global one is never
+// dropped
+impl<S: MemorySource> Drop for Allocator<S> {
+    fn drop(&mut self) {
+        if !cfg!(debug_assertions) && !cfg!(feature = "debug-checks") {
+            return;
+        }
+        let allocator_impl = self.allocator_impl.get_mut();
+        // a corrupted chain can't be walked safely, and has been reported
+        // by whoever found it
+        if allocator_impl.check_integrity().is_err() {
+            return;
+        }
+        self.pending_frees.drain(allocator_impl);
+        let live = allocator_impl.live_blocks();
+        #[cfg(feature = "debug-checks")]
+        if live != 0 && allocator_impl.source.shared() {
+            fatal(format_args!("allocator dropped with {live} blocks still in use"));
+        }
+        if live != 0 {
+            eprintln!("allocator dropped with {live} blocks still in use");
+        }
+    }
+}
+
+// try_lock, so formatting from inside the allocator can't deadlock on itself
+impl<S: MemorySource> fmt::Debug for Allocator<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(allocator_impl) = self.try_lock_diagnostics() else {
+            return write!(f, "Allocator <locked>");
+        };
+        let mut blocks = BlockIter::new(allocator_impl);
+        write!(f, "Allocator ({} blocks)", blocks.by_ref().count())?;
+        blocks.rewind();
+        for info in blocks {
+            let state = if info.free { "free" } else { "used" };
+            write!(f, "\n  {:#x}: size={} {state}", info.addr, info.size)?;
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over the block chain, see `Allocator::iter_blocks`.
+pub struct BlockIter<'a, S> {
+    allocator_impl: DiagnosticLock<'a, S>,
+    current: Option<NonNull<Block>>,
+}
+
+impl<'a, S> BlockIter<'a, S> {
+    fn new(allocator_impl: DiagnosticLock<'a, S>) -> Self {
+        let current = allocator_impl.head.next;
+        Self { allocator_impl, current }
+    }
+
+    fn rewind(&mut self) {
+        self.current = self.allocator_impl.head.next;
+    }
+}
+
+impl<S> Iterator for BlockIter<'_, S> {
+    type Item = BlockInfo;
+
+    fn next(&mut self) -> Option<BlockInfo> {
+        let block = self.current?;
+        // SAFETY: the guard keeps the chain from changing under us, and
+        // block.next is a valid pointer to an instance of Block.
+        let block_ref = unsafe { block.as_ref() };
+        self.current = block_ref.next;
+        Some(block_ref.info(block))
+    }
+}
+
+// Blocks freed while lazy frees are on, in a Treiber stack linked through
+// the blocks themselves. It's only ever taken whole, so unlike the depot's
+// stacks it can't be fooled by a node being popped and pushed again (ABA).
+struct PendingFrees {
+    head: AtomicPtr<PendingFree>,
+}
+
+// Written over the first bytes of a pending block, which every block has
+// room for, see MIN_BLOCK_SIZE.
+#[repr(C)]
+struct PendingFree {
+    next: *mut PendingFree,
+    // what the block was freed with
+    size: usize,
+}
+
+impl PendingFrees {
+    const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(null_mut()),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+
+    // Safety: `ptr` has to be the data of a block in the heap, which nothing
+    // uses anymore.
+    unsafe fn push(&self, ptr: *mut u8, size: usize) {
+        let node = ptr.cast::<PendingFree>();
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            // SAFETY: block data is aligned for a header, and large enough.
+            unsafe { node.write(PendingFree { next: head, size }) };
+            match self.head.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    // free everything pending into the heap, returning whether there was
+    // anything
+    fn drain<S: MemorySource>(&self, allocator_impl: &mut AllocatorImpl<S>) -> bool {
+        let mut node = self.head.swap(null_mut(), Ordering::Acquire);
+        let drained = !node.is_null();
+        while !node.is_null() {
+            // SAFETY: push wrote the node; it's read before freeing the
+            // block, which may poison it.
+            let PendingFree { next, size } = unsafe { node.read() };
+            // the alignment isn't kept, and 1 fits any block
+            let layout = unsafe { Layout::from_size_align_unchecked(size, 1) };
+            unsafe { allocator_impl.deallocate(node.cast(), layout) };
+            node = next;
+        }
+        drained
+    }
+
+    fn clear(&self) {
+        self.head.store(null_mut(), Ordering::Relaxed);
+    }
+}
+
+// Copies of a few stats that can be read without the lock.
+struct RelaxedStats {
+    live_bytes: AtomicUsize,
+    total_allocations: AtomicU64,
+    secure_blocks: AtomicUsize,
+}
+
+// The allocator lock, which publishes the stats to RelaxedStats whenever it's
+// let go of.
+pub(crate) struct HeapLock<'a, S> {
+    allocator_impl: MutexGuard<'a, AllocatorImpl<S>>,
+    relaxed_stats: &'a RelaxedStats,
+}
+
+impl<S> Deref for HeapLock<'_, S> {
+    type Target = AllocatorImpl<S>;
+
+    fn deref(&self) -> &AllocatorImpl<S> {
+        &self.allocator_impl
+    }
+}
+
+impl<S> DerefMut for HeapLock<'_, S> {
+    fn deref_mut(&mut self) -> &mut AllocatorImpl<S> {
+        &mut self.allocator_impl
+    }
+}
+
+impl<S> Drop for HeapLock<'_, S> {
+    fn drop(&mut self) {
+        let stats = &self.allocator_impl.stats;
+        self.relaxed_stats.live_bytes.store(stats.live_bytes, Ordering::Relaxed);
+        self.relaxed_stats.total_allocations.store(stats.total_allocations, Ordering::Relaxed);
+        self.relaxed_stats.secure_blocks.store(self.allocator_impl.secure_blocks, Ordering::Relaxed);
+    }
+}
+
+thread_local! {
+    // the allocator this thread holds a DiagnosticLock of, if any
+    static DIAGNOSING: Cell<*const ()> = const { Cell::new(null()) };
+}
+
+// The lock, plus a note for this thread that allocations through the
+// allocator have to go to SCRATCH until it's dropped.
+struct DiagnosticLock<'a, S> {
+    allocator_impl: HeapLock<'a, S>,
+    previous: *const (),
+}
+
+impl<'a, S> DiagnosticLock<'a, S> {
+    fn new<T>(allocator: &T, allocator_impl: HeapLock<'a, S>) -> Self {
+        let previous = DIAGNOSING.replace(allocator as *const T as *const ());
+        Self { allocator_impl, previous }
+    }
+}
+
+impl<S> Deref for DiagnosticLock<'_, S> {
+    type Target = AllocatorImpl<S>;
+
+    fn deref(&self) -> &AllocatorImpl<S> {
+        &self.allocator_impl
+    }
+}
+
+impl<S> Drop for DiagnosticLock<'_, S> {
+    fn drop(&mut self) {
+        DIAGNOSING.set(self.previous);
+    }
+}
+
+const SCRATCH_SIZE: usize = 256 << 10;
+
+// A bump allocator for allocations made while diagnosing, which can't go
+// through the locked heap. Only the newest allocation is ever reused.
+struct Scratch {
+    buf: UnsafeCell<[u8; SCRATCH_SIZE]>,
+    used: AtomicUsize,
+}
+
+unsafe impl Sync for Scratch {}
+
+static SCRATCH: Scratch = Scratch {
+    buf: UnsafeCell::new([0; SCRATCH_SIZE]),
+    used: AtomicUsize::new(0),
+};
+
+impl Scratch {
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let base = self.buf.get() as usize;
+        let mut used = self.used.load(Ordering::Relaxed);
+        loop {
+            let start = checked_align_up(base + used, layout.align())? - base;
+            let end = start.checked_add(layout.size()).filter(|&end| end <= SCRATCH_SIZE)?;
+            match self.used.compare_exchange_weak(used, end, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return NonNull::new(self.buf.get().cast::<u8>().wrapping_add(start)),
+                Err(current) => used = current,
+            }
+        }
+    }
+
+    // extend the newest allocation in place, so a growing Vec doesn't
+    // leave a trail of copies behind
+    fn grow(&self, ptr: *mut u8, old_size: usize, new_size: usize) -> bool {
+        let start = ptr as usize - self.buf.get() as usize;
+        let Some(end) = start.checked_add(new_size).filter(|&end| end <= SCRATCH_SIZE) else {
+            return false;
+        };
+        self.used
+            .compare_exchange(start + old_size, end, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    // hand the newest allocation back; anything older stays put
+    fn deallocate(&self, ptr: *mut u8, size: usize) {
+        let start = ptr as usize - self.buf.get() as usize;
+        let _ = self.used.compare_exchange(start + size, start, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    fn contains(&self, ptr: *mut u8) -> bool {
+        let base = self.buf.get() as usize;
+        (base..base + SCRATCH_SIZE).contains(&(ptr as usize))
+    }
+}
+
+unsafe impl<S: MemorySource> GlobalAlloc for Allocator<S> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.allocate_raw(layout).map_or(null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.allocate_zeroed_checked(layout).map_or(null_mut(), |ptr| ptr.cast().as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if SCRATCH.contains(ptr) {
+            SCRATCH.deallocate(ptr, layout.size());
+            return;
+        }
+        // we hold the lock while diagnosing, so heap blocks freed in the
+        // meantime are leaked rather than deadlocking
+        if self.is_diagnosing() {
+            return;
+        }
+        #[cfg(feature = "trace")]
+        self.traces.forget(ptr);
+        // the cache would hand a secret straight back out unwiped, and a
+        // lazy free would leave it lying around until the heap fills up;
+        // which freed block is secure is only known under the lock
+        let secrets = self.relaxed_stats.secure_blocks.load(Ordering::Relaxed) != 0;
+        if !secrets && self.thread_cache.load(Ordering::Acquire) && thread_cache::deallocate(self, ptr, layout) {
+            return;
+        }
+        if !secrets && layout.size() != 0 && self.lazy_frees.load(Ordering::Relaxed) {
+            // SAFETY: with lazy frees, only blocks allocated here are freed.
+            unsafe { self.pending_frees.push(ptr, layout.size()) };
+            return;
+        }
+        let mut allocator_impl = self.lock();
+        if !allocator_impl.deallocate(ptr, layout) {
+            let policy = allocator_impl.free_policy;
+            // printing may allocate, so not while holding the lock
+            drop(allocator_impl);
+            // dangling pointers for zero bytes, like realloc to zero returns,
+            // are fine to free
+            if layout.size() != 0 {
+                foreign_free(policy, ptr);
+            }
+            return;
+        }
+        if !self.depot.is_popping() {
+            allocator_impl.trim();
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if ptr.is_null() {
+            // also like realloc(3), there's nothing to move, so just allocate
+            let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+            return unsafe { self.alloc(new_layout) };
+        }
+        if new_size == 0 {
+            // like realloc(3), this frees; the result is for zero bytes, so
+            // it mustn't be dereferenced, and freeing it does nothing
+            unsafe { self.dealloc(ptr, layout) };
+            return without_provenance_mut(layout.align());
+        }
+        if SCRATCH.contains(ptr) && new_size > layout.size() && SCRATCH.grow(ptr, layout.size(), new_size) {
+            return ptr;
+        }
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        // heap blocks can often be resized where they are, but the heap is
+        // off limits while diagnosing
+        if !SCRATCH.contains(ptr) && !self.is_diagnosing() {
+            let ptr = NonNull::new(ptr).unwrap();
+            let resized = if new_size >= layout.size() {
+                unsafe { AllocatorTrait::grow(self, ptr, layout, new_layout) }
+            } else {
+                unsafe { AllocatorTrait::shrink(self, ptr, layout, new_layout) }
+            };
+            return resized.map_or(null_mut(), |new| new.cast().as_ptr());
+        }
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
+
+unsafe impl<S: MemorySource> AllocatorTrait for Allocator<S> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_checked(layout).map_err(|_| AllocError {})
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_zeroed_checked(layout).map_err(|_| AllocError {})
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.dealloc(ptr.as_ptr(), layout);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let mut allocator_impl = self.lock();
+        if (ptr.as_ptr() as usize).is_multiple_of(new_layout.align()) {
+            let usable = allocator_impl.try_extend(ptr.as_ptr(), old_layout.size(), new_layout.size());
+            if let Some(usable) = usable {
+                return Ok(NonNull::slice_from_raw_parts(ptr, usable));
+            }
+        }
+        let flags = allocator_impl.header_flags(ptr.as_ptr());
+        drop(allocator_impl);
+        unsafe { self.relocate(ptr, old_layout, new_layout, flags) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // growing in place leaves whatever was there before in the new part
+        let new = unsafe { self.grow(ptr, old_layout, new_layout)? };
+        unsafe {
+            let grown = new.cast::<u8>().add(old_layout.size());
+            grown.write_bytes(0, new.len() - old_layout.size());
+        }
+        Ok(new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let mut allocator_impl = self.lock();
+        // a stricter alignment than the block happens to have means moving
+        if (ptr.as_ptr() as usize).is_multiple_of(new_layout.align())
+            && allocator_impl.shrink_in_place(ptr.as_ptr(), old_layout.size(), new_layout.size())
+        {
+            if !self.depot.is_popping() {
+                allocator_impl.trim();
+            }
+            return Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()));
+        }
+        let flags = allocator_impl.header_flags(ptr.as_ptr());
+        drop(allocator_impl);
+        unsafe { self.relocate(ptr, old_layout, new_layout, flags) }
+    }
+}
+
+impl<S: MemorySource> Allocator<S> {
+    // move an allocation that couldn't be resized in place, carrying its
+    // block's tag and secure flag over; freeing the old copy wipes it like
+    // any other secure free
+    unsafe fn relocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        (tag, secure): (u32, bool),
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // marked ones straight from the heap, like allocate_tagged and allocate_secure
+        let marks = (tag != 0 || secure).then_some(Marks { tag, secure });
+        let new = self.allocate_ptr(new_layout, marks).map_err(|_| AllocError {})?.ptr;
+        unsafe {
+            copy_nonoverlapping(
+                ptr.as_ptr(),
+                new.cast::<u8>().as_ptr(),
+                old_layout.size().min(new_layout.size()),
+            );
+            self.deallocate(ptr, old_layout);
+        }
+        Ok(new)
+    }
+}
+
+pub(crate) struct AllocatorImpl<S> {
+    head: Block,
+    source: S,
+    strategy: FitStrategy,
+    // last block handed out, used as the starting point for next-fit
+    cursor: Option<NonNull<Block>>,
+    stats: AllocStats,
+    histogram: [u64; SIZE_CLASSES],
+    // freed blocks that aren't reusable yet, oldest first
+    quarantine_head: Option<NonNull<Block>>,
+    quarantine_tail: Option<NonNull<Block>>,
+    quarantine_bytes: usize,
+    quarantine_budget: usize,
+    // blocks waiting for flush_deferred, newest first, and their sizes
+    deferred: Option<NonNull<Block>>,
+    deferred_bytes: usize,
+    // allocations that didn't fit in the heap and got a region of their own,
+    // linked through `next`
+    regions: Option<NonNull<Block>>,
+    // end of the capacity reserved up front, which is never trimmed
+    trim_floor: usize,
+    // a free tail this big or smaller is kept rather than trimmed
+    decommit_threshold: usize,
+    // how much the heap has been grown by, net of trimming
+    heap_bytes: usize,
+    // the break before the heap was first grown
+    initial_break: Option<NonNull<u8>>,
+    // the furthest the heap has been grown to, past which memory from a
+    // zeroed source hasn't been written to yet
+    high_water: usize,
+    // how many live blocks are secure, which keeps frees out of the thread
+    // cache while there are any
+    secure_blocks: usize,
+    // whether the block allocate_block last handed out is all untouched
+    // memory from a zeroed source
+    fresh: bool,
+    free_policy: FreePolicy,
+    growth_factor: f64,
+    // alignment and size granularity of every block
+    min_align: usize,
+    prefault: bool,
+    round_to_class: bool,
+    // smallest block handed out, see Allocator::with_min_alloc_size
+    min_block_size: usize,
+    // whether frees merge blocks with their free neighbours
+    coalesce: bool,
+    // see Allocator::with_scan_limit
+    scan_limit: usize,
+    // see Allocator::pointer_lookups
+    lookups: u64,
+    // see Block::OWNER_BITS, taken when the first block is made
+    #[cfg(feature = "debug-checks")]
+    owner: u32,
+    // sizes of the blocks in the chain that aren't free (quarantined ones
+    // included), and of those that are
+    used_bytes: usize,
+    free_bytes: usize,
+    #[cfg(feature = "record")]
+    recorder: Recorder,
+    // see Allocator::new_seeded
+    #[cfg(feature = "test-util")]
+    seed: u64,
+}
+
+unsafe impl<S: Send> Send for AllocatorImpl<S> {}
+unsafe impl Send for Block {}
+unsafe impl Sync for Block {}
+
+impl<S: MemorySource> AllocatorImpl<S> {
+    const BLOCK0: Block = Block {
+        magic: Block::MAGIC,
+        data: NonNull::dangling().as_ptr(),
+        size: 0,
+        next: None,
+        free: false,
+        quarantined: false,
+        quarantine_next: None,
+        tag: 0,
+        secure: false,
+        lead: 0,
+    };
+
+    pub const fn new(source: S, strategy: FitStrategy) -> Self {
+        Self {
+            head: Self::BLOCK0,
+            source,
+            strategy,
+            cursor: None,
+            stats: AllocStats::EMPTY,
+            histogram: [0; SIZE_CLASSES],
+            quarantine_head: None,
+            quarantine_tail: None,
+            quarantine_bytes: 0,
+            quarantine_budget: 0,
+            deferred: None,
+            deferred_bytes: 0,
+            regions: None,
+            trim_floor: 0,
+            decommit_threshold: 0,
+            heap_bytes: 0,
+            initial_break: None,
+            high_water: 0,
+            secure_blocks: 0,
+            fresh: false,
+            free_policy: FreePolicy::Warn,
+            growth_factor: 1.0,
+            min_align: 1,
+            prefault: false,
+            round_to_class: false,
+            min_block_size: MIN_BLOCK_SIZE,
+            coalesce: true,
+            scan_limit: 0,
+            lookups: 0,
+            #[cfg(feature = "debug-checks")]
+            owner: 0,
+            used_bytes: 0,
+            free_bytes: 0,
+            #[cfg(feature = "record")]
+            recorder: Recorder::new(),
+            #[cfg(feature = "test-util")]
+            seed: 0,
+        }
+    }
+
+    const fn with_config(source: S, config: AllocatorConfig) -> Self {
+        let mut allocator_impl = Self::new(source, config.strategy);
+        allocator_impl.quarantine_budget = config.quarantine_bytes;
+        allocator_impl.growth_factor = clamp_growth_factor(config.growth_factor);
+        allocator_impl.min_align = check_min_alignment(config.min_alignment);
+        allocator_impl.prefault = config.prefault;
+        allocator_impl.free_policy = config.free_policy;
+        allocator_impl.round_to_class = config.size_class_rounding;
+        allocator_impl.min_block_size = clamp_min_alloc_size(config.min_alloc_size);
+        allocator_impl.coalesce = config.coalesce;
+        allocator_impl.scan_limit = config.scan_limit;
+        allocator_impl.decommit_threshold = config.decommit_threshold;
+        allocator_impl
+    }
+
+    // hand out and count a block for `layout`
+    fn place(&mut self, layout: Layout) -> Result<Handle, AllocFailure> {
+        if layout.align() > MAX_ALIGN {
+            return Err(AllocFailure::UnsupportedAlignment);
+        }
+        let block = self.place_block(layout)?;
+        Ok(self.handed_out(block, layout))
+    }
+
+    // like place, but only out of free blocks already in the heap
+    pub fn allocate_no_grow(&mut self, layout: Layout) -> Option<NonNull<[u8]>> {
+        self.place_no_grow(layout).map(|handle| handle.usable())
+    }
+
+    fn place_no_grow(&mut self, layout: Layout) -> Option<Handle> {
+        if layout.align() > MAX_ALIGN {
+            return None;
+        }
+        let block = self.reuse_block(self.block_layout(layout).ok()?)?;
+        Some(self.handed_out(block, layout))
+    }
+
+    // count a block allocate just handed out for `layout`
+    fn handed_out(&mut self, block: NonNull<Block>, layout: Layout) -> Handle {
+        self.count_allocation(layout);
+        #[cfg(feature = "record")]
+        self.recorder.record(TraceOp::Alloc, unsafe { block.as_ref() }.data, layout.size(), layout.align());
+        // a new region is always put in front of the others
+        let mapped = self.regions == Some(block);
+        Handle { block, layout, mapped }
+    }
+
+    fn count_allocation(&mut self, layout: Layout) {
+        self.stats.live_bytes += layout.size();
+        self.stats.peak_bytes = self.stats.peak_bytes.max(self.stats.live_bytes);
+        self.stats.total_allocations += 1;
+        self.histogram[size_class(layout.size())] += 1;
+    }
+
+    // fold in allocations and frees that were counted somewhere else
+    pub(crate) fn record(&mut self, live_delta: isize, allocations: &[u64; SIZE_CLASSES], frees: u64) {
+        self.stats.live_bytes = self.stats.live_bytes.wrapping_add_signed(live_delta);
+        self.stats.peak_bytes = self.stats.peak_bytes.max(self.stats.live_bytes);
+        for (count, &new) in self.histogram.iter_mut().zip(allocations) {
+            *count += new;
+            self.stats.total_allocations += new;
+        }
+        self.stats.total_frees += frees;
+    }
+
+    // like place, but leaves the stats alone
+    pub(crate) fn allocate_block(&mut self, layout: Layout) -> Result<NonNull<[u8]>, AllocFailure> {
+        // SAFETY: place_block only returns live blocks.
+        self.place_block(layout).map(|block| unsafe { block.as_ref() }.usable())
+    }
+
+    fn place_block(&mut self, layout: Layout) -> Result<NonNull<Block>, AllocFailure> {
+        let layout = self.block_layout(layout)?;
+        self.fresh = false;
+        if let Some(block) = self.reuse_block(layout) {
+            return Ok(block);
+        }
+
+        let previous_break = self.source.current_break();
+        let alloc_sz = checked_align_up(previous_break, align_of::<Block>())
+            .and_then(|block_addr| block_addr.checked_add(size_of::<Block>()))
+            .and_then(|header_end| checked_align_up(header_end, layout.align()))
+            .and_then(|data| data.checked_add(layout.size()))
+            .ok_or(AllocFailure::SizeOverflow)?;
+        let mut increment = alloc_sz - previous_break;
+        if layout.align() > align_of::<Block>() {
+            // if something else moves the break before we grow it, the
+            // padding in front of data changes by up to the alignment
+            increment = increment.checked_add(layout.align()).ok_or(AllocFailure::SizeOverflow)?;
+        }
+        // sources like sbrk take the increment as an isize
+        if isize::try_from(increment).is_err() {
+            return Err(AllocFailure::SizeOverflow);
+        }
+
+        // grow geometrically, leaving the surplus as a free block behind
+        // this one, and settle for what's needed if that's too much
+        let surplus = (self.heap_bytes as f64 * (self.growth_factor - 1.0)) as usize;
+        let surplus = surplus.min(isize::MAX as usize);
+        let high_water = self.high_water;
+        let grown = match self.grow(increment.max(surplus)) {
+            Some(new_brk) => Some((new_brk, increment.max(surplus))),
+            None if surplus > increment => self.grow(increment).map(|new_brk| (new_brk, increment)),
+            None => None,
+        };
+        let Some((new_brk, increment)) = grown else {
+            return self.allocate_region(layout);
+        };
+        let new_brk = new_brk.as_ptr();
+        let end = new_brk as usize + increment;
+
+        let mut new_block_addr = align_up(new_brk as usize, align_of::<Block>());
+        let data = new_brk.with_addr(align_up(new_block_addr + size_of::<Block>(), layout.align()));
+        if data as usize + layout.size() > end {
+            // the break moved too far, keep what we got and try again
+            self.insert_free(new_brk, increment);
+            return self.place_block(layout);
+        }
+        // padding in front of highly aligned data that's big enough to hold
+        // a block of its own becomes a free one, with our header moved up
+        // against the data
+        let header_addr = data as usize - size_of::<Block>();
+        let mut lead = new_block_addr - new_brk as usize;
+        if header_addr >= new_block_addr + size_of::<Block>() + Block::MIN_SPLIT {
+            self.insert_free(new_brk, header_addr - new_brk as usize);
+            new_block_addr = header_addr;
+            lead = 0;
+        }
+        let mut new_block_ptr = NonNull::new(new_brk.with_addr(new_block_addr).cast::<Block>()).unwrap();
+        // the block gets everything up to the new break for now, and split
+        // returns any slack past the allocation
+        unsafe {
+            new_block_ptr.as_ptr().write(Block {
+                magic: self.magic(),
+                data,
+                size: end - data as usize,
+                next: None,
+                free: false,
+                quarantined: false,
+                quarantine_next: None,
+                tag: 0,
+                secure: false,
+                lead: lead as u8,
+            });
+        }
+        self.head.insert(new_block_ptr);
+        self.cursor = Some(new_block_ptr);
+        // SAFETY: new_block was initialized above.
+        let new_block = unsafe { new_block_ptr.as_mut() };
+        self.used_bytes += new_block.size;
+        self.split_used(new_block, layout.size());
+        // the tail split off is fresh memory
+        #[cfg(feature = "poison")]
+        if let Some(rest) = new_block.next {
+            unsafe { rest.as_ref() }.poison();
+        }
+        #[cfg(feature = "debug-checks")]
+        new_block.check_overlap();
+
+        // memory the heap grew back over after trimming may be dirty
+        self.fresh = self.source.zeroed() && data as usize >= high_water;
+        Ok(new_block_ptr)
+    }
+
+    // the size and alignment of the block an allocation of `layout` gets
+    fn block_layout(&self, layout: Layout) -> Result<Layout, AllocFailure> {
+        let size = self.block_size(layout.size()).ok_or(AllocFailure::SizeOverflow)?;
+        Layout::from_size_align(size, layout.align().max(self.min_align)).map_err(|_| AllocFailure::SizeOverflow)
+    }
+
+    // hand out a free block that fits the block layout `layout`, if any
+    fn reuse_block(&mut self, layout: Layout) -> Option<NonNull<Block>> {
+        let mut block_ptr = self.find_fit(layout)?;
+        // SAFETY: find_fit only returns blocks linked into the chain.
+        let front = unsafe { block_ptr.as_mut() };
+        if !(front.data as usize).is_multiple_of(layout.align()) {
+            block_ptr = front.split_front(layout.align());
+            // the new header comes out of free space
+            self.free_bytes -= size_of::<Block>();
+        }
+        self.cursor = Some(block_ptr);
+        // SAFETY: find_fit only returns blocks linked into the chain.
+        let block = unsafe { block_ptr.as_mut() };
+        block.free = false;
+        #[cfg(feature = "poison")]
+        check_poison(block.data, layout.size());
+        self.free_bytes -= block.size;
+        self.used_bytes += block.size;
+        self.split_used(block, layout.size());
+        #[cfg(feature = "debug-checks")]
+        block.check_overlap();
+        Some(block_ptr)
+    }
+
+    // how big a block holding `size` bytes is made, always a whole number
+    // of header alignments so whatever gets split off behind it starts
+    // right at its end
+    fn block_size(&self, size: usize) -> Option<usize> {
+        let align = self.min_align.max(align_of::<Block>());
+        let size = checked_align_up(size.max(self.min_block_size), align)?;
+        if self.round_to_class {
+            class_size(size)
+        } else {
+            Some(size)
+        }
+    }
+
+    // grow the source, faulting the new pages in if asked to
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+        let old_brk = self.source.grow(increment)?;
+        self.heap_bytes += increment;
+        self.initial_break.get_or_insert(old_brk);
+        self.high_water = self.high_water.max(old_brk.as_ptr() as usize + increment);
+        #[cfg(feature = "record")]
+        self.recorder.grew(old_brk.as_ptr() as usize);
+        if self.prefault {
+            let end = old_brk.as_ptr() as usize + increment;
+            let mut page = align_up(old_brk.as_ptr() as usize, PAGE_SIZE);
+            while page < end {
+                // write back what's there, so zeroed memory stays zeroed
+                let byte = old_brk.as_ptr().with_addr(page);
+                unsafe { byte.write_volatile(byte.read_volatile()) };
+                page += PAGE_SIZE;
+            }
+        }
+        Some(old_brk)
+    }
+
+    // grow the heap by `bytes` and turn it into one free block
+    fn reserve(&mut self, bytes: usize) -> bool {
+        match self.grow(bytes) {
+            Some(old_brk) => {
+                self.insert_free(old_brk.as_ptr(), bytes);
+                self.trim_floor = old_brk.as_ptr() as usize + bytes;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // turn `bytes` of fresh heap at `old_brk` into a free block
+    fn insert_free(&mut self, old_brk: *mut u8, bytes: usize) {
+        let start = old_brk as usize;
+        let block_addr = align_up(start, align_of::<Block>());
+        let data = block_addr + size_of::<Block>();
+        if data > start.saturating_add(bytes) {
+            // too small to even hold a header
+            return;
+        }
+
+        let block = NonNull::new(old_brk.with_addr(block_addr).cast::<Block>()).unwrap();
+        unsafe {
+            block.as_ptr().write(Block {
+                magic: self.magic(),
+                data: old_brk.with_addr(data),
+                size: start + bytes - data,
+                next: None,
+                free: false,
+                quarantined: false,
+                quarantine_next: None,
+                tag: 0,
+                secure: false,
+                lead: (block_addr - start) as u8,
+            });
+        }
+        #[cfg(feature = "poison")]
+        unsafe { block.as_ref() }.poison();
+        self.head.insert(block);
+        self.used_bytes += start + bytes - data;
+        let (prev, block) = self.find_by_ptr(old_brk.with_addr(data)).unwrap();
+        self.release(prev, block);
+    }
+
+    fn allocate_region(&mut self, layout: Layout) -> Result<NonNull<Block>, AllocFailure> {
+        // regions are only page aligned, so leave room to align data
+        let len = (size_of::<Block>() + layout.align())
+            .checked_add(layout.size())
+            .ok_or(AllocFailure::SizeOverflow)?;
+        let region = self.source.map(len).ok_or(AllocFailure::OutOfMemory)?;
+
+        let block = region.cast::<Block>();
+        let region = region.as_ptr();
+        let data = region.with_addr(align_up(region as usize + size_of::<Block>(), layout.align()));
+        // the block owns the rest of the region, which also tells
+        // deallocate how much to unmap
+        unsafe {
+            block.as_ptr().write(Block {
+                magic: self.magic(),
+                data,
+                size: region as usize + len - data as usize,
+                next: self.regions,
+                free: false,
+                quarantined: false,
+                quarantine_next: None,
+                tag: 0,
+                secure: false,
+                lead: 0,
+            });
+        }
+        self.regions = Some(block);
+        self.fresh = self.source.map_zeroed();
+        Ok(block)
+    }
+
+    // unlink the region owning `ptr`, if any
+    // regions are never split or grown, but a new size that still fits the
+    // region, and uses at least half of it, is fine where it is; returns how
+    // much of it is usable
+    fn resize_region(&mut self, ptr: *mut u8, old_size: usize, new_size: usize) -> Option<usize> {
+        let mut region = self.regions;
+        while let Some(block) = region {
+            // SAFETY: regions only links blocks at the start of a mapped region.
+            let block = unsafe { block.as_ref() };
+            block.check_magic();
+            if block.data == ptr {
+                if new_size > block.size || new_size < block.size / 2 {
+                    return None;
+                }
+                self.stats.live_bytes = self.stats.live_bytes.saturating_sub(old_size) + new_size;
+                self.stats.peak_bytes = self.stats.peak_bytes.max(self.stats.live_bytes);
+                return Some(block.size);
+            }
+            region = block.next;
+        }
+        None
+    }
+
+    fn take_region(&mut self, ptr: *mut u8) -> Option<NonNull<Block>> {
+        let mut link = &mut self.regions;
+        while let Some(mut region) = *link {
+            // SAFETY: regions only links blocks at the start of a mapped region.
+            let block = unsafe { region.as_mut() };
+            block.check_magic();
+            if block.data == ptr {
+                *link = block.next;
+                return Some(region);
+            }
+            link = &mut block.next;
+        }
+        None
+    }
+
+    fn find_fit(&mut self, layout: Layout) -> Option<NonNull<Block>> {
+        // how many more free blocks may be looked at
+        let mut budget = if self.scan_limit == 0 { usize::MAX } else { self.scan_limit };
+        match self.strategy {
+            FitStrategy::FirstFit => self.head.find_first_fit(layout, &mut budget),
+            FitStrategy::BestFit => self.head.find_best_fit(layout, &mut budget),
+            FitStrategy::NextFit => {
+                let mut start = self.cursor.unwrap_or(NonNull::from(&mut self.head));
+                // SAFETY: the cursor always points to a block in the chain.
+                unsafe { start.as_mut() }
+                    .find_first_fit(layout, &mut budget)
+                    .or_else(|| self.head.find_first_fit_until(layout, start, &mut budget))
+            }
+        }
+    }
+
+    // returns whether `ptr` was one of ours
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) -> bool {
+        #[cfg(feature = "debug-checks")]
+        self.check_layout(ptr, layout);
+        let found = unsafe { self.free_block(ptr) };
+        if found {
+            // blocks can be freed with more than they were allocated with
+            self.stats.live_bytes = self.stats.live_bytes.saturating_sub(layout.size());
+            self.stats.total_frees += 1;
+            #[cfg(feature = "record")]
+            self.recorder.record(TraceOp::Free, ptr, layout.size(), layout.align());
+        }
+        #[cfg(feature = "debug-checks")]
+        if !found {
+            self.check_owner(ptr);
+        }
+        found
+    }
+
+    // the magic this allocator's headers start with, see Block::OWNER_BITS
+    #[cfg(not(feature = "debug-checks"))]
+    fn magic(&mut self) -> u64 {
+        Block::MAGIC
+    }
+
+    #[cfg(feature = "debug-checks")]
+    fn magic(&mut self) -> u64 {
+        Block::MAGIC & !Block::OWNER_BITS | u64::from(self.owner())
+    }
+
+    #[cfg(feature = "debug-checks")]
+    fn owner(&mut self) -> u32 {
+        if self.owner == 0 {
+            self.owner = NEXT_OWNER.fetch_add(1, Ordering::Relaxed);
+        }
+        self.owner
+    }
+
+    // `ptr` isn't ours, but if there's a header right in front of it,
+    // another allocator handed it out. Only pointers between our first
+    // block and the break are looked at, since that much of the heap is
+    // known to be mapped, which is where another allocator sharing the
+    // break puts its blocks. Headers padded away from their data aren't
+    // found.
+    #[cfg(feature = "debug-checks")]
+    fn check_owner(&mut self, ptr: *mut u8) {
+        let Some(first) = self.head.next else {
+            return;
+        };
+        let header = ptr.wrapping_sub(size_of::<Block>());
+        let brk = self.source.current_break();
+        if header.addr() < first.as_ptr().addr() || ptr.addr() > brk {
+            return;
+        }
+        if !header.addr().is_multiple_of(align_of::<Block>()) {
+            return;
+        }
+        // SAFETY: the header is aligned and lies within the heap.
+        let Some(block) = (unsafe { Block::intact_at(header, brk) }) else {
+            return;
+        };
+        // SAFETY: intact_at checked it's a header.
+        let block = unsafe { block.as_ref() };
+        if block.data == ptr {
+            self.check_same_owner(block);
+        }
+    }
 
-pub struct Allocator {
-    allocator_impl: Mutex<AllocatorImpl>,
-}
+    #[cfg(feature = "debug-checks")]
+    fn check_same_owner(&mut self, block: &Block) {
+        let owner = self.owner();
+        if block.owner() != owner {
+            fatal(format_args!(
+                "cross-allocator free of {:?}: allocated by allocator {}, freed with allocator {owner}",
+                block.data,
+                block.owner()
+            ));
+        }
+    }
 
-impl Allocator {
-    pub const fn new() -> Self {
-        Self {
-            allocator_impl: Mutex::new(AllocatorImpl::new()),
+    // make sure `layout` could have been what the block at `ptr` was
+    // allocated with
+    #[cfg(feature = "debug-checks")]
+    fn check_layout(&mut self, ptr: *mut u8, layout: Layout) {
+        if let Some(block) = self.find_block(ptr) {
+            // SAFETY: both lists only link valid blocks.
+            unsafe { block.as_ref() }.check_layout(layout);
         }
     }
-}
 
-unsafe impl GlobalAlloc for Allocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let alloca = self.allocator_impl.lock().allocate(layout);
-        assert!(alloca.is_aligned());
-        alloca
+    // the block owning `ptr` in the chain, together with its predecessor
+    fn find_by_ptr(&mut self, ptr: *mut u8) -> Option<(NonNull<Block>, NonNull<Block>)> {
+        self.lookups += 1;
+        self.head.find_by_ptr(ptr)
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        self.allocator_impl.lock().deallocate(ptr);
+    // the block at `ptr`, whether it's in the chain or a region of its own
+    fn find_block(&mut self, ptr: *mut u8) -> Option<NonNull<Block>> {
+        let mut block = self.find_by_ptr(ptr).map(|(_, block)| block);
+        let mut region = self.regions;
+        while let (None, Some(next)) = (block, region) {
+            // SAFETY: regions only links blocks at the start of a mapped region.
+            let next = unsafe { next.as_ref() };
+            if next.data == ptr {
+                block = region;
+            }
+            region = next.next;
+        }
+        block
     }
-}
 
-unsafe impl AllocatorTrait for Allocator {
-    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        let ptr = self.allocator_impl.lock().allocate(layout);
-        assert!(ptr.is_aligned());
-        if ptr.is_null() {
-            Err(AllocError {})
+    // mark the block `handle` is for; only while holding the lock, since
+    // the block is still in the heap
+    fn mark(&mut self, mut handle: Handle, marks: Option<Marks>) -> Handle {
+        if let Some(marks) = marks {
+            // SAFETY: a handle is only made for a live block.
+            let block = unsafe { handle.block.as_mut() };
+            block.tag = marks.tag;
+            block.secure = marks.secure;
+            self.secure_blocks += usize::from(marks.secure);
+        }
+        handle
+    }
+
+    // the tag and secure flag of the block at `ptr`, which a block that
+    // isn't ours has neither of
+    fn header_flags(&mut self, ptr: *mut u8) -> (u32, bool) {
+        self.find_block(ptr).map_or((0, false), |block| {
+            // SAFETY: find_block only returns valid blocks.
+            let block = unsafe { block.as_ref() };
+            (block.tag, block.secure)
+        })
+    }
+
+    // queue the block at `ptr` up for flush_deferred, linked through its
+    // quarantine_next since it's still live
+    fn defer_free(&mut self, ptr: *mut u8, layout: Layout) {
+        let Some(mut block_ptr) = self.find_block(ptr) else {
+            return;
+        };
+        // SAFETY: find_block only returns valid blocks.
+        unsafe { block_ptr.as_mut() }.quarantine_next = self.deferred;
+        self.deferred = Some(block_ptr);
+        self.deferred_bytes += layout.size();
+    }
+
+    fn flush_deferred(&mut self) {
+        let mut frees = 0;
+        while let Some(block_ptr) = self.deferred {
+            // SAFETY: only live blocks are deferred; read the link before
+            // freeing the block reuses it.
+            let block = unsafe { block_ptr.as_ref() };
+            self.deferred = block.quarantine_next;
+            if unsafe { self.free_block(block.data) } {
+                frees += 1;
+            }
+        }
+        self.stats.live_bytes -= self.deferred_bytes;
+        self.stats.total_frees += frees;
+        self.deferred_bytes = 0;
+    }
+
+    // like deallocate, but leaves the stats alone; returns whether `ptr`
+    // belonged to this allocator
+    pub(crate) unsafe fn free_block(&mut self, ptr: *mut u8) -> bool {
+        let found = self.find_by_ptr(ptr);
+        if let Some((prev_ptr, mut block_ptr)) = found {
+            // SAFETY: find_by_ptr only returns blocks linked into the chain.
+            let block = unsafe { block_ptr.as_mut() };
+            self.retire(block);
+            if self.quarantine_budget == 0 {
+                self.release(prev_ptr, block_ptr);
+            } else {
+                self.quarantine(block_ptr);
+            }
+            true
         } else {
-            Ok(
-                unsafe {
-                    NonNull::slice_from_raw_parts(NonNull::new_unchecked(ptr), layout.size())
-                },
-            )
+            unsafe { self.unmap_region(ptr) }
         }
     }
 
-    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-        self.dealloc(ptr.as_ptr(), layout);
+    // Block::retire, keeping count of the secure blocks
+    fn retire(&mut self, block: &mut Block) {
+        self.secure_blocks -= usize::from(block.secure);
+        block.retire();
     }
-}
 
-struct AllocatorImpl {
-    head: Block,
-}
+    // give the region at `ptr` back to the source, if there is one
+    unsafe fn unmap_region(&mut self, ptr: *mut u8) -> bool {
+        let Some(region) = self.take_region(ptr) else {
+            return false;
+        };
+        // SAFETY: take_region only returns blocks at the start of a region.
+        let block = unsafe { region.as_ref() };
+        self.secure_blocks -= usize::from(block.secure);
+        let len = block.data as usize + block.size - region.as_ptr() as usize;
+        unsafe { self.source.unmap(region.cast(), len) };
+        true
+    }
 
-unsafe impl Send for Block {}
-unsafe impl Sync for Block {}
+    // like deallocate, for a block allocate_handle handed out, and without
+    // knowing the block in front of it; returns whether trim might now
+    // have something to give back
+    unsafe fn free_handle(&mut self, handle: Handle) -> bool {
+        let mut block_ptr = handle.block;
+        // SAFETY: the caller promises the handle's block is still live.
+        let block = unsafe { block_ptr.as_mut() };
+        block.check_magic();
+        #[cfg(feature = "debug-checks")]
+        {
+            self.check_same_owner(block);
+            block.check_layout(handle.layout);
+        }
+        let ptr = block.data;
+        let trimmable = if handle.mapped {
+            unsafe { self.unmap_region(ptr) };
+            false
+        } else {
+            self.retire(block);
+            if self.quarantine_budget == 0 {
+                self.release_forward(block_ptr);
+                // SAFETY: release_forward leaves the header where it was.
+                unsafe { block_ptr.as_ref() }.next.is_none()
+            } else {
+                // draining the quarantine may release any block, the last
+                // one included
+                self.quarantine(block_ptr);
+                true
+            }
+        };
+        self.stats.live_bytes = self.stats.live_bytes.saturating_sub(handle.layout.size());
+        self.stats.total_frees += 1;
+        #[cfg(feature = "record")]
+        self.recorder.record(TraceOp::Free, ptr, handle.layout.size(), handle.layout.align());
+        trimmable
+    }
 
-impl AllocatorImpl {
-    const BLOCK0: Block = Block {
-        data: NonNull::dangling().as_ptr(),
-        size: 0,
-        next: None,
-        free: false,
-    };
+    // park a freed block at the back of the quarantine
+    fn quarantine(&mut self, mut block_ptr: NonNull<Block>) {
+        // SAFETY: only blocks linked into the chain are quarantined.
+        let block = unsafe { block_ptr.as_mut() };
+        block.quarantined = true;
+        block.quarantine_next = None;
+        self.quarantine_bytes += block.size;
+        match self.quarantine_tail {
+            Some(mut tail) => unsafe { tail.as_mut() }.quarantine_next = Some(block_ptr),
+            None => self.quarantine_head = Some(block_ptr),
+        }
+        self.quarantine_tail = Some(block_ptr);
+        self.drain_quarantine();
+    }
 
-    pub const fn new() -> Self {
-        Self { head: Self::BLOCK0 }
+    // release the oldest quarantined blocks until we're back within budget
+    fn drain_quarantine(&mut self) {
+        while self.quarantine_bytes > self.quarantine_budget {
+            let Some(mut oldest_ptr) = self.quarantine_head else {
+                break;
+            };
+            let oldest = unsafe { oldest_ptr.as_mut() };
+            self.quarantine_head = oldest.quarantine_next;
+            if self.quarantine_head.is_none() {
+                self.quarantine_tail = None;
+            }
+            oldest.quarantined = false;
+            self.quarantine_bytes -= oldest.size;
+
+            let (prev_ptr, _) = self.find_by_ptr(oldest.data).unwrap();
+            self.release(prev_ptr, oldest_ptr);
+        }
     }
 
-    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
-        let previous_break = unsafe { sbrk(0) } as usize;
-        let block_addr = align_up(previous_break, align_of::<Block>());
-        let alloc_sz = align_up(block_addr + size_of::<Block>(), layout.align()) + layout.size();
+    // mark a block free and coalesce it with its free neighbours
+    fn release(&mut self, mut prev_ptr: NonNull<Block>, block_ptr: NonNull<Block>) {
+        self.release_forward(block_ptr);
+        if !self.coalesce {
+            return;
+        }
+        // SAFETY: both pointers come from find_by_ptr.
+        let block = unsafe { block_ptr.as_ref() };
 
-        let block = self
-            .head
-            .find_first_fit(alloc_sz);
-        if let Some(block) = block {
-            return block.data;
+        // and merge into the previous block if it's free as well
+        // SAFETY: prev_ptr is either the sentinel or a block in the chain.
+        let prev = unsafe { prev_ptr.as_mut() };
+        if prev.free && prev.is_adjacent_to(block_ptr) {
+            let (old_size, block_size) = (prev.size, block.size);
+            prev.absorb(block);
+            self.free_bytes += prev.size - old_size - block_size;
+            #[cfg(feature = "poison")]
+            prev.poison_gap(old_size, block_size);
+            if self.cursor == Some(block_ptr) {
+                self.cursor = Some(prev_ptr);
+            }
         }
+    }
 
-        let new_brk = unsafe { sbrk((alloc_sz - previous_break) as isize) };
-        if new_brk.is_null() {
-            return null_mut();
+    // mark a block free and merge the free blocks behind it into it
+    fn release_forward(&mut self, mut block_ptr: NonNull<Block>) {
+        // SAFETY: callers only pass blocks in the chain.
+        let block = unsafe { block_ptr.as_mut() };
+        if !block.free {
+            block.free = true;
+            self.used_bytes -= block.size;
+            self.free_bytes += block.size;
         }
+        if self.coalesce {
+            self.absorb_free_run(block_ptr);
+        }
+    }
 
-        let new_block_addr = align_up(new_brk as usize, align_of::<Block>());
-        let mut new_block = NonNull::new(new_block_addr as *mut Block).unwrap();
-        let data = align_up(new_block_addr + size_of::<Block>(), layout.align()) as *mut u8;
-        unsafe {
-            new_block.as_mut().size = layout.size();
-            new_block.as_mut().next = None;
-            new_block.as_mut().data = data;
-            new_block.as_mut().free = false;
+    // merge the free blocks right behind the free block at `block_ptr` into
+    // it, returning how many there were
+    fn absorb_free_run(&mut self, mut block_ptr: NonNull<Block>) -> usize {
+        // SAFETY: callers only pass blocks in the chain.
+        let block = unsafe { block_ptr.as_mut() };
+        let mut merges = 0;
+        while let Some(next) = block.next {
+            // SAFETY: block.next is a valid pointer to an instance of Block.
+            let next_block = unsafe { next.as_ref() };
+            next_block.check_magic();
+            if !next_block.free || !block.is_adjacent_to(next) {
+                break;
+            }
+            // the header in between becomes free space too
+            let (old_size, next_size) = (block.size, next_block.size);
+            block.absorb(next_block);
+            self.free_bytes += block.size - old_size - next_size;
+            #[cfg(feature = "poison")]
+            block.poison_gap(old_size, next_size);
+            if self.cursor == Some(next) {
+                self.cursor = Some(block_ptr);
+            }
+            merges += 1;
         }
-        self.head.insert(new_block);
+        merges
+    }
 
-        data
+    fn coalesce_all(&mut self) -> usize {
+        let mut merges = 0;
+        let mut current = self.head.next;
+        while let Some(block_ptr) = current {
+            // SAFETY: block_ptr is a block in the chain.
+            if unsafe { block_ptr.as_ref() }.free {
+                merges += self.absorb_free_run(block_ptr);
+            }
+            current = unsafe { block_ptr.as_ref() }.next;
+        }
+        merges
     }
 
-    pub unsafe fn deallocate(&mut self, ptr: *mut u8) {
-        let block = self.head.find_by_ptr(ptr);
-        if let Some(block) = block {
+    // slide every block in use down into the free block right in front of
+    // it, so the free space ends up at the back of the heap
+    fn compact(&mut self, relocate: &mut impl FnMut(*mut u8, *mut u8, usize)) {
+        let mut prev_ptr = NonNull::from(&mut self.head);
+        // SAFETY: prev_ptr and its successors are blocks in the chain.
+        while let Some(block_ptr) = unsafe { prev_ptr.as_ref() }.next {
+            let prev = unsafe { prev_ptr.as_ref() };
+            let block = unsafe { block_ptr.as_ref() };
+            let movable = !block.free && !block.quarantined;
+            prev_ptr = if movable && prev.free && prev.is_adjacent_to(block_ptr) {
+                unsafe { self.slide(prev_ptr, block_ptr, relocate) }
+            } else {
+                block_ptr
+            };
+        }
+
+        // the moves shuffled bytes between used and free blocks
+        let (mut used, mut free) = (0, 0);
+        let mut next = self.head.next;
+        while let Some(block) = next {
+            let block = unsafe { block.as_ref() };
             if block.free {
-                eprintln!("double free: {:?}", ptr);
-                abort();
+                free += block.size;
+            } else {
+                used += block.size;
             }
-            block.free = true;
+            next = block.next;
+        }
+        self.used_bytes = used;
+        self.free_bytes = free;
+        self.cursor = None;
+    }
+
+    // move the block at `block_ptr` into the free block `hole_ptr` in front
+    // of it, returning the free block left behind it, or the moved block if
+    // there's none
+    unsafe fn slide(
+        &mut self,
+        hole_ptr: NonNull<Block>,
+        block_ptr: NonNull<Block>,
+        relocate: &mut impl FnMut(*mut u8, *mut u8, usize),
+    ) -> NonNull<Block> {
+        // SAFETY: both blocks are in the chain; everything needed from the
+        // block is read before moving its data overwrites the header.
+        let block = unsafe { block_ptr.as_ref() };
+        let (magic, old_data, size, next) = (block.magic, block.data, block.size, block.next);
+        let (tag, secure) = (block.tag, block.secure);
+        let end = old_data as usize + size;
+        // we don't know what the block was allocated with, so keep all the
+        // alignment its data has
+        let align = (1 << (old_data as usize).trailing_zeros()).min(MAX_ALIGN);
+        let hole = hole_ptr.as_ptr().cast::<u8>();
+        let new_data = hole.with_addr(align_up(hole as usize + size_of::<Block>(), align));
+        if new_data >= old_data {
+            return block_ptr;
+        }
+
+        // SAFETY: the hole's header is still intact until it's overwritten
+        let lead = unsafe { hole_ptr.as_ref() }.lead;
+        unsafe { std::ptr::copy(old_data, new_data, size) };
+        // what's left up to where the block used to end is free now
+        let mut moved = Block {
+            magic,
+            data: new_data,
+            size: end - new_data as usize,
+            next,
+            free: false,
+            quarantined: false,
+            quarantine_next: None,
+            tag,
+            secure,
+            lead,
+        };
+        moved.split(size);
+        let rest = moved.next.filter(|&rest| Some(rest) != next);
+        unsafe { hole_ptr.as_ptr().write(moved) };
+        relocate(old_data, new_data, size);
+
+        let Some(rest) = rest else {
+            return hole_ptr;
+        };
+        if secure {
+            unsafe { rest.as_ref() }.zero();
+        }
+        #[cfg(feature = "poison")]
+        unsafe { rest.as_ref() }.poison();
+        self.absorb_free_run(rest);
+        rest
+    }
+
+    // hand the tail of the block at `ptr` back, keeping the first `new_size` bytes
+    fn shrink_in_place(&mut self, ptr: *mut u8, old_size: usize, new_size: usize) -> bool {
+        let Some(block_size) = self.block_size(new_size) else {
+            return false;
+        };
+        let Some((_, mut block_ptr)) = self.find_by_ptr(ptr) else {
+            return self.resize_region(ptr, old_size, new_size).is_some();
+        };
+        // SAFETY: find_by_ptr only returns blocks linked into the chain.
+        let block = unsafe { block_ptr.as_mut() };
+        if block.free || block.quarantined {
+            return false;
+        }
+        if block.secure {
+            // SAFETY: the tail is part of the block, and no longer wanted.
+            unsafe { block.data.add(new_size).write_bytes(0, block.size - new_size) };
+        }
+        let old_next = block.next;
+        self.split_used(block, block_size);
+        if let Some(rest) = block.next.filter(|&rest| Some(rest) != old_next) {
+            #[cfg(feature = "poison")]
+            unsafe { rest.as_ref() }.poison();
+            // the split off tail may well border another free block
+            self.release(block_ptr, rest);
+        }
+
+        self.stats.live_bytes = self.stats.live_bytes.saturating_sub(old_size) + new_size;
+        #[cfg(feature = "record")]
+        self.recorder.record(TraceOp::Shrink, ptr, new_size, 1);
+        true
+    }
+
+    fn is_tail(&mut self, ptr: *mut u8) -> bool {
+        let brk = self.source.current_break();
+        // SAFETY: find_by_ptr only returns blocks linked into the chain.
+        self.find_by_ptr(ptr).is_some_and(|(_, block)| unsafe { block.as_ref() }.ends_at(brk))
+    }
+
+    // returns how much of the block is usable now
+    fn try_extend(&mut self, ptr: *mut u8, old_size: usize, new_size: usize) -> Option<usize> {
+        let block_size = self.block_size(new_size)?;
+        let Some((_, mut block_ptr)) = self.find_by_ptr(ptr) else {
+            return self.resize_region(ptr, old_size, new_size);
+        };
+        // SAFETY: find_by_ptr only returns blocks linked into the chain.
+        let block = unsafe { block_ptr.as_mut() };
+        if block.free || block.quarantined {
+            return None;
+        }
 
-            // collect all consecutive free blocks
-            if let Some(ref mut final_block) = block.next {
-                let mut final_block = final_block.as_mut();
-                loop {
-                    match final_block.next {
-                        Some(ref mut next) => {
-                            block.next = next.as_ref().next;
-                            if unsafe { next.as_ref() }.free {
-                                final_block = next.as_mut();
-                            } else {
-                                break;
-                            }
+        if block.size < new_size {
+            match block.next {
+                Some(next) => {
+                    // SAFETY: block.next is a valid pointer to an instance of Block.
+                    let next_block = unsafe { next.as_ref() };
+                    next_block.check_magic();
+                    let combined = next_block.data as usize + next_block.size - block.data as usize;
+                    if !next_block.free || !block.is_adjacent_to(next) || combined < block_size {
+                        return None;
+                    }
+                    // the part of the neighbour being taken over must be untouched
+                    #[cfg(feature = "poison")]
+                    check_poison(
+                        next_block.data,
+                        (block.data as usize + block_size).saturating_sub(next_block.data as usize),
+                    );
+                    let (old_block_size, next_size) = (block.size, next_block.size);
+                    block.absorb(next_block);
+                    self.free_bytes -= next_size;
+                    self.used_bytes += block.size - old_block_size;
+                    // part of the gap may end up in a free tail again
+                    #[cfg(feature = "poison")]
+                    block.poison_gap(old_block_size, next_size);
+                    if self.cursor == Some(next) {
+                        self.cursor = Some(block_ptr);
+                    }
+                    self.split_used(block, block_size);
+                }
+                None => {
+                    let end = block.data as usize + block.size;
+                    if !block.ends_at(self.source.current_break()) {
+                        return None;
+                    }
+                    let increment = block_size - block.size;
+                    match self.grow(increment) {
+                        Some(old_brk) if old_brk.as_ptr() as usize == end => {
+                            self.used_bytes += block_size - block.size;
+                            block.size = block_size;
                         }
-                        None => break,
+                        Some(old_brk) => {
+                            // the break moved under us, so this isn't ours to take
+                            self.insert_free(old_brk.as_ptr(), increment);
+                            return None;
+                        }
+                        None => return None,
                     }
                 }
+            }
+        }
+
+        self.stats.live_bytes = self.stats.live_bytes.saturating_sub(old_size) + new_size;
+        self.stats.peak_bytes = self.stats.peak_bytes.max(self.stats.live_bytes);
+        #[cfg(feature = "record")]
+        self.recorder.record(TraceOp::Extend, ptr, new_size, 1);
+        Some(block.size)
+    }
+
+    // split a block that's in use, counting the tail split off as free
+    fn split_used(&mut self, block: &mut Block, size: usize) {
+        let old_size = block.size;
+        let old_next = block.next;
+        block.split(size);
+        if let Some(rest) = block.next.filter(|&rest| Some(rest) != old_next) {
+            self.used_bytes -= old_size - block.size;
+            // SAFETY: split just wrote the new block.
+            self.free_bytes += unsafe { rest.as_ref() }.size;
+        }
+    }
+
+    // give the free block at the end of the heap back to the source, as
+    // long as nothing else has moved the break past it
+    pub(crate) fn trim(&mut self) {
+        let mut prev_ptr = NonNull::from(&mut self.head);
+        // SAFETY: prev_ptr and its successors are blocks in the chain.
+        let Some(mut last_ptr) = (unsafe { prev_ptr.as_ref() }).next else {
+            return;
+        };
+        while let Some(next) = unsafe { last_ptr.as_ref() }.next {
+            prev_ptr = last_ptr;
+            last_ptr = next;
+        }
+
+        let last = unsafe { last_ptr.as_mut() };
+        let brk = self.source.current_break();
+        if !last.free || !last.ends_at(brk) {
+            return;
+        }
+        let addr = last_ptr.as_ptr() as usize;
+        let keep = if self.trim_floor > addr {
+            self.trim_floor.max(last.data as usize)
+        } else {
+            self.trim_floor.max(addr - last.lead as usize)
+        };
+        // the header may be gone once the source shrinks
+        let size = last.size;
+        if keep >= brk || brk - keep <= self.decommit_threshold || !self.source.shrink(brk - keep) {
+            return;
+        }
+        self.heap_bytes -= brk - keep;
+
+        if keep <= addr {
+            self.free_bytes -= size;
+            unsafe { prev_ptr.as_mut() }.next = None;
+            if self.cursor == Some(last_ptr) {
+                self.cursor = None;
+            }
+        } else {
+            self.free_bytes -= brk - keep;
+            last.size = keep - last.data as usize;
+        }
+    }
+
+    // size of the biggest free block in the chain
+    fn largest_free(&self) -> usize {
+        let mut largest = 0;
+        let mut next = self.head.next;
+        while let Some(block) = next {
+            // SAFETY: block.next is a valid pointer to an instance of Block.
+            let block = unsafe { block.as_ref() };
+            if block.free {
+                largest = largest.max(block.size);
+            }
+            next = block.next;
+        }
+        largest
+    }
+
+    fn block_counts(&self) -> (usize, usize) {
+        let (mut used, mut free) = (0, 0);
+        let mut next = self.head.next;
+        while let Some(block) = next {
+            // SAFETY: block.next is a valid pointer to an instance of Block.
+            let block = unsafe { block.as_ref() };
+            if block.free {
+                free += 1;
+            } else {
+                used += 1;
+            }
+            next = block.next;
+        }
+        (used, free)
+    }
+
+    // blocks handed out and not freed yet, regions included
+    fn live_blocks(&self) -> usize {
+        let mut live = 0;
+        for mut next in [self.head.next, self.regions] {
+            while let Some(block) = next {
+                // SAFETY: both lists only link valid blocks.
+                let block = unsafe { block.as_ref() };
+                live += usize::from(!block.free && !block.quarantined);
+                next = block.next;
+            }
+        }
+        live
+    }
+
+    fn bytes_by_tag(&self) -> HashMap<u32, usize> {
+        let mut bytes = HashMap::new();
+        for mut next in [self.head.next, self.regions] {
+            while let Some(block) = next {
+                // SAFETY: both lists only link valid blocks.
+                let block = unsafe { block.as_ref() };
+                if !block.free && !block.quarantined {
+                    *bytes.entry(block.tag).or_insert(0) += block.size;
+                }
+                next = block.next;
+            }
+        }
+        bytes
+    }
+
+    fn check_integrity(&self) -> Result<(), IntegrityError> {
+        let brk = self.source.current_break();
+        let mut prev: Option<&Block> = None;
+        let mut next = self.head.next;
+        while let Some(block_ptr) = next {
+            let addr = block_ptr.as_ptr() as usize;
+            if !addr.is_multiple_of(align_of::<Block>()) {
+                return Err(IntegrityError::MisalignedHeader(addr));
+            }
+            if let Some(prev) = prev {
+                let prev_addr = prev as *const Block as usize;
+                if addr <= prev_addr {
+                    return Err(IntegrityError::OutOfOrder(addr));
+                }
+                if addr < prev.data as usize + prev.size {
+                    return Err(IntegrityError::Overlap(addr));
+                }
+            }
+
+            if addr.saturating_add(size_of::<Block>()) > brk {
+                return Err(IntegrityError::PastBreak(addr));
+            }
+
+            // SAFETY: ordered, so we haven't been here before, and the
+            // header is aligned and below the break; whether it's a header
+            // is what magic says.
+            let block = unsafe { block_ptr.as_ref() };
+            if !Block::has_magic(block.magic) {
+                return Err(IntegrityError::Corrupted(addr));
+            }
+            let data = block.data as usize;
+            if data < addr + size_of::<Block>() || !data.is_multiple_of(align_of::<Block>()) {
+                return Err(IntegrityError::BadData(addr));
+            }
+            if self.coalesce && prev.is_some_and(|prev| prev.free && block.free && prev.is_adjacent_to(block_ptr)) {
+                return Err(IntegrityError::Uncoalesced(addr));
+            }
+            if data.checked_add(block.size).is_none_or(|end| end > brk) {
+                return Err(IntegrityError::PastBreak(addr));
+            }
+            prev = Some(block);
+            next = block.next;
+        }
+        Ok(())
+    }
+
+    unsafe fn recover(&mut self) -> RecoveryReport {
+        let mut report = RecoveryReport::default();
+        let Some(initial) = self.initial_break else {
+            return report;
+        };
+        let brk = self.source.current_break();
+        let mut tail = NonNull::from(&mut self.head);
+        // where the last recovered block, or the heap, ends
+        let start = initial.as_ptr();
+        let mut end = align_up(start as usize, align_of::<Block>());
+        let mut addr = end;
+        self.quarantine_head = None;
+        self.quarantine_tail = None;
+        self.quarantine_bytes = 0;
+        self.used_bytes = 0;
+        self.free_bytes = 0;
+        while addr + size_of::<Block>() <= brk {
+            let Some(mut block_ptr) = (unsafe { Block::intact_at(start.with_addr(addr), brk) }) else {
+                addr += align_of::<Block>();
+                continue;
+            };
+            // padding too small for a block of its own isn't lost
+            if addr - end >= size_of::<Block>() + Block::MIN_SPLIT {
+                report.lost_blocks += 1;
+                report.lost_bytes += addr - end;
+            }
+            report.recovered_blocks += 1;
+            // SAFETY: tail is the sentinel or a block recovered before.
+            let tail_block = unsafe { tail.as_mut() };
+            if tail_block.next != Some(block_ptr) {
+                report.relinked_blocks += 1;
+                tail_block.next = Some(block_ptr);
+            }
 
-                if final_block != block {
-                    block.size += (final_block.data as usize + final_block.size) - block.data as usize;
+            // SAFETY: intact_at checked every field holds a valid value.
+            let block = unsafe { block_ptr.as_mut() };
+            if block.free {
+                self.free_bytes += block.size;
+            } else {
+                self.used_bytes += block.size;
+            }
+            if block.quarantined {
+                block.quarantine_next = None;
+                self.quarantine_bytes += block.size;
+                match self.quarantine_tail {
+                    Some(mut last) => unsafe { last.as_mut() }.quarantine_next = Some(block_ptr),
+                    None => self.quarantine_head = Some(block_ptr),
                 }
+                self.quarantine_tail = Some(block_ptr);
             }
+            tail = block_ptr;
+            end = align_up(block.data as usize + block.size, align_of::<Block>());
+            addr = end;
+        }
+        unsafe { tail.as_mut() }.next = None;
+        if brk.saturating_sub(end) >= size_of::<Block>() + Block::MIN_SPLIT {
+            report.lost_blocks += 1;
+            report.lost_bytes += brk - end;
         }
+        self.cursor = None;
+        self.deferred = None;
+        self.deferred_bytes = 0;
+        report
     }
 
     pub fn dump_blocks(&self) {
@@ -155,37 +3225,345 @@ impl AllocatorImpl {
 
 #[derive(PartialOrd, PartialEq)]
 struct Block {
+    // first, so an overrun of the previous block's data hits it
+    magic: u64,
     data: *mut u8,
     size: usize,
     next: Option<NonNull<Block>>,
     free: bool,
+    quarantined: bool,
+    quarantine_next: Option<NonNull<Block>>,
+    // see Allocator::allocate_tagged, 0 for untagged blocks
+    tag: u32,
+    // see Allocator::allocate_secure
+    secure: bool,
+    // padding between where the break was and the header when the block
+    // was made, so trimming it away shrinks the break back to exactly there
+    lead: u8,
 }
 
 impl Block {
-    fn find_first_fit(&mut self, size: usize) -> Option<&Block> {
-        if self.size >= size && self.free {
-            Some(self)
-        } else {
-            match self.next {
-                // SAFETY: block.next is a valid pointer to an instance of Block.
-                Some(ref mut next) => unsafe { next.as_mut() }.find_first_fit(size),
-                None => None,
+    const MAGIC: u64 = 0x5354_5550_4944_424b;
+    // with debug-checks the low half of the magic is the allocator that
+    // made the block instead, to catch it being freed by another
+    #[cfg(feature = "debug-checks")]
+    const OWNER_BITS: u64 = u32::MAX as u64;
+    #[cfg(not(feature = "debug-checks"))]
+    const OWNER_BITS: u64 = 0;
+    // smallest leftover worth splitting off into its own free block
+    const MIN_SPLIT: usize = 16;
+
+    // the header at `ptr`, if it looks like one the allocator wrote for
+    // a block ending by `brk`. Safety: `ptr` must be aligned for a header
+    // and the header readable.
+    unsafe fn intact_at(ptr: *mut u8, brk: usize) -> Option<NonNull<Block>> {
+        let addr = ptr as usize;
+        let field = |offset: usize| ptr.wrapping_add(offset).cast_const();
+        // SAFETY: every field is read as plain bytes before the header as
+        // a whole is trusted, since garbage bools would be UB.
+        unsafe {
+            if !Self::has_magic(field(offset_of!(Block, magic)).cast::<u64>().read()) {
+                return None;
+            }
+            let data = field(offset_of!(Block, data)).cast::<usize>().read();
+            let size = field(offset_of!(Block, size)).cast::<usize>().read();
+            let free = field(offset_of!(Block, free)).read();
+            let quarantined = field(offset_of!(Block, quarantined)).read();
+            let lead = field(offset_of!(Block, lead)).read();
+            let header_end = addr + size_of::<Block>();
+            if data < header_end || data - header_end >= MAX_ALIGN || !data.is_multiple_of(align_of::<Block>()) {
+                return None;
+            }
+            if data.checked_add(size).is_none_or(|end| end > brk) || free > 1 || quarantined > 1 {
+                return None;
+            }
+            if (free == 1 && quarantined == 1) || lead as usize >= align_of::<Block>() {
+                return None;
             }
         }
+        NonNull::new(ptr.cast::<Block>())
     }
 
-    fn find_by_ptr(&mut self, ptr: *mut u8) -> Option<&mut Block> {
-        if self.data == ptr {
-            Some(self)
-        } else {
-            match self.next {
-                // SAFETY: block.next is a valid pointer to an instance of Block.
-                Some(ref mut next) => unsafe { next.as_mut() }.find_by_ptr(ptr),
-                None => None,
+    fn has_magic(magic: u64) -> bool {
+        magic & !Self::OWNER_BITS == Self::MAGIC & !Self::OWNER_BITS
+    }
+
+    #[cfg(feature = "debug-checks")]
+    fn owner(&self) -> u32 {
+        self.magic as u32
+    }
+
+    #[inline]
+    fn check_magic(&self) {
+        #[cfg(feature = "debug-checks")]
+        if !Self::has_magic(self.magic) {
+            fatal(format_args!("heap corruption detected at {:p}", self));
+        }
+    }
+
+    // make sure `layout` could have been what the block was allocated with
+    #[cfg(feature = "debug-checks")]
+    fn check_layout(&self, layout: Layout) {
+        let (ptr, size) = (self.data, self.size);
+        let align = 1 << (ptr as usize).trailing_zeros();
+        if layout.size() > size || layout.align() > align {
+            fatal(format_args!(
+                "dealloc layout mismatch at {ptr:?}: got size {} align {}, block has size {size} align {align}",
+                layout.size(),
+                layout.align()
+            ));
+        }
+    }
+
+    // catch pointers into the middle of a live allocation, which would
+    // otherwise go unnoticed since they match no block
+    #[cfg(feature = "debug-checks")]
+    fn check_interior(&self, ptr: *mut u8) {
+        let live = !self.free && !self.quarantined;
+        if live && self.data < ptr && (ptr as usize) < self.data as usize + self.size {
+            fatal(format_args!("freeing interior pointer, expected {:p}, got {:p}", self.data, ptr));
+        }
+    }
+
+    // make sure the data handed out stops short of the next block's header
+    #[cfg(feature = "debug-checks")]
+    fn check_overlap(&self) {
+        let Some(next) = self.next else {
+            return;
+        };
+        if self.data as usize + self.size > next.as_ptr() as usize {
+            fatal(format_args!("block data at {:p} overlaps the next block at {:p}", self.data, next));
+        }
+    }
+
+    // blocks are laid out back to back, so the next header starts at the
+    // first Block-aligned address after our data unless something else
+    // moved the break in between
+    fn is_adjacent_to(&self, next: NonNull<Block>) -> bool {
+        align_up(self.data as usize + self.size, align_of::<Block>()) == next.as_ptr() as usize
+    }
+
+    // all of the block's data, which is at least what it was allocated with
+    fn usable(&self) -> NonNull<[u8]> {
+        NonNull::slice_from_raw_parts(NonNull::new(self.data).unwrap(), self.size)
+    }
+
+    // shrink the block to `size` bytes, turning the rest into a free block
+    // if it's big enough to be useful
+    fn split(&mut self, size: usize) {
+        let end = self.data as usize + self.size;
+        let rest_addr = align_up(self.data as usize + size, align_of::<Block>());
+        let rest_data = rest_addr + size_of::<Block>();
+        if rest_data + Self::MIN_SPLIT > end {
+            return;
+        }
+
+        let rest = NonNull::new(self.data.with_addr(rest_addr).cast::<Block>()).unwrap();
+        unsafe {
+            rest.as_ptr().write(Block {
+                magic: self.magic,
+                data: self.data.with_addr(rest_data),
+                size: end - rest_data,
+                next: self.next,
+                free: true,
+                quarantined: false,
+                quarantine_next: None,
+                tag: 0,
+                secure: false,
+                lead: 0,
+            });
+        }
+        self.size = size;
+        self.next = Some(rest);
+    }
+
+    // merge the physically following block into this one
+    fn absorb(&mut self, next: &Block) {
+        // anything between us and next that isn't header alignment belongs
+        // to someone else, and merging it would hand it out
+        #[cfg(feature = "debug-checks")]
+        if !self.is_adjacent_to(NonNull::from(next)) {
+            let (block, next) = (self as *const Block, next as *const Block);
+            fatal(format_args!("heap corruption detected: merging {block:p} with {next:p}, which doesn't follow it"));
+        }
+        self.size = next.data as usize + next.size - self.data as usize;
+        self.next = next.next;
+    }
+
+    // poison what used to be the header and padding between this block's
+    // first `old_size` bytes and the `next_size` it absorbed, which
+    // overwrites the absorbed header
+    #[cfg(feature = "poison")]
+    fn poison_gap(&self, old_size: usize, next_size: usize) {
+        unsafe { self.data.add(old_size).write_bytes(POISON, self.size - old_size - next_size) };
+    }
+
+    fn ends_at(&self, addr: usize) -> bool {
+        self.data as usize + self.size == addr
+    }
+
+    // get a block that's being freed ready to be reused, dropping its flags
+    fn retire(&mut self) {
+        if self.free || self.quarantined {
+            fatal(format_args!("double free: {:?}", self.data));
+        }
+        self.tag = 0;
+        if self.secure {
+            self.zero();
+            self.secure = false;
+        }
+        #[cfg(feature = "poison")]
+        self.poison();
+    }
+
+    // wipe all of the block's data
+    fn zero(&self) {
+        unsafe { self.data.write_bytes(0, self.size) };
+    }
+
+    #[cfg(feature = "poison")]
+    fn poison(&self) {
+        unsafe { self.data.write_bytes(POISON, self.size) };
+    }
+
+    fn info(&self, addr: NonNull<Block>) -> BlockInfo {
+        BlockInfo {
+            addr: addr.as_ptr() as usize,
+            data: self.data as usize,
+            size: self.size,
+            free: self.free,
+        }
+    }
+
+    fn fits(&self, layout: Layout) -> bool {
+        let end = self.data as usize + self.size;
+        self.free
+            && self
+                .aligned_data(layout.align())
+                .and_then(|data| data.checked_add(layout.size()))
+                .is_some_and(|data_end| data_end <= end)
+    }
+
+    // where data aligned to `align` can start in this block: right away if
+    // ours already is, or else past a free block split off the front, with
+    // a header of its own right in front of the data
+    fn aligned_data(&self, align: usize) -> Option<usize> {
+        let data = self.data as usize;
+        if data.is_multiple_of(align) {
+            return Some(data);
+        }
+        checked_align_up(data + Self::MIN_SPLIT + size_of::<Block>(), align)
+    }
+
+    // for a free block whose data isn't aligned to `align`, split what's in
+    // front of aligned data off, returning the free block behind it, which
+    // starts right at the aligned data
+    fn split_front(&mut self, align: usize) -> NonNull<Block> {
+        let data = self.aligned_data(align).unwrap();
+        let end = self.data as usize + self.size;
+        let header = data - size_of::<Block>();
+        let back = NonNull::new(self.data.with_addr(header).cast::<Block>()).unwrap();
+        unsafe {
+            back.as_ptr().write(Block {
+                magic: self.magic,
+                data: self.data.with_addr(data),
+                size: end - data,
+                next: self.next,
+                free: true,
+                quarantined: false,
+                quarantine_next: None,
+                tag: 0,
+                secure: false,
+                lead: 0,
+            });
+        }
+        self.size = header - self.data as usize;
+        self.next = Some(back);
+        back
+    }
+
+    // the find_*_fit functions give up once they've looked at `budget`
+    // free blocks, taking what they look at off it
+    fn find_first_fit(&mut self, layout: Layout, budget: &mut usize) -> Option<NonNull<Block>> {
+        let mut current = NonNull::from(self);
+        loop {
+            // SAFETY: block.next is a valid pointer to an instance of Block.
+            let block = unsafe { current.as_ref() };
+            block.check_magic();
+            if block.free {
+                *budget = budget.checked_sub(1)?;
+            }
+            if block.fits(layout) {
+                return Some(current);
+            }
+            current = block.next?;
+        }
+    }
+
+    fn find_first_fit_until(
+        &mut self,
+        layout: Layout,
+        end: NonNull<Block>,
+        budget: &mut usize,
+    ) -> Option<NonNull<Block>> {
+        let mut current = NonNull::from(self);
+        while current != end {
+            // SAFETY: block.next is a valid pointer to an instance of Block.
+            let block = unsafe { current.as_ref() };
+            block.check_magic();
+            if block.free {
+                *budget = budget.checked_sub(1)?;
+            }
+            if block.fits(layout) {
+                return Some(current);
+            }
+            current = block.next?;
+        }
+        None
+    }
+
+    fn find_best_fit(&mut self, layout: Layout, budget: &mut usize) -> Option<NonNull<Block>> {
+        let mut best: Option<NonNull<Block>> = None;
+        let mut current = NonNull::from(self);
+        loop {
+            // SAFETY: block.next is a valid pointer to an instance of Block.
+            let block = unsafe { current.as_ref() };
+            block.check_magic();
+            if block.free {
+                let Some(left) = budget.checked_sub(1) else {
+                    return best;
+                };
+                *budget = left;
+            }
+            if block.fits(layout)
+                && best.is_none_or(|best| unsafe { best.as_ref() }.size > block.size)
+            {
+                best = Some(current);
+            }
+            match block.next {
+                Some(next) => current = next,
+                None => return best,
             }
         }
     }
 
+    // returns the block owning `ptr` together with its predecessor
+    fn find_by_ptr(&mut self, ptr: *mut u8) -> Option<(NonNull<Block>, NonNull<Block>)> {
+        let mut prev = NonNull::from(self);
+        loop {
+            // SAFETY: block.next is a valid pointer to an instance of Block.
+            let current = unsafe { prev.as_ref() }.next?;
+            let block = unsafe { current.as_ref() };
+            block.check_magic();
+            if block.data == ptr {
+                return Some((prev, current));
+            }
+            #[cfg(feature = "debug-checks")]
+            block.check_interior(ptr);
+            prev = current;
+        }
+    }
+
     fn insert(&mut self, block: NonNull<Block>) {
         match self.next {
             // SAFETY: block.next is a valid pointer to an instance of Block.
@@ -195,7 +3573,64 @@ impl Block {
     }
 }
 
+fn warn_large(size: usize) {
+    eprintln!("allocating {size} bytes, more than the warn threshold");
+    #[cfg(feature = "trace")]
+    crate::trace::print_backtrace();
+}
+
+fn foreign_free(policy: FreePolicy, ptr: *mut u8) {
+    match policy {
+        FreePolicy::Ignore => {}
+        FreePolicy::Warn => eprintln!("freeing {ptr:?}, which wasn't allocated here"),
+        FreePolicy::Abort => fatal(format_args!("freeing {ptr:?}, which wasn't allocated here")),
+    }
+}
+
+// print `args` and abort, for errors found with the lock held. Printing
+// normally may allocate, which would deadlock on the lock if we're the
+// global allocator, so the message is formatted into a buffer on the stack
+// and written straight to stderr's file descriptor, cut short if it's long.
+#[cold]
+fn fatal(args: fmt::Arguments<'_>) -> ! {
+    struct Message {
+        bytes: [u8; 256],
+        len: usize,
+    }
+
+    impl Write for Message {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            // the last byte is kept for the newline
+            let len = s.len().min(self.bytes.len() - 1 - self.len);
+            self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+            self.len += len;
+            Ok(())
+        }
+    }
+
+    let mut message = Message { bytes: [0; 256], len: 0 };
+    let _ = message.write_fmt(args);
+    message.bytes[message.len] = b'\n';
+    unsafe { write(STDERR_FILENO, message.bytes.as_ptr().cast(), message.len + 1) };
+    abort();
+}
+
+// abort if anything wrote to the `len` bytes of free memory at `data`
+#[cfg(feature = "poison")]
+fn check_poison(data: *const u8, len: usize) {
+    // SAFETY: callers only pass the data of free blocks.
+    let bytes = unsafe { std::slice::from_raw_parts(data, len) };
+    if let Some(offset) = bytes.iter().position(|&byte| byte != POISON) {
+        fatal(format_args!("use after free detected: freed memory at {:p} was written to", data.wrapping_add(offset)));
+    }
+}
+
 fn align_up(addr: usize, align: usize) -> usize {
     assert!(align.is_power_of_two());
     (addr + align - 1) & !(align - 1)
 }
+
+fn checked_align_up(addr: usize, align: usize) -> Option<usize> {
+    assert!(align.is_power_of_two());
+    Some(addr.checked_add(align - 1)? & !(align - 1))
+}
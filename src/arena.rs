@@ -0,0 +1,172 @@
+use crate::allocator::{AllocStats, Allocator, BlockInfo, FitStrategy};
+use crate::source::MemorySource;
+
+use std::alloc::{AllocError, Allocator as AllocatorTrait, GlobalAlloc, Layout};
+use std::cell::UnsafeCell;
+use std::ptr::{null_mut, NonNull};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// An allocator that never asks the OS for memory, and instead hands out
+/// pieces of an `N` byte array it carries around.
+///
+/// The array is only bound to the allocator on its first allocation, so it
+/// must not be moved after that. Putting it in a `static` takes care of it.
+pub struct ArenaAllocator<const N: usize> {
+    buf: UnsafeCell<[u8; N]>,
+    allocator: Allocator<ArenaSource>,
+    attached: AtomicBool,
+}
+
+// SAFETY: buf is only ever touched through the allocator, which locks.
+unsafe impl<const N: usize> Sync for ArenaAllocator<N> {}
+
+impl<const N: usize> ArenaAllocator<N> {
+    pub const fn new() -> Self {
+        Self::with_strategy(FitStrategy::FirstFit)
+    }
+
+    pub const fn with_strategy(strategy: FitStrategy) -> Self {
+        Self {
+            buf: UnsafeCell::new([0; N]),
+            allocator: Allocator::with_source(ArenaSource::EMPTY, strategy),
+            attached: AtomicBool::new(false),
+        }
+    }
+
+    pub fn stats(&self) -> AllocStats {
+        self.allocator.stats()
+    }
+
+    pub fn blocks(&self) -> Vec<BlockInfo> {
+        self.allocator.blocks()
+    }
+
+    /// See `Allocator::allocate_raw`.
+    pub fn allocate_raw(&self, layout: Layout) -> Option<NonNull<u8>> {
+        self.allocator().allocate_raw(layout)
+    }
+
+    // point the source at buf, now that it has an address to stay at
+    fn allocator(&self) -> &Allocator<ArenaSource> {
+        if !self.attached.load(Ordering::Acquire) {
+            self.allocator.with_source_mut(|source| {
+                if source.base.is_null() {
+                    source.base = self.buf.get().cast();
+                    source.capacity = N;
+                }
+            });
+            self.attached.store(true, Ordering::Release);
+        }
+        &self.allocator
+    }
+}
+
+impl<const N: usize> Default for ArenaAllocator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for ArenaAllocator<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.allocate_raw(layout).map_or(null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.allocator().alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.allocator().dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        unsafe { self.allocator().realloc(ptr, layout, new_size) }
+    }
+}
+
+unsafe impl<const N: usize> AllocatorTrait for ArenaAllocator<N> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocator().allocate(layout)
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocator().allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { self.allocator().deallocate(ptr, layout) }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { self.allocator().grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { self.allocator().grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { self.allocator().shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+/// The part of an `ArenaAllocator`'s array in use so far, as a break.
+pub struct ArenaSource {
+    base: *mut u8,
+    capacity: usize,
+    len: usize,
+}
+
+unsafe impl Send for ArenaSource {}
+
+impl ArenaSource {
+    const EMPTY: ArenaSource = ArenaSource {
+        base: null_mut(),
+        capacity: 0,
+        len: 0,
+    };
+}
+
+unsafe impl MemorySource for ArenaSource {
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+        if increment > self.capacity - self.len {
+            return None;
+        }
+        let old_break = self.base.wrapping_add(self.len);
+        self.len += increment;
+        NonNull::new(old_break)
+    }
+
+    fn current_break(&self) -> usize {
+        self.base as usize + self.len
+    }
+
+    // the array starts out zeroed
+    fn zeroed(&self) -> bool {
+        true
+    }
+
+    fn shrink(&mut self, decrement: usize) -> bool {
+        if decrement > self.len {
+            return false;
+        }
+        self.len -= decrement;
+        true
+    }
+}
@@ -0,0 +1,141 @@
+//! A wrapper that hands allocations the primary allocator can't serve to a
+//! secondary one.
+
+use crate::allocator::Allocator;
+use crate::source::MemorySource;
+
+use std::alloc::{AllocError, Allocator as AllocatorTrait, GlobalAlloc, Layout};
+use std::ptr::{copy_nonoverlapping, NonNull};
+
+/// Allocators that can tell their own allocations from anyone else's.
+pub trait Owns {
+    /// Whether `ptr` is the start of an allocation that came from here.
+    fn owns(&self, ptr: *mut u8) -> bool;
+}
+
+impl<S: MemorySource> Owns for Allocator<S> {
+    fn owns(&self, ptr: *mut u8) -> bool {
+        Allocator::owns(self, ptr)
+    }
+}
+
+/// Tries `primary` first and `secondary` when it fails, e.g. this crate's
+/// allocator in front of `System` for whatever its heap can't hold. Frees
+/// go to whichever of the two `primary.owns` says the allocation came from,
+/// and allocations stay with it when they're resized, unless the primary
+/// runs out while growing one.
+pub struct FallbackAllocator<P, S> {
+    primary: P,
+    secondary: S,
+}
+
+impl<P, S> FallbackAllocator<P, S> {
+    pub const fn new(primary: P, secondary: S) -> Self {
+        Self { primary, secondary }
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+}
+
+unsafe impl<P: GlobalAlloc + Owns, S: GlobalAlloc> GlobalAlloc for FallbackAllocator<P, S> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.primary.alloc(layout) };
+        if !ptr.is_null() {
+            return ptr;
+        }
+        unsafe { self.secondary.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.primary.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            return ptr;
+        }
+        unsafe { self.secondary.alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self.primary.owns(ptr) {
+            unsafe { self.primary.dealloc(ptr, layout) }
+        } else {
+            unsafe { self.secondary.dealloc(ptr, layout) }
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !self.primary.owns(ptr) {
+            return unsafe { self.secondary.realloc(ptr, layout, new_size) };
+        }
+        let new_ptr = unsafe { self.primary.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            return new_ptr;
+        }
+        // SAFETY: the caller promises new_size is valid for the alignment.
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let new_ptr = unsafe { self.secondary.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.primary.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
+
+unsafe impl<P: AllocatorTrait + Owns, S: AllocatorTrait> AllocatorTrait for FallbackAllocator<P, S> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.primary.allocate(layout).or_else(|_| self.secondary.allocate(layout))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.primary.allocate_zeroed(layout).or_else(|_| self.secondary.allocate_zeroed(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.primary.owns(ptr.as_ptr()) {
+            unsafe { self.primary.deallocate(ptr, layout) }
+        } else {
+            unsafe { self.secondary.deallocate(ptr, layout) }
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.primary.owns(ptr.as_ptr()) {
+            return unsafe { self.secondary.grow(ptr, old_layout, new_layout) };
+        }
+        if let Ok(new) = unsafe { self.primary.grow(ptr, old_layout, new_layout) } {
+            return Ok(new);
+        }
+        let new = self.secondary.allocate(new_layout)?;
+        unsafe {
+            copy_nonoverlapping(ptr.as_ptr(), new.cast::<u8>().as_ptr(), old_layout.size());
+            self.primary.deallocate(ptr, old_layout);
+        }
+        Ok(new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if self.primary.owns(ptr.as_ptr()) {
+            unsafe { self.primary.shrink(ptr, old_layout, new_layout) }
+        } else {
+            unsafe { self.secondary.shrink(ptr, old_layout, new_layout) }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! A wrapper that puts guard bytes around every allocation.
+
+use std::alloc::{AllocError, Allocator, Layout};
+use std::process::abort;
+use std::ptr::NonNull;
+
+/// Bytes of guard after each allocation, and at least as many before it.
+pub const GUARD_SIZE: usize = 16;
+
+/// What the guards are filled with.
+pub const GUARD_BYTE: u8 = 0xfd;
+
+/// Wraps any `Allocator`, surrounding each allocation with guard bytes that
+/// are checked when it's freed, so writing past either end of it aborts
+/// with the address that was overwritten.
+pub struct DebugAllocator<A> {
+    inner: A,
+}
+
+impl<A> DebugAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+// the front guard is padded so the payload keeps the layout's alignment
+fn front_guard(layout: Layout) -> usize {
+    GUARD_SIZE.next_multiple_of(layout.align())
+}
+
+// the allocation asked of the inner allocator, guards included
+fn outer_layout(layout: Layout) -> Result<Layout, AllocError> {
+    let size = front_guard(layout)
+        .checked_add(layout.size())
+        .and_then(|size| size.checked_add(GUARD_SIZE))
+        .ok_or(AllocError)?;
+    Layout::from_size_align(size, layout.align()).map_err(|_| AllocError)
+}
+
+fn check_guard(start: *const u8, len: usize, what: &str, ptr: NonNull<u8>) {
+    // SAFETY: callers only pass guards of live allocations.
+    let guard = unsafe { std::slice::from_raw_parts(start, len) };
+    if let Some(offset) = guard.iter().position(|&byte| byte != GUARD_BYTE) {
+        eprintln!("heap {what} detected at {:p}, allocation at {ptr:p}", start.wrapping_add(offset));
+        abort();
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for DebugAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let front = front_guard(layout);
+        let base = self.inner.allocate(outer_layout(layout)?)?.cast::<u8>();
+        unsafe {
+            base.write_bytes(GUARD_BYTE, front);
+            let ptr = base.add(front);
+            ptr.add(layout.size()).write_bytes(GUARD_BYTE, GUARD_SIZE);
+            // the back guard starts right at the end, so there's no slack
+            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let front = front_guard(layout);
+        let base = unsafe { ptr.sub(front) };
+        check_guard(base.as_ptr(), front, "underflow", ptr);
+        check_guard(unsafe { ptr.add(layout.size()) }.as_ptr(), GUARD_SIZE, "overflow", ptr);
+        // SAFETY: allocate succeeded with this layout, so it's valid.
+        let outer = unsafe { outer_layout(layout).unwrap_unchecked() };
+        unsafe { self.inner.deallocate(base, outer) };
+    }
+}
@@ -1,6 +1,12 @@
 #![feature(allocator_api)]
-#![feature(alloc_layout_extra)]
-#![feature(pointer_is_aligned)]
-#![feature(pointer_byte_offsets)]
 
 pub mod allocator;
+pub mod arena;
+pub mod fallback;
+pub mod guard;
+#[cfg(feature = "record")]
+pub mod record;
+pub mod source;
+mod thread_cache;
+#[cfg(feature = "trace")]
+mod trace;
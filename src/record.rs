@@ -0,0 +1,140 @@
+//! A log of what the heap was asked to do, for the `record` feature, and a
+//! way to play it back.
+
+use crate::allocator::{Allocator, FitStrategy};
+use crate::source::MockSource;
+
+use std::alloc::{Allocator as AllocatorTrait, Layout};
+use std::collections::BTreeMap;
+use std::ptr::NonNull;
+
+/// How many events an allocator keeps. Older ones are overwritten.
+pub const RECORD_CAPACITY: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceOp {
+    Alloc,
+    Free,
+    /// Grown in place, see `Allocator::try_extend`.
+    Extend,
+    /// Shrunk in place.
+    Shrink,
+}
+
+/// One operation on the heap. For `Extend` and `Shrink`, `size` is the new
+/// size and `align` is always 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub op: TraceOp,
+    pub size: usize,
+    pub align: usize,
+    /// Where the data is, relative to the break the heap started at.
+    pub offset: usize,
+}
+
+impl TraceEvent {
+    const EMPTY: TraceEvent = TraceEvent {
+        op: TraceOp::Alloc,
+        size: 0,
+        align: 1,
+        offset: 0,
+    };
+}
+
+// Lives in the allocator's state, so events are logged in the order the
+// heap saw them, into a buffer that needs no allocating.
+pub(crate) struct Recorder {
+    events: [TraceEvent; RECORD_CAPACITY],
+    // events recorded so far, including overwritten ones
+    len: usize,
+    origin: Option<usize>,
+}
+
+impl Recorder {
+    pub(crate) const fn new() -> Self {
+        Self {
+            events: [TraceEvent::EMPTY; RECORD_CAPACITY],
+            len: 0,
+            origin: None,
+        }
+    }
+
+    // called with the old break every time the heap grows
+    pub(crate) fn grew(&mut self, old_break: usize) {
+        self.origin.get_or_insert(old_break);
+    }
+
+    // every slot in the buffer, and how many events have been recorded
+    pub(crate) fn events(&self) -> ([TraceEvent; RECORD_CAPACITY], usize) {
+        (self.events, self.len)
+    }
+
+    pub(crate) fn record(&mut self, op: TraceOp, ptr: *mut u8, size: usize, align: usize) {
+        let offset = (ptr as usize).wrapping_sub(self.origin.unwrap_or_default());
+        self.events[self.len % RECORD_CAPACITY] = TraceEvent {
+            op,
+            size,
+            align,
+            offset,
+        };
+        self.len += 1;
+    }
+}
+
+/// Runs `trace` against a fresh first-fit allocator on a `MockSource`,
+/// panicking as soon as a block lands somewhere other than it did when it
+/// was recorded.
+///
+/// A trace only replays exactly if it covers the heap from the start, the
+/// recording allocator was first-fit with the default settings, nothing
+/// else moved its break, and the break started page aligned, like a
+/// `MockSource`'s does.
+pub fn replay(trace: &[TraceEvent]) {
+    let heap_end = trace
+        .iter()
+        .map(|event| event.offset.saturating_add(event.size).saturating_add(event.align))
+        .max()
+        .unwrap_or(0);
+    let source = MockSource::new(heap_end.saturating_mul(2).max(1 << 16));
+    let base = source.base();
+    let base_ptr = source.base_ptr();
+    let allocator = Allocator::with_source(source, FitStrategy::FirstFit);
+
+    let mut live = BTreeMap::new();
+    for (i, event) in trace.iter().enumerate() {
+        let layout = Layout::from_size_align(event.size, event.align)
+            .unwrap_or_else(|_| panic!("event {i} has a bad layout: {event:?}"));
+        let ptr = base_ptr.as_ptr().wrapping_add(event.offset);
+        match event.op {
+            TraceOp::Alloc => {
+                let got = allocator
+                    .allocate(layout)
+                    .unwrap_or_else(|_| panic!("event {i} failed to allocate: {event:?}"));
+                let got = got.cast::<u8>().as_ptr() as usize - base;
+                assert_eq!(got, event.offset, "event {i} allocated at a different offset: {event:?}");
+                live.insert(event.offset, layout);
+            }
+            TraceOp::Free => {
+                live.remove(&event.offset);
+                unsafe { allocator.deallocate(NonNull::new(ptr).unwrap(), layout) };
+            }
+            TraceOp::Extend => {
+                let old = *live
+                    .get(&event.offset)
+                    .unwrap_or_else(|| panic!("event {i} extends a block that isn't live: {event:?}"));
+                assert!(allocator.try_extend(ptr, old, event.size), "event {i} failed to extend: {event:?}");
+                live.insert(event.offset, Layout::from_size_align(event.size, old.align()).unwrap());
+            }
+            TraceOp::Shrink => {
+                let old = *live
+                    .get(&event.offset)
+                    .unwrap_or_else(|| panic!("event {i} shrinks a block that isn't live: {event:?}"));
+                let new = Layout::from_size_align(event.size, old.align()).unwrap();
+                let got = unsafe { allocator.shrink(NonNull::new(ptr).unwrap(), old, new) }
+                    .unwrap_or_else(|_| panic!("event {i} failed to shrink: {event:?}"));
+                assert_eq!(got.cast::<u8>().as_ptr(), ptr, "event {i} moved the block: {event:?}");
+                live.insert(event.offset, new);
+            }
+        }
+    }
+}
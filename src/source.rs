@@ -0,0 +1,318 @@
+use nix::libc::{mmap, munmap, sbrk, MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ptr::{null_mut, NonNull};
+
+/// Where the allocator gets its memory from.
+///
+/// A source behaves like a program break: it hands out one contiguous
+/// region that only ever grows at its end.
+///
+/// # Safety
+///
+/// Memory returned by `grow` must stay valid and unused by anyone else for
+/// as long as the source is alive.
+pub unsafe trait MemorySource {
+    /// Extends the heap by `increment` bytes, returning the old end of the heap.
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>>;
+
+    /// Returns the current end of the heap.
+    fn current_break(&self) -> usize;
+
+    /// Whether memory from `grow` is all zeroes the first time the heap
+    /// grows over it. Memory given back with `shrink` and grown over again
+    /// doesn't count, since a partly given back page keeps whatever was
+    /// written to it.
+    fn zeroed(&self) -> bool {
+        false
+    }
+
+    /// Gives back the last `decrement` bytes of the heap, returning whether
+    /// the source took them.
+    fn shrink(&mut self, _decrement: usize) -> bool {
+        false
+    }
+
+    /// Whether the heap outlives the source, like the process break or a
+    /// static buffer do, so another allocator can grow over it later.
+    fn shared(&self) -> bool {
+        false
+    }
+
+    /// Maps a standalone region of `len` bytes outside the heap, used when
+    /// `grow` fails. The region must be page aligned.
+    fn map(&mut self, _len: usize) -> Option<NonNull<u8>> {
+        None
+    }
+
+    /// Whether memory from `map` is all zeroes.
+    fn map_zeroed(&self) -> bool {
+        false
+    }
+
+    /// Gives back a region returned by `map`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must describe a region previously returned by `map`.
+    unsafe fn unmap(&mut self, _ptr: NonNull<u8>, _len: usize) {}
+}
+
+/// The process break, grown with `sbrk`.
+pub struct SbrkSource;
+
+unsafe impl MemorySource for SbrkSource {
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+        // anything past isize::MAX would come out negative and shrink the break
+        let increment = isize::try_from(increment).ok()?;
+        let old_break = unsafe { sbrk(increment) };
+        if old_break as isize == -1 {
+            return None;
+        }
+        NonNull::new(old_break.cast())
+    }
+
+    fn current_break(&self) -> usize {
+        unsafe { sbrk(0) as usize }
+    }
+
+    fn shared(&self) -> bool {
+        true
+    }
+
+    // not zeroed: malloc and any other allocator in the process move the
+    // same break, and can give back part of a page they wrote to before
+    // this heap ever reached it
+    fn zeroed(&self) -> bool {
+        false
+    }
+
+    fn shrink(&mut self, decrement: usize) -> bool {
+        let Ok(decrement) = isize::try_from(decrement) else {
+            return false;
+        };
+        unsafe { sbrk(-decrement) as isize != -1 }
+    }
+
+    fn map(&mut self, len: usize) -> Option<NonNull<u8>> {
+        let region = unsafe {
+            mmap(
+                null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if region == MAP_FAILED {
+            return None;
+        }
+        NonNull::new(region.cast())
+    }
+
+    // anonymous mappings are private to this heap, so the kernel's zeroed
+    // pages can be trusted
+    fn map_zeroed(&self) -> bool {
+        true
+    }
+
+    unsafe fn unmap(&mut self, ptr: NonNull<u8>, len: usize) {
+        unsafe { munmap(ptr.as_ptr() as *mut _, len) };
+    }
+}
+
+/// A fixed-size buffer pretending to be the program break.
+///
+/// Useful for tests, since it doesn't share the break with the rest of the
+/// process and runs out of memory at a known point.
+pub struct MockSource {
+    base: NonNull<u8>,
+    capacity: usize,
+    len: usize,
+    grows: usize,
+}
+
+unsafe impl Send for MockSource {}
+
+impl MockSource {
+    const ALIGN: usize = 4096;
+
+    pub fn new(capacity: usize) -> Self {
+        let layout = Layout::from_size_align(capacity.max(1), Self::ALIGN).unwrap();
+        // the buffer comes from the system allocator so a mock heap works
+        // even when the allocator under test is the global one
+        let base = NonNull::new(unsafe { System.alloc_zeroed(layout) }).expect("failed to allocate mock heap");
+        Self {
+            base,
+            capacity,
+            len: 0,
+            grows: 0,
+        }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base.as_ptr() as usize
+    }
+
+    /// The start of the buffer, for turning offsets into the heap back
+    /// into pointers without losing track of where they came from.
+    pub fn base_ptr(&self) -> NonNull<u8> {
+        self.base
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of successful calls to `grow`.
+    pub fn grows(&self) -> usize {
+        self.grows
+    }
+}
+
+unsafe impl MemorySource for MockSource {
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+        if increment > self.capacity - self.len {
+            return None;
+        }
+        let old_break = unsafe { self.base.add(self.len) };
+        self.len += increment;
+        self.grows += 1;
+        Some(old_break)
+    }
+
+    fn current_break(&self) -> usize {
+        self.base() + self.len
+    }
+
+    fn zeroed(&self) -> bool {
+        true
+    }
+
+    fn shrink(&mut self, decrement: usize) -> bool {
+        if decrement > self.len {
+            return false;
+        }
+        self.len -= decrement;
+        true
+    }
+}
+
+impl Drop for MockSource {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.capacity.max(1), Self::ALIGN).unwrap();
+        unsafe { System.dealloc(self.base.as_ptr(), layout) }
+    }
+}
+
+/// A buffer handed over for good, for when there's no OS to get memory
+/// from at all. The heap never grows past it.
+pub struct StaticSource {
+    base: NonNull<u8>,
+    capacity: usize,
+    len: usize,
+}
+
+unsafe impl Send for StaticSource {}
+
+impl StaticSource {
+    pub const fn new(buf: &'static mut [u8]) -> Self {
+        let capacity = buf.len();
+        // SAFETY: a slice's pointer is never null.
+        let base = unsafe { NonNull::new_unchecked(buf.as_mut_ptr()) };
+        Self { base, capacity, len: 0 }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+unsafe impl MemorySource for StaticSource {
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+        if increment > self.capacity - self.len {
+            return None;
+        }
+        let old_break = unsafe { self.base.add(self.len) };
+        self.len += increment;
+        Some(old_break)
+    }
+
+    fn current_break(&self) -> usize {
+        self.base.as_ptr() as usize + self.len
+    }
+
+    fn shared(&self) -> bool {
+        true
+    }
+
+    fn shrink(&mut self, decrement: usize) -> bool {
+        if decrement > self.len {
+            return false;
+        }
+        self.len -= decrement;
+        true
+    }
+}
+
+/// Wraps another source and fails every `grow` after the first `grows`,
+/// like `sbrk` running out, so out of memory paths can be tested without
+/// actually exhausting anything. `map` is passed through, so with
+/// `SbrkSource` allocations fall back to mapped regions as they would for
+/// real.
+#[cfg(feature = "test-util")]
+pub struct FailingSource<S> {
+    inner: S,
+    grows_left: usize,
+}
+
+#[cfg(feature = "test-util")]
+impl<S> FailingSource<S> {
+    pub const fn fail_after(inner: S, grows: usize) -> Self {
+        Self {
+            inner,
+            grows_left: grows,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+#[cfg(feature = "test-util")]
+unsafe impl<S: MemorySource> MemorySource for FailingSource<S> {
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+        self.grows_left = self.grows_left.checked_sub(1)?;
+        self.inner.grow(increment)
+    }
+
+    fn current_break(&self) -> usize {
+        self.inner.current_break()
+    }
+
+    fn zeroed(&self) -> bool {
+        self.inner.zeroed()
+    }
+
+    fn shrink(&mut self, decrement: usize) -> bool {
+        self.inner.shrink(decrement)
+    }
+
+    fn shared(&self) -> bool {
+        self.inner.shared()
+    }
+
+    fn map(&mut self, len: usize) -> Option<NonNull<u8>> {
+        self.inner.map(len)
+    }
+
+    fn map_zeroed(&self) -> bool {
+        self.inner.map_zeroed()
+    }
+
+    unsafe fn unmap(&mut self, ptr: NonNull<u8>, len: usize) {
+        unsafe { self.inner.unmap(ptr, len) }
+    }
+}
@@ -0,0 +1,388 @@
+//! Per-thread magazines of small free blocks, see
+//! [`Allocator::enable_thread_cache`].
+
+use crate::allocator::{size_class, Allocator, AllocatorImpl, MIN_BLOCK_SIZE, SIZE_CLASSES};
+use crate::source::MemorySource;
+
+use nix::libc::{c_void, pthread_key_create, pthread_key_t, pthread_setspecific};
+
+use std::alloc::Layout;
+use std::cell::UnsafeCell;
+use std::ptr::{null, null_mut, with_exposed_provenance_mut, NonNull};
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+// size classes 0 through 6, so blocks of up to 1 KiB
+const CACHED_CLASSES: usize = 7;
+const MAGAZINE_SIZE: usize = 16;
+// blocks moved between a magazine and the heap under one lock
+const BATCH: usize = MAGAZINE_SIZE / 2;
+// refilled blocks are aligned to this, so they fit any smaller alignment
+const CACHED_ALIGN: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    ptr: *mut u8,
+    // how much of the block can be handed out again
+    usable: usize,
+}
+
+struct Magazine {
+    len: usize,
+    entries: [Entry; MAGAZINE_SIZE],
+}
+
+struct Cache {
+    // the allocator the blocks and counts below belong to
+    owner: *const (),
+    flush: Option<unsafe fn(&mut Cache)>,
+    registered: bool,
+    live_delta: isize,
+    allocations: [u64; SIZE_CLASSES],
+    frees: u64,
+    magazines: [Magazine; CACHED_CLASSES],
+}
+
+impl Magazine {
+    const EMPTY: Magazine = Magazine {
+        len: 0,
+        entries: [Entry {
+            ptr: null_mut(),
+            usable: 0,
+        }; MAGAZINE_SIZE],
+    };
+
+    fn take(&mut self, layout: Layout) -> Option<Entry> {
+        let entries = &mut self.entries[..self.len];
+        let index = entries
+            .iter()
+            .rposition(|entry| entry.usable >= layout.size() && (entry.ptr as usize).is_multiple_of(layout.align()))?;
+        let entry = entries[index];
+        entries[index] = entries[entries.len() - 1];
+        self.len -= 1;
+        Some(entry)
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.entries[self.len] = entry;
+        self.len += 1;
+    }
+}
+
+impl Cache {
+    const EMPTY: Cache = Cache {
+        owner: null(),
+        flush: None,
+        registered: false,
+        live_delta: 0,
+        allocations: [0; SIZE_CLASSES],
+        frees: 0,
+        magazines: [Magazine::EMPTY; CACHED_CLASSES],
+    };
+
+    fn is_empty(&self) -> bool {
+        self.live_delta == 0
+            && self.frees == 0
+            && self.allocations.iter().all(|&count| count == 0)
+            && self.magazines.iter().all(|magazine| magazine.len == 0)
+    }
+
+    // make `allocator` the owner, unless another allocator still has
+    // blocks or counts in here
+    fn claim<S: MemorySource>(&mut self, allocator: &Allocator<S>) -> bool {
+        let owner = allocator as *const Allocator<S> as *const ();
+        if self.owner != owner {
+            if !self.is_empty() {
+                return false;
+            }
+            self.owner = owner;
+            self.flush = Some(flush::<S>);
+        }
+        if !self.registered {
+            // SAFETY: the cache lives as long as the thread, which outlives
+            // its thread-specific data destructors.
+            self.registered = unsafe { pthread_setspecific(*key(), self as *mut Cache as *const c_void) } == 0;
+        }
+        true
+    }
+
+    fn hand_out(&mut self, layout: Layout, class: usize, entry: Entry) -> NonNull<[u8]> {
+        self.live_delta = self.live_delta.wrapping_add_unsigned(layout.size());
+        self.allocations[class] += 1;
+        NonNull::slice_from_raw_parts(NonNull::new(entry.ptr).unwrap(), entry.usable)
+    }
+
+    // fold the counts kept here into the owner's stats
+    fn record_into<S: MemorySource>(&mut self, allocator_impl: &mut AllocatorImpl<S>) {
+        allocator_impl.record(self.live_delta, &self.allocations, self.frees);
+        self.live_delta = 0;
+        self.allocations = [0; SIZE_CLASSES];
+        self.frees = 0;
+    }
+}
+
+/// Per size class stacks of blocks handed back by thread caches, shared by
+/// all threads and pushed to and popped from without taking the lock.
+///
+/// The heap sees these blocks as still in use until `reclaim` frees them.
+pub(crate) struct Depot {
+    stacks: [Stack; CACHED_CLASSES],
+    // pops in progress, see `is_popping`
+    poppers: AtomicUsize,
+}
+
+// A Treiber stack whose head packs a pointer into the low 48 bits and a
+// counter bumped on every change into the high 16, so a pop can't be fooled
+// by its head being popped and pushed again in between (ABA). Packing loses
+// the pointer's provenance, so nodes are exposed on the way in.
+struct Stack {
+    head: AtomicU64,
+}
+
+// Written over the first bytes of a block while it sits in a stack, which
+// every block has room for, see MIN_BLOCK_SIZE.
+#[repr(C)]
+struct Node {
+    next: AtomicPtr<Node>,
+    usable: usize,
+}
+
+const ADDR_BITS: u32 = 48;
+const ADDR_MASK: u64 = (1 << ADDR_BITS) - 1;
+
+// the node a packed head points to, null for an empty stack
+fn unpack(head: u64) -> *mut Node {
+    with_exposed_provenance_mut((head & ADDR_MASK) as usize)
+}
+
+impl Stack {
+    const fn new() -> Self {
+        Self {
+            head: AtomicU64::new(0),
+        }
+    }
+
+    fn push(&self, entry: Entry) -> bool {
+        if entry.ptr as u64 & !ADDR_MASK != 0 {
+            return false;
+        }
+        let node = entry.ptr.cast::<Node>();
+        // SAFETY: the block is ours and at least a Node large and aligned.
+        unsafe {
+            node.write(Node {
+                next: AtomicPtr::new(null_mut()),
+                usable: entry.usable,
+            });
+        }
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let next = unpack(head);
+            unsafe { (*node).next.store(next, Ordering::Relaxed) };
+            let new = (head & !ADDR_MASK).wrapping_add(1 << ADDR_BITS) | node.expose_provenance() as u64;
+            match self.head.compare_exchange_weak(head, new, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<Entry> {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            let node = unpack(head);
+            if node.is_null() {
+                return None;
+            }
+            // SAFETY: node may have been popped and reused since we loaded
+            // head, but it's still heap memory while pops are in progress,
+            // and the counter makes the exchange below fail if it changed.
+            let next = unsafe { (*node).next.load(Ordering::Relaxed) };
+            let new = (head & !ADDR_MASK).wrapping_add(1 << ADDR_BITS) | next.expose_provenance() as u64;
+            match self.head.compare_exchange_weak(head, new, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_) => {
+                    return Some(Entry {
+                        ptr: node.cast(),
+                        usable: unsafe { (*node).usable },
+                    });
+                }
+                Err(current) => head = current,
+            }
+        }
+    }
+}
+
+impl Depot {
+    pub(crate) const fn new() -> Self {
+        Self {
+            stacks: [const { Stack::new() }; CACHED_CLASSES],
+            poppers: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, class: usize, entry: Entry) -> bool {
+        self.stacks[class].push(entry)
+    }
+
+    fn pop(&self, class: usize) -> Option<Entry> {
+        self.poppers.fetch_add(1, Ordering::SeqCst);
+        let entry = self.stacks[class].pop();
+        self.poppers.fetch_sub(1, Ordering::SeqCst);
+        entry
+    }
+
+    /// Whether some thread may be reading a block that just left a stack.
+    /// The heap mustn't be trimmed while this is true.
+    pub(crate) fn is_popping(&self) -> bool {
+        self.poppers.load(Ordering::SeqCst) != 0
+    }
+
+    /// Frees every block in the depot back into the heap, where it can be
+    /// coalesced again.
+    pub(crate) fn reclaim<S: MemorySource>(&self, allocator_impl: &mut AllocatorImpl<S>) -> bool {
+        let mut reclaimed = false;
+        for class in 0..CACHED_CLASSES {
+            while let Some(entry) = self.pop(class) {
+                unsafe { allocator_impl.free_block(entry.ptr) };
+                reclaimed = true;
+            }
+        }
+        reclaimed
+    }
+}
+
+thread_local! {
+    // const and without a destructor, since the global allocator can't use
+    // thread locals that need one; thread exit goes through `key` instead
+    static CACHE: UnsafeCell<Cache> = const { UnsafeCell::new(Cache::EMPTY) };
+}
+
+fn key() -> &'static pthread_key_t {
+    static KEY: OnceLock<pthread_key_t> = OnceLock::new();
+    KEY.get_or_init(|| {
+        let mut key = 0;
+        // SAFETY: key is a valid place to store the new key.
+        let ret = unsafe { pthread_key_create(&mut key, Some(on_thread_exit)) };
+        assert_eq!(ret, 0, "failed to create the thread cache key");
+        key
+    })
+}
+
+extern "C" fn on_thread_exit(cache: *mut c_void) {
+    // SAFETY: the key is only ever set to the exiting thread's cache.
+    let cache = unsafe { &mut *(cache as *mut Cache) };
+    cache.registered = false;
+    if let Some(flush) = cache.flush {
+        unsafe { flush(cache) };
+    }
+}
+
+// hand everything in `cache` back to its owner, which must be an `Allocator<S>`
+unsafe fn flush<S: MemorySource>(cache: &mut Cache) {
+    // SAFETY: the cache is only enabled on allocators that live forever.
+    let allocator = unsafe { &*(cache.owner as *const Allocator<S>) };
+    let mut allocator_impl = allocator.lock();
+    cache.record_into(&mut allocator_impl);
+    for magazine in &mut cache.magazines {
+        for entry in &magazine.entries[..magazine.len] {
+            unsafe { allocator_impl.free_block(entry.ptr) };
+        }
+        magazine.len = 0;
+    }
+    if !allocator.depot().is_popping() {
+        allocator_impl.trim();
+    }
+    cache.owner = null();
+    cache.flush = None;
+}
+
+fn cached_class(layout: Layout) -> Option<usize> {
+    let class = size_class(layout.size());
+    (class < CACHED_CLASSES && layout.align() <= CACHED_ALIGN).then_some(class)
+}
+
+fn with_cache<R>(f: impl FnOnce(&mut Cache) -> Option<R>) -> Option<R> {
+    // SAFETY: the cache is only touched from its own thread, and never
+    // reentrantly since nothing in here calls back into the allocator.
+    CACHE.try_with(|cache| f(unsafe { &mut *cache.get() })).ok().flatten()
+}
+
+/// Serves `layout` from the calling thread's cache, refilling it from
+/// `allocator` if needed. `None` means the caller has to take the slow path.
+pub(crate) fn allocate<S: MemorySource>(allocator: &Allocator<S>, layout: Layout) -> Option<NonNull<[u8]>> {
+    let class = cached_class(layout)?;
+    with_cache(|cache| {
+        if !cache.claim(allocator) {
+            return None;
+        }
+
+        let entry = match cache.magazines[class].take(layout) {
+            Some(entry) => entry,
+            None => {
+                if cache.magazines[class].len + BATCH > MAGAZINE_SIZE {
+                    return None;
+                }
+                let magazine = &mut cache.magazines[class];
+                for _ in 0..BATCH {
+                    match allocator.depot().pop(class) {
+                        Some(entry) => magazine.push(entry),
+                        None => break,
+                    }
+                }
+                if let Some(entry) = magazine.take(layout) {
+                    return Some(cache.hand_out(layout, class, entry));
+                }
+
+                let block = Layout::from_size_align(16 << class, CACHED_ALIGN).unwrap();
+                let mut allocator_impl = allocator.lock();
+                cache.record_into(&mut allocator_impl);
+                let magazine = &mut cache.magazines[class];
+                for _ in 0..BATCH.min(MAGAZINE_SIZE - magazine.len) {
+                    match allocator_impl.allocate_block(block) {
+                        Ok(ptr) => magazine.push(Entry {
+                            ptr: ptr.cast().as_ptr(),
+                            usable: ptr.len(),
+                        }),
+                        Err(_) => break,
+                    }
+                }
+                drop(allocator_impl);
+                magazine.take(layout)?
+            }
+        };
+        Some(cache.hand_out(layout, class, entry))
+    })
+}
+
+/// Parks `ptr` in the calling thread's cache, handing half of it back to
+/// `allocator` when it's full. Returns `false` if the caller has to free
+/// `ptr` itself.
+pub(crate) fn deallocate<S: MemorySource>(allocator: &Allocator<S>, ptr: *mut u8, layout: Layout) -> bool {
+    let Some(class) = cached_class(layout) else {
+        return false;
+    };
+    with_cache(|cache| {
+        if !cache.claim(allocator) {
+            return None;
+        }
+
+        let magazine = &mut cache.magazines[class];
+        if magazine.len == MAGAZINE_SIZE {
+            for &entry in &magazine.entries[..BATCH] {
+                if !allocator.depot().push(class, entry) {
+                    unsafe { allocator.lock().free_block(entry.ptr) };
+                }
+            }
+            magazine.entries.copy_within(BATCH.., 0);
+            magazine.len -= BATCH;
+        }
+
+        magazine.push(Entry {
+            ptr,
+            usable: layout.size().max(MIN_BLOCK_SIZE),
+        });
+        cache.live_delta = cache.live_delta.wrapping_sub_unsigned(layout.size());
+        cache.frees += 1;
+        Some(())
+    })
+    .is_some()
+}
@@ -0,0 +1,96 @@
+//! Where live allocations came from, for the `trace` feature.
+
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ptr::NonNull;
+use std::sync::Mutex;
+
+thread_local! {
+    // set while this thread works on a side table, so the allocations that
+    // does, which may well come back through the allocator, aren't traced
+    static TRACING: Cell<bool> = const { Cell::new(false) };
+    // set once an allocation on this thread has failed
+    static FAILED: Cell<bool> = const { Cell::new(false) };
+}
+
+// std holds its backtrace lock while it reports a panic or a failed
+// allocation, and allocates while doing so. Capturing a backtrace then would
+// wait on that lock forever, so after a failure this thread stops tracing.
+fn can_capture() -> bool {
+    !std::thread::panicking() && !FAILED.get()
+}
+
+pub(crate) fn allocation_failed() {
+    FAILED.set(true);
+}
+
+// Runs `f` unless this thread is already in the middle of tracing.
+fn untraced<R>(f: impl FnOnce() -> R) -> Option<R> {
+    if TRACING.replace(true) {
+        return None;
+    }
+    let result = f();
+    TRACING.set(false);
+    Some(result)
+}
+
+// Prints where this thread is now, without tracing what that allocates.
+pub(crate) fn print_backtrace() {
+    if !can_capture() {
+        return;
+    }
+    untraced(|| eprintln!("{}", Backtrace::force_capture()));
+}
+
+struct Trace {
+    size: usize,
+    backtrace: Backtrace,
+}
+
+/// Backtraces of live allocations, keyed by their address. Kept out of the
+/// block headers so tracing costs nothing when the feature is off.
+pub(crate) struct Traces {
+    live: Mutex<BTreeMap<usize, Trace>>,
+}
+
+impl Traces {
+    pub(crate) const fn new() -> Self {
+        Self {
+            live: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub(crate) fn record(&self, ptr: NonNull<u8>, size: usize) {
+        if !can_capture() {
+            return;
+        }
+        untraced(|| {
+            let backtrace = Backtrace::force_capture();
+            let trace = Trace { size, backtrace };
+            self.live.lock().unwrap().insert(ptr.as_ptr() as usize, trace);
+        });
+    }
+
+    pub(crate) fn forget(&self, ptr: *mut u8) {
+        untraced(|| {
+            let trace = self.live.lock().unwrap().remove(&(ptr as usize));
+            // dropping the backtrace frees memory, so do it without the lock
+            drop(trace);
+        });
+    }
+
+    pub(crate) fn report(&self) -> String {
+        untraced(|| {
+            let live = self.live.lock().unwrap();
+            let mut report = String::new();
+            for (addr, trace) in live.iter() {
+                let _ = writeln!(report, "leaked {} bytes at {addr:#x}, allocated at:", trace.size);
+                let _ = writeln!(report, "{}", trace.backtrace);
+            }
+            report
+        })
+        .unwrap_or_default()
+    }
+}
@@ -0,0 +1,40 @@
+// Helpers shared by the integration tests, which don't all use every one.
+#![allow(dead_code)]
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::Layout;
+use std::env;
+use std::process::Command;
+
+const CHILD_VAR: &str = "EXPECT_ABORT_CHILD";
+
+pub fn mock_allocator() -> Allocator<MockSource> {
+    Allocator::with_source(MockSource::new(1 << 16), FitStrategy::FirstFit)
+}
+
+pub fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+pub fn holds(ptr: *mut u8, len: usize, byte: u8) -> bool {
+    unsafe { std::slice::from_raw_parts(ptr, len) }.iter().all(|&b| b == byte)
+}
+
+// The checks abort the process, so each test re-runs itself in a child
+// process that does the actual damage and inspects how the child died.
+pub fn expect_abort(test: &str, message: &str, body: impl FnOnce()) {
+    if env::var_os(CHILD_VAR).is_some() {
+        body();
+        return;
+    }
+
+    let output = Command::new(env::current_exe().unwrap())
+        .args([test, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD_VAR, "1")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "child exited cleanly, stderr: {stderr}");
+    assert!(stderr.contains(message), "unexpected stderr: {stderr}");
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::{MemorySource, MockSource};
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr::NonNull;
+use common::mock_allocator;
+
+// moves the break right before every grow, like another sbrk user in the
+// same process would
+struct ShiftingSource(MockSource);
+
+unsafe impl MemorySource for ShiftingSource {
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+        self.0.grow(4064)?;
+        self.0.grow(increment)
+    }
+
+    fn current_break(&self) -> usize {
+        self.0.current_break()
+    }
+}
+
+// leaves the break a few bytes off before every grow
+struct SkewingSource(MockSource);
+
+unsafe impl MemorySource for SkewingSource {
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+        self.0.grow(3)?;
+        self.0.grow(increment)
+    }
+
+    fn current_break(&self) -> usize {
+        self.0.current_break()
+    }
+}
+
+#[test]
+fn page_aligned_allocation() {
+    let allocator = mock_allocator();
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let page = Layout::from_size_align(64, 4096).unwrap();
+
+    let a = unsafe { allocator.alloc(small) };
+    let b = unsafe { allocator.alloc(page) };
+    assert_eq!(b as usize % 4096, 0);
+    unsafe { b.write_bytes(0xaa, 64) };
+
+    // the slack past the page aligned block is handed back
+    assert!(allocator.blocks().last().unwrap().free);
+
+    unsafe { allocator.dealloc(b, page) };
+    unsafe { allocator.dealloc(a, small) };
+    // and with both gone, the whole heap is given back
+    assert!(allocator.blocks().is_empty());
+}
+
+#[test]
+fn page_aligned_allocation_when_break_moves() {
+    let allocator = Allocator::with_source(ShiftingSource(MockSource::new(1 << 16)), FitStrategy::FirstFit);
+    let layout = Layout::from_size_align(64, 4096).unwrap();
+
+    for _ in 0..4 {
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert_eq!(ptr as usize % 4096, 0);
+        let end = allocator.inspect_source(|source| source.current_break());
+        assert!(ptr as usize + 64 <= end);
+    }
+    for pair in allocator.blocks().windows(2) {
+        assert!(pair[0].data + pair[0].size <= pair[1].addr);
+    }
+}
+
+#[test]
+fn aligned_growth_covers_header_and_padding() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(8, 32).unwrap();
+    let start = allocator.current_break();
+
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert_eq!(ptr as usize % 32, 0);
+    unsafe { ptr.write_bytes(0xaa, 8) };
+
+    // the header sits in front of the padded data, and both fit inside
+    // what the break was moved by
+    let block = allocator.blocks()[0];
+    assert!(start <= block.addr && block.addr < block.data);
+    assert_eq!(block.data, ptr as usize);
+    assert!(block.data + 8 <= allocator.current_break());
+    unsafe { allocator.dealloc(ptr, layout) };
+}
+
+#[test]
+fn first_fit_skips_holes_that_arent_aligned() {
+    for strategy in [FitStrategy::FirstFit, FitStrategy::BestFit] {
+        let allocator = Allocator::with_source(MockSource::new(1 << 16), strategy);
+        let hole = Layout::from_size_align(256, 8).unwrap();
+        let mut holes = Vec::new();
+        for i in 0..16 {
+            holes.push(unsafe { allocator.alloc(hole) });
+            // separators of different sizes leave the holes at different
+            // alignments
+            unsafe { allocator.alloc(Layout::from_size_align(8 + 8 * i, 8).unwrap()) };
+        }
+        for &ptr in &holes {
+            unsafe { allocator.dealloc(ptr, hole) };
+        }
+        let aligned = holes.iter().position(|&ptr| (ptr as usize).is_multiple_of(64)).unwrap();
+        // and there are misaligned ones in front of it to skip
+        assert!(aligned > 0);
+
+        let layout = Layout::from_size_align(200, 64).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert_eq!(ptr, holes[aligned], "{strategy:?}");
+        allocator.check_integrity().unwrap();
+    }
+}
+
+#[test]
+fn front_padding_becomes_a_free_block() {
+    let allocator = mock_allocator();
+    let start = allocator.current_break();
+    let layout = Layout::from_size_align(16, 4096).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert_eq!(ptr as usize % 4096, 0);
+
+    // the padding in front of the aligned data is a free block now
+    let front = allocator.blocks()[0];
+    assert!(front.free);
+    assert!(front.addr >= start && front.data + front.size <= ptr as usize);
+    assert!(front.size >= 4096 - 256);
+
+    // which small allocations are carved out of
+    let small = Layout::from_size_align(100, 8).unwrap();
+    let smalls: Vec<_> = (0..8).map(|_| unsafe { allocator.alloc(small) }).collect();
+    assert!(smalls.iter().all(|&small| (small as usize) < ptr as usize));
+    allocator.check_integrity().unwrap();
+
+    for small_ptr in smalls {
+        unsafe { allocator.dealloc(small_ptr, small) };
+    }
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert!(allocator.blocks().is_empty());
+}
+
+#[test]
+fn odd_sizes_keep_the_next_header_aligned() {
+    let allocator = mock_allocator();
+    let layout = |size| Layout::from_size_align(size, 1).unwrap();
+    let sizes = [3, 5, 17, 1, 33, 7, 19, 65];
+    let ptrs: Vec<_> = (0..40).map(|i| unsafe { allocator.alloc(layout(sizes[i % sizes.len()])) }).collect();
+    allocator.check_integrity().unwrap();
+    for (i, &ptr) in ptrs.iter().enumerate().step_by(3) {
+        unsafe { allocator.dealloc(ptr, layout(sizes[i % sizes.len()])) };
+    }
+    allocator.check_integrity().unwrap();
+
+    // blocks are whole multiples of the header alignment, so each header
+    // sits right where the block before it ends
+    let blocks = allocator.blocks();
+    assert!(blocks.iter().all(|block| block.size.is_multiple_of(8)));
+    for pair in blocks.windows(2) {
+        assert_eq!(pair[0].data + pair[0].size, pair[1].addr);
+    }
+}
+
+#[test]
+fn offset_allocations_align_past_the_offset() {
+    let allocator = mock_allocator();
+    for (offset, align) in [(3, 16), (0, 64), (17, 16), (100, 4096)] {
+        let layout = Layout::from_size_align(40, align).unwrap();
+        let ptr = allocator.allocate_with_offset(layout, offset);
+        assert!(!ptr.is_null());
+        assert_eq!((ptr as usize + offset) % align, 0);
+        unsafe { ptr.write_bytes(0xaa, 40) };
+        unsafe { allocator.deallocate_with_offset(ptr, layout, offset) };
+    }
+    assert_eq!(allocator.stats().live_bytes, 0);
+    assert!(allocator.blocks().iter().all(|block| block.free));
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn headers_are_aligned_when_the_break_isnt() {
+    let allocator = Allocator::with_source(SkewingSource(MockSource::new(1 << 16)), FitStrategy::FirstFit);
+    let layouts = [(1, 1), (24, 8), (5, 2), (100, 16), (7, 1), (64, 64)];
+    for (size, align) in layouts {
+        let ptr = unsafe { allocator.alloc(Layout::from_size_align(size, align).unwrap()) };
+        assert_eq!(ptr as usize % align, 0);
+        unsafe { ptr.write_bytes(0xaa, size) };
+    }
+    let blocks = allocator.blocks();
+    assert!(blocks.len() >= layouts.len());
+    for block in blocks {
+        assert_eq!(block.addr % align_of::<usize>(), 0, "header at {:#x} is misaligned", block.addr);
+        assert_eq!(block.data % align_of::<usize>(), 0);
+    }
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn aligned_data_is_carved_out_of_a_misaligned_hole() {
+    for strategy in [FitStrategy::FirstFit, FitStrategy::BestFit, FitStrategy::NextFit] {
+        let allocator = Allocator::with_source(MockSource::new(1 << 16), strategy);
+        let hole = Layout::from_size_align(512, 8).unwrap();
+        let ptr = unsafe { allocator.alloc(hole) };
+        let guard = unsafe { allocator.alloc(Layout::from_size_align(16, 8).unwrap()) };
+        unsafe { allocator.dealloc(ptr, hole) };
+        assert!(!(ptr as usize).is_multiple_of(64));
+
+        // the hole is big enough once its front is split off
+        let brk = allocator.current_break();
+        let layout = Layout::from_size_align(200, 64).unwrap();
+        let aligned = unsafe { allocator.alloc(layout) };
+        assert!((aligned as usize).is_multiple_of(64), "{strategy:?}");
+        assert!(aligned > ptr && aligned as usize + 200 <= ptr as usize + 512, "{strategy:?}");
+        assert_eq!(allocator.current_break(), brk);
+        let blocks = allocator.blocks();
+        assert!(blocks[0].free && blocks[0].data == ptr as usize);
+        assert_eq!(blocks[1].data, aligned as usize);
+        allocator.check_integrity().unwrap();
+
+        unsafe { allocator.dealloc(aligned, layout) };
+        unsafe { allocator.dealloc(guard, Layout::from_size_align(16, 8).unwrap()) };
+        allocator.check_integrity().unwrap();
+        assert_eq!(allocator.stats().live_bytes, 0);
+    }
+}
@@ -0,0 +1,132 @@
+#![feature(allocator_api)]
+
+mod common;
+
+use allocator_speedrun::allocator::{AllocFailure, Allocator, FitStrategy, MAX_ALIGN};
+use allocator_speedrun::source::{MemorySource, MockSource, SbrkSource};
+use std::alloc::{AllocError, Allocator as _, GlobalAlloc, Layout};
+use std::ptr::NonNull;
+use common::mock_allocator;
+
+// pretends the break sits right below the top of the address space
+struct TopOfAddressSpace;
+
+unsafe impl MemorySource for TopOfAddressSpace {
+    fn grow(&mut self, _increment: usize) -> Option<NonNull<u8>> {
+        panic!("overflowing allocations must not grow the heap")
+    }
+
+    fn current_break(&self) -> usize {
+        usize::MAX - 4095
+    }
+}
+
+#[test]
+fn size_overflow() {
+    let allocator = Allocator::with_source(TopOfAddressSpace, FitStrategy::FirstFit);
+    let layout = Layout::from_size_align(isize::MAX as usize - 7, 8).unwrap();
+    assert_eq!(allocator.allocate_checked(layout), Err(AllocFailure::SizeOverflow));
+
+    let layout = Layout::from_size_align(8192, 8).unwrap();
+    assert_eq!(allocator.allocate_checked(layout), Err(AllocFailure::SizeOverflow));
+}
+
+#[test]
+fn out_of_memory() {
+    let allocator = Allocator::with_source(MockSource::new(4096), FitStrategy::FirstFit);
+    let layout = Layout::from_size_align(8192, 8).unwrap();
+    assert_eq!(allocator.allocate_checked(layout), Err(AllocFailure::OutOfMemory));
+    assert_eq!(allocator.stats().total_allocations, 0);
+}
+
+#[test]
+fn unsupported_alignment() {
+    let allocator = Allocator::with_source(MockSource::new(4096), FitStrategy::FirstFit);
+    let layout = Layout::from_size_align(8, MAX_ALIGN * 2).unwrap();
+    assert_eq!(allocator.allocate_checked(layout), Err(AllocFailure::UnsupportedAlignment));
+}
+
+#[test]
+fn successful_allocation() {
+    let allocator = Allocator::with_source(MockSource::new(4096), FitStrategy::FirstFit);
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let ptr = allocator.allocate_checked(layout).unwrap();
+    assert_eq!(ptr.len(), 24);
+    assert_eq!(allocator.stats().live_bytes, 24);
+}
+
+#[test]
+fn size_near_limit_fails_cleanly() {
+    let allocator = mock_allocator();
+    for slack in [0, 8, 16, 32, 64] {
+        let layout = Layout::from_size_align(isize::MAX as usize - 7 - slack, 8).unwrap();
+        assert!(allocator.allocate_checked(layout).is_err());
+        assert!(unsafe { allocator.alloc(layout) }.is_null());
+    }
+    assert_eq!(allocator.inspect_source(MockSource::grows), 0);
+
+    // the heap is still usable afterwards
+    let ptr = unsafe { allocator.alloc(Layout::new::<u64>()) };
+    assert!(!ptr.is_null());
+}
+
+#[test]
+fn break_at_top_of_address_space_fails_cleanly() {
+    struct UnalignedTop;
+
+    unsafe impl MemorySource for UnalignedTop {
+        fn grow(&mut self, _increment: usize) -> Option<NonNull<u8>> {
+            panic!("overflowing allocations must not grow the heap")
+        }
+
+        fn current_break(&self) -> usize {
+            usize::MAX - 2
+        }
+    }
+
+    let allocator = Allocator::with_source(UnalignedTop, FitStrategy::FirstFit);
+    assert_eq!(
+        allocator.allocate_checked(Layout::new::<u8>()),
+        Err(AllocFailure::SizeOverflow)
+    );
+}
+
+#[test]
+fn increment_past_isize_max_is_rejected() {
+    // the break is low enough for the sum not to overflow a usize, but the
+    // alignment slack pushes the increment past isize::MAX
+    struct LowBreak;
+
+    unsafe impl MemorySource for LowBreak {
+        fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+            panic!("asked to grow by {increment:#x}")
+        }
+
+        fn current_break(&self) -> usize {
+            4096
+        }
+    }
+
+    let allocator = Allocator::with_source(LowBreak, FitStrategy::FirstFit);
+    let layout = Layout::from_size_align(isize::MAX as usize - 4095, 4096).unwrap();
+    assert_eq!(allocator.allocate_checked(layout), Err(AllocFailure::SizeOverflow));
+}
+
+#[test]
+fn sbrk_never_shrinks_on_huge_increments() {
+    let before = SbrkSource.current_break();
+    assert!(SbrkSource.grow(isize::MAX as usize + 1).is_none());
+    assert!(SbrkSource.grow(usize::MAX).is_none());
+    assert_eq!(SbrkSource.current_break(), before);
+}
+
+#[test]
+fn absurd_sizes_are_an_alloc_error() {
+    let allocator = mock_allocator();
+    for size in [isize::MAX as usize - 7, 1 << 40, 1 << 20] {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        assert_eq!(allocator.allocate(layout), Err(AllocError));
+        assert!(unsafe { allocator.alloc(layout) }.is_null());
+    }
+    assert_eq!(allocator.stats().total_allocations, 0);
+}
@@ -0,0 +1,56 @@
+#![feature(allocator_api)]
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::arena::ArenaAllocator;
+use allocator_speedrun::source::MockSource;
+use std::alloc::{Allocator as AllocatorTrait, Layout};
+use std::ptr::NonNull;
+
+struct Node {
+    value: u64,
+    next: Option<NonNull<Node>>,
+}
+
+// builds a list of 0..len by hand, checks it and frees it again
+fn linked_list<A: AllocatorTrait>(allocator: &A, allocate_raw: impl Fn(Layout) -> Option<NonNull<u8>>, len: u64) {
+    let layout = Layout::new::<Node>();
+    let mut head = None;
+    for value in (0..len).rev() {
+        let node = allocate_raw(layout).unwrap().cast::<Node>();
+        unsafe { node.write(Node { value, next: head }) };
+        head = Some(node);
+    }
+
+    let mut expected = 0;
+    while let Some(node) = head {
+        let node_ref = unsafe { node.as_ref() };
+        assert_eq!(node_ref.value, expected);
+        expected += 1;
+        head = node_ref.next;
+        unsafe { allocator.deallocate(node.cast(), layout) };
+    }
+    assert_eq!(expected, len);
+}
+
+#[test]
+fn linked_list_on_the_heap() {
+    let allocator = Allocator::with_source(MockSource::new(1 << 20), FitStrategy::FirstFit);
+    linked_list(&allocator, |layout| allocator.allocate_raw(layout), 1000);
+    assert_eq!(allocator.stats().live_bytes, 0);
+    assert!(allocator.blocks().is_empty());
+}
+
+#[test]
+fn linked_list_in_an_arena() {
+    let arena: ArenaAllocator<65536> = ArenaAllocator::new();
+    linked_list(&arena, |layout| arena.allocate_raw(layout), 100);
+    assert_eq!(arena.stats().live_bytes, 0);
+}
+
+#[test]
+fn failure_is_none() {
+    let arena: ArenaAllocator<4096> = ArenaAllocator::new();
+    assert!(arena.allocate_raw(Layout::from_size_align(8192, 8).unwrap()).is_none());
+    let allocator = Allocator::with_source(MockSource::new(4096), FitStrategy::FirstFit);
+    assert!(allocator.allocate_raw(Layout::from_size_align(8192, 8).unwrap()).is_none());
+}
@@ -0,0 +1,76 @@
+#![feature(allocator_api)]
+
+use allocator_speedrun::arena::ArenaAllocator;
+use std::alloc::{Allocator, GlobalAlloc, Layout};
+
+static ARENA: ArenaAllocator<65536> = ArenaAllocator::new();
+
+#[test]
+fn allocations_stay_inside_the_arena() {
+    let arena: ArenaAllocator<4096> = ArenaAllocator::new();
+    let layout = Layout::from_size_align(64, 16).unwrap();
+    let a = arena.allocate(layout).unwrap();
+    let b = arena.allocate(layout).unwrap();
+    let start = &arena as *const _ as usize;
+    let end = start + std::mem::size_of_val(&arena);
+    for ptr in [a, b] {
+        let addr = ptr.cast::<u8>().as_ptr() as usize;
+        assert!(start <= addr && addr + 64 <= end);
+        assert_eq!(addr % 16, 0);
+    }
+    unsafe {
+        arena.deallocate(a.cast(), layout);
+        arena.deallocate(b.cast(), layout);
+    }
+    assert_eq!(arena.stats().live_bytes, 0);
+}
+
+#[test]
+fn runs_out_when_the_array_is_full() {
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let mut ptrs = Vec::new();
+    loop {
+        let ptr = unsafe { ARENA.alloc(layout) };
+        if ptr.is_null() {
+            break;
+        }
+        ptrs.push(ptr);
+    }
+    assert!(ptrs.len() >= 50 && ptrs.len() < 64, "got {} blocks", ptrs.len());
+
+    // freeing anything makes room again
+    unsafe { ARENA.dealloc(ptrs.pop().unwrap(), layout) };
+    let ptr = unsafe { ARENA.alloc(layout) };
+    assert!(!ptr.is_null());
+    ptrs.push(ptr);
+
+    for ptr in ptrs {
+        unsafe { ARENA.dealloc(ptr, layout) };
+    }
+    assert!(ARENA.blocks().is_empty());
+}
+
+#[test]
+fn vec_in_arena() {
+    let arena: ArenaAllocator<8192> = ArenaAllocator::new();
+    let mut v = Vec::new_in(&arena);
+    v.extend(0u32..1000);
+    assert!(v.iter().copied().eq(0..1000));
+    assert!(v.try_reserve(10_000).is_err());
+}
+
+#[test]
+fn resizes_in_place_through_the_allocator_trait() {
+    let arena: ArenaAllocator<8192> = ArenaAllocator::new();
+    let layout = |size| Layout::from_size_align(size, 8).unwrap();
+    let ptr = arena.allocate(layout(2048)).unwrap().cast::<u8>();
+    unsafe { ptr.write_bytes(0xab, 64) };
+
+    let shrunk = unsafe { arena.shrink(ptr, layout(2048), layout(64)) }.unwrap();
+    assert_eq!(shrunk.cast(), ptr);
+    let grown = unsafe { arena.grow_zeroed(ptr, layout(64), layout(1024)) }.unwrap();
+    assert_eq!(grown.cast(), ptr);
+    let bytes = unsafe { grown.as_ref() };
+    assert!(bytes[..64].iter().all(|&byte| byte == 0xab));
+    assert!(bytes[64..].iter().all(|&byte| byte == 0));
+}
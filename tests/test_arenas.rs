@@ -0,0 +1,40 @@
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy, FreePolicy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::GlobalAlloc;
+use common::layout;
+
+#[test]
+fn arenas_share_settings_but_not_memory() {
+    let parent = Allocator::with_source(MockSource::new(1 << 16), FitStrategy::BestFit);
+    parent.set_min_alignment(32);
+    parent.set_free_policy(FreePolicy::Ignore);
+    parent.set_decommit_threshold(4096);
+    let a = parent.new_arena(MockSource::new(1 << 16));
+    let b = parent.new_arena(MockSource::new(1 << 16));
+    assert_eq!(a.config(), parent.config());
+    assert_eq!(b.config(), parent.config());
+
+    let in_a: Vec<_> = (0..8).map(|_| unsafe { a.alloc(layout(100)) }).collect();
+    let in_b: Vec<_> = (0..8).map(|_| unsafe { b.alloc(layout(100)) }).collect();
+    let (a_base, b_base) = (a.inspect_source(|s| s.base()), b.inspect_source(|s| s.base()));
+    for &ptr in &in_a {
+        assert!((a_base..a_base + (1 << 16)).contains(&(ptr as usize)));
+        assert_eq!(ptr as usize % 32, 0);
+    }
+    for &ptr in &in_b {
+        assert!((b_base..b_base + (1 << 16)).contains(&(ptr as usize)));
+    }
+
+    // freeing everything in one arena leaves the other alone
+    let b_blocks = b.blocks();
+    for &ptr in &in_a {
+        unsafe { a.dealloc(ptr, layout(100)) };
+    }
+    assert_eq!(a.stats().live_bytes, 0);
+    assert_eq!(b.blocks(), b_blocks);
+    assert_eq!(b.stats().live_bytes, 800);
+    assert_eq!(parent.stats().total_allocations, 0);
+    b.check_integrity().unwrap();
+}
@@ -0,0 +1,64 @@
+#![feature(allocator_api)]
+
+mod common;
+
+use std::rc::Rc;
+use std::sync::Arc;
+use common::mock_allocator;
+
+#[repr(align(16))]
+struct Align16([u8; 24]);
+
+#[repr(align(64))]
+struct Align64([u8; 100]);
+
+#[test]
+fn boxes_of_aligned_types() {
+    let allocator = mock_allocator();
+    let small: Vec<_> = (0..8).map(|i| Box::new_in(Align16([i; 24]), &allocator)).collect();
+    let big: Vec<_> = (0..8).map(|i| Box::new_in(Align64([i; 100]), &allocator)).collect();
+    for (i, (small, big)) in small.iter().zip(&big).enumerate() {
+        assert!((&**small as *const Align16).is_aligned());
+        assert!((&**big as *const Align64).is_aligned());
+        assert!(small.0.iter().chain(&big.0).all(|&byte| byte == i as u8));
+    }
+    let boxed = Box::try_new_in(Align64([0xaa; 100]), &allocator).unwrap();
+    assert!((&*boxed as *const Align64).is_aligned());
+
+    drop((small, big, boxed));
+    assert_eq!(allocator.stats().live_bytes, 0);
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn uninit_slices_and_zero_sized_boxes() {
+    let allocator = mock_allocator();
+    let mut slice = Box::<[u8], _>::new_uninit_slice_in(1000, &allocator);
+    slice.iter_mut().for_each(|byte| {
+        byte.write(0x5a);
+    });
+    let slice = unsafe { slice.assume_init() };
+    assert!(slice.iter().all(|&byte| byte == 0x5a));
+    let empty = Box::<[u64], _>::new_uninit_slice_in(0, &allocator);
+    let unit = Box::new_in((), &allocator);
+
+    drop((slice, empty, unit));
+    assert_eq!(allocator.stats().live_bytes, 0);
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn rc_and_arc() {
+    let allocator = mock_allocator();
+    let rc = Rc::new_in(Align64([1; 100]), &allocator);
+    let shared = Rc::clone(&rc);
+    let arc = Arc::new_in(Align16([2; 24]), &allocator);
+    assert!((&*rc as *const Align64).is_aligned());
+    assert!((&*arc as *const Align16).is_aligned());
+    assert_eq!(shared.0[99], 1);
+    assert_eq!(arc.0[23], 2);
+
+    drop((rc, shared, arc));
+    assert_eq!(allocator.stats().live_bytes, 0);
+    allocator.check_integrity().unwrap();
+}
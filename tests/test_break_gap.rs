@@ -0,0 +1,68 @@
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::{MemorySource, MockSource};
+use std::alloc::{GlobalAlloc, Layout};
+use std::ops::Range;
+use std::ptr::NonNull;
+
+// takes a page of the break for itself on the second grow, like something
+// else calling sbrk between two of ours
+struct GappySource {
+    mock: MockSource,
+    grows: usize,
+    gap: Range<usize>,
+}
+
+unsafe impl MemorySource for GappySource {
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+        self.grows += 1;
+        if self.grows == 2 {
+            let start = self.mock.grow(4096)?.as_ptr() as usize;
+            self.gap = start..start + 4096;
+        }
+        self.mock.grow(increment)
+    }
+
+    fn current_break(&self) -> usize {
+        self.mock.current_break()
+    }
+
+    fn shrink(&mut self, decrement: usize) -> bool {
+        self.mock.shrink(decrement)
+    }
+}
+
+#[test]
+fn gap_in_the_break_is_never_handed_out() {
+    let source = GappySource {
+        mock: MockSource::new(1 << 20),
+        grows: 0,
+        gap: 0..0,
+    };
+    let allocator = Allocator::with_source(source, FitStrategy::FirstFit);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = unsafe { allocator.alloc(layout) };
+    let b = unsafe { allocator.alloc(layout) };
+    let gap = allocator.inspect_source(|source| source.gap.clone());
+    assert!(!gap.is_empty());
+    assert!(b as usize >= gap.end);
+
+    // nothing freed on either side of the gap grows into it
+    unsafe { allocator.dealloc(a, layout) };
+    unsafe { allocator.dealloc(b, layout) };
+    let big = Layout::from_size_align(1024, 8).unwrap();
+    let ptrs: Vec<_> = (0..16).map(|_| unsafe { allocator.alloc(big) }).collect();
+    for &ptr in &ptrs {
+        let ptr = ptr as usize;
+        assert!(ptr + 1024 <= gap.start || ptr >= gap.end, "{ptr:#x} overlaps {gap:x?}");
+    }
+    for block in allocator.blocks() {
+        let overlaps = block.addr < gap.end && gap.start < block.data + block.size;
+        assert!(!overlaps, "{block:?} overlaps {gap:x?}");
+    }
+    allocator.check_integrity().unwrap();
+
+    for ptr in ptrs {
+        unsafe { allocator.dealloc(ptr, big) };
+    }
+    allocator.check_integrity().unwrap();
+}
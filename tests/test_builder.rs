@@ -0,0 +1,48 @@
+mod common;
+
+use allocator_speedrun::allocator::{class_size, Allocator, AllocatorBuilder, AllocatorConfig, FitStrategy, FreePolicy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::GlobalAlloc;
+use common::layout;
+
+#[test]
+fn builder_options_take_effect() {
+    let allocator = AllocatorBuilder::new(MockSource::new(1 << 16))
+        .strategy(FitStrategy::BestFit)
+        .size_class_rounding(true)
+        .free_policy(FreePolicy::Ignore)
+        .decommit_threshold(1024)
+        .capacity(8192)
+        .build();
+    let config = AllocatorConfig {
+        strategy: FitStrategy::BestFit,
+        size_class_rounding: true,
+        free_policy: FreePolicy::Ignore,
+        decommit_threshold: 1024,
+        ..AllocatorConfig::DEFAULT
+    };
+    assert_eq!(allocator.config(), config);
+
+    // the capacity is there up front, so nothing here grows the heap
+    let brk = allocator.current_break();
+    assert!(allocator.total_free() >= 8000);
+    let big = unsafe { allocator.alloc(layout(1000)) };
+    unsafe { allocator.alloc(layout(64)) };
+    let small = unsafe { allocator.alloc(layout(200)) };
+    unsafe { allocator.alloc(layout(64)) };
+    assert_eq!(allocator.current_break(), brk);
+    let used = allocator.blocks().into_iter().filter(|block| !block.free);
+    assert!(used.map(|block| block.size).all(|size| class_size(size) == Some(size)));
+
+    // best fit skips the big hole for the one that fits
+    unsafe { allocator.dealloc(big, layout(1000)) };
+    unsafe { allocator.dealloc(small, layout(200)) };
+    assert_eq!(unsafe { allocator.alloc(layout(150)) }, small);
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn builder_defaults_match_new() {
+    assert_eq!(Allocator::builder().build().config(), Allocator::new().config());
+    assert_eq!(Allocator::new().config(), AllocatorConfig::default());
+}
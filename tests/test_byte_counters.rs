@@ -0,0 +1,133 @@
+#![feature(allocator_api)]
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{Allocator as _, GlobalAlloc, Layout};
+use std::ptr::NonNull;
+use std::thread;
+
+fn assert_counters_match(allocator: &Allocator<MockSource>) {
+    let blocks = allocator.blocks();
+    let used: usize = blocks.iter().filter(|block| !block.free).map(|block| block.size).sum();
+    let free: usize = blocks.iter().filter(|block| block.free).map(|block| block.size).sum();
+    assert_eq!(allocator.total_allocated(), used);
+    assert_eq!(allocator.total_free(), free);
+}
+
+#[test]
+fn counters_match_a_rescan() {
+    for strategy in [FitStrategy::FirstFit, FitStrategy::BestFit, FitStrategy::NextFit] {
+        let allocator = Allocator::with_source(MockSource::new(1 << 20), strategy);
+        let mut live: Vec<(NonNull<u8>, Layout)> = Vec::new();
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..2000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let size = 1 + (seed >> 40) as usize % 512;
+            let align = 1 << ((seed >> 20) % 7);
+            match seed % 5 {
+                0 | 1 => {
+                    let layout = Layout::from_size_align(size, align).unwrap();
+                    let ptr = allocator.allocate(layout).unwrap().cast();
+                    live.push((ptr, layout));
+                }
+                2 if !live.is_empty() => {
+                    let (ptr, layout) = live.swap_remove(seed as usize % live.len());
+                    unsafe { allocator.deallocate(ptr, layout) };
+                }
+                3 if !live.is_empty() => {
+                    let i = seed as usize % live.len();
+                    let (ptr, old) = live[i];
+                    let new = Layout::from_size_align(old.size() + size, old.align()).unwrap();
+                    let ptr = unsafe { allocator.grow(ptr, old, new) }.unwrap().cast();
+                    live[i] = (ptr, new);
+                }
+                4 if !live.is_empty() => {
+                    let i = seed as usize % live.len();
+                    let (ptr, old) = live[i];
+                    let new = Layout::from_size_align(old.size() / 2, old.align()).unwrap();
+                    let ptr = unsafe { allocator.shrink(ptr, old, new) }.unwrap().cast();
+                    live[i] = (ptr, new);
+                }
+                _ => {}
+            }
+            assert_counters_match(&allocator);
+        }
+
+        for (ptr, layout) in live {
+            unsafe { allocator.dealloc(ptr.as_ptr(), layout) };
+        }
+        assert_counters_match(&allocator);
+        assert_eq!(allocator.total_allocated(), 0);
+    }
+}
+
+#[test]
+fn splitting_a_free_block_takes_from_total_free() {
+    let allocator = Allocator::with_capacity_in(4096, MockSource::new(1 << 16));
+    let free = allocator.total_free();
+    assert!(free > 0);
+    assert_eq!(allocator.total_allocated(), 0);
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let [a, b] = [(); 2].map(|_| unsafe { allocator.alloc(layout) });
+    assert_eq!(allocator.total_allocated(), 128);
+    assert!(allocator.total_free() < free - 128);
+    assert_counters_match(&allocator);
+
+    // merging the two back into the rest hands their headers back as well
+    unsafe { allocator.dealloc(a, layout) };
+    unsafe { allocator.dealloc(b, layout) };
+    assert_eq!(allocator.total_allocated(), 0);
+    assert_eq!(allocator.total_free(), free);
+}
+
+#[test]
+fn peak_since_reset() {
+    let allocator = Allocator::with_source(MockSource::new(1 << 20), FitStrategy::FirstFit);
+    let big = Layout::from_size_align(4096, 8).unwrap();
+    let small = Layout::from_size_align(256, 8).unwrap();
+    let a = unsafe { allocator.alloc(big) };
+    let b = unsafe { allocator.alloc(big) };
+    unsafe { allocator.dealloc(b, big) };
+    assert_eq!(allocator.stats().peak_bytes, 2 * 4096);
+
+    allocator.reset_peak();
+    assert_eq!(allocator.stats().peak_bytes, 4096);
+    let c = unsafe { allocator.alloc(small) };
+    let d = unsafe { allocator.alloc(small) };
+    unsafe { allocator.dealloc(c, small) };
+    unsafe { allocator.dealloc(d, small) };
+    // only what happened after the reset counts
+    assert_eq!(allocator.stats().peak_bytes, 4096 + 2 * 256);
+    unsafe { allocator.dealloc(a, big) };
+}
+
+#[test]
+fn relaxed_counters_catch_up_without_the_lock() {
+    let allocator = Allocator::with_source(MockSource::new(1 << 20), FitStrategy::FirstFit);
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let ptrs = thread::scope(|scope| {
+        let sampler = scope.spawn(|| {
+            // spins until it sees the final total, which the worker never
+            // goes past
+            let mut seen = 0;
+            while seen < 100 * 1000 {
+                let live = allocator.live_bytes_relaxed();
+                assert!(live >= seen && live <= 100 * 1000, "saw {live} after {seen}");
+                seen = live;
+                thread::yield_now();
+            }
+        });
+        let ptrs: Vec<_> = (0..1000).map(|_| unsafe { allocator.alloc(layout) } as usize).collect();
+        sampler.join().unwrap();
+        ptrs
+    });
+
+    for ptr in ptrs {
+        unsafe { allocator.dealloc(ptr as *mut u8, layout) };
+    }
+    assert_eq!(allocator.live_bytes_relaxed(), 0);
+    assert_eq!(allocator.total_allocations_relaxed(), allocator.stats().total_allocations);
+}
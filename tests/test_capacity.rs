@@ -0,0 +1,53 @@
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout};
+
+#[test]
+fn preallocated_capacity_avoids_growing() {
+    let allocator = Allocator::with_capacity_in(1 << 20, MockSource::new(2 << 20));
+    assert_eq!(allocator.inspect_source(MockSource::grows), 1);
+    let blocks = allocator.blocks();
+    assert_eq!(blocks.len(), 1);
+    assert!(blocks[0].free);
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptrs: Vec<_> = (0..100).map(|_| unsafe { allocator.alloc(layout) }).collect();
+    assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+    assert_eq!(allocator.inspect_source(MockSource::grows), 1);
+
+    for ptr in ptrs {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    // everything coalesces back into the original reservation
+    assert_eq!(allocator.blocks(), blocks);
+}
+
+#[test]
+fn capacity_larger_than_source_starts_empty() {
+    let allocator = Allocator::with_capacity_in(1 << 20, MockSource::new(4096));
+    assert!(allocator.blocks().is_empty());
+    assert!(!unsafe { allocator.alloc(Layout::new::<u64>()) }.is_null());
+}
+
+#[test]
+fn reserving_twice_makes_one_free_block() {
+    let allocator = Allocator::with_source(MockSource::new(4 << 20), FitStrategy::FirstFit);
+    assert!(allocator.reserve(1 << 20));
+    assert!(allocator.reserve(1 << 20));
+    assert_eq!(allocator.block_counts(), (0, 1));
+    assert!(allocator.largest_free_block() > (2 << 20) - 64);
+    assert_eq!(allocator.largest_free_block(), allocator.total_free());
+
+    // and it's all there for one allocation, without growing again
+    let layout = Layout::from_size_align((2 << 20) - 256, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(!ptr.is_null());
+    assert_eq!(allocator.inspect_source(MockSource::grows), 2);
+    unsafe { allocator.dealloc(ptr, layout) };
+    // freeing it doesn't trim the reserve away
+    assert_eq!(allocator.largest_free_block(), allocator.total_free());
+    assert!(allocator.largest_free_block() > (2 << 20) - 64);
+
+    assert!(!allocator.reserve(4 << 20));
+    allocator.check_integrity().unwrap();
+}
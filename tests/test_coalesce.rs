@@ -0,0 +1,93 @@
+mod common;
+
+use allocator_speedrun::allocator::AllocatorBuilder;
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[test]
+fn frees_in_any_order_leave_nothing_to_coalesce() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptrs = [(); 6].map(|_| unsafe { allocator.alloc(layout) });
+    let _tail = unsafe { allocator.alloc(layout) };
+
+    // out of order, so runs get merged from both ends
+    for i in [4, 1, 3, 0, 5, 2] {
+        unsafe { allocator.dealloc(ptrs[i], layout) };
+    }
+    assert_eq!(allocator.coalesce_all(), 0);
+
+    let blocks = allocator.blocks();
+    assert_eq!(blocks.len(), 2);
+    assert!(blocks[0].free && !blocks[1].free);
+}
+
+#[test]
+fn freeing_the_first_block_keeps_the_chain() {
+    for order in [[0, 1, 2], [1, 0, 2], [0, 2, 1]] {
+        let allocator = mock_allocator();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let ptrs = [(); 3].map(|_| unsafe { allocator.alloc(layout) });
+        let tail = unsafe { allocator.alloc(layout) };
+        for (i, &ptr) in ptrs.iter().chain([&tail]).enumerate() {
+            unsafe { ptr.write_bytes(i as u8, 64) };
+        }
+
+        for (freed, &i) in order.iter().enumerate() {
+            unsafe { allocator.dealloc(ptrs[i], layout) };
+            allocator.check_integrity().unwrap();
+            // whatever's still live, including what follows a merged first
+            // block, is untouched
+            for &j in &order[freed + 1..] {
+                assert!(unsafe { std::slice::from_raw_parts(ptrs[j], 64) }.iter().all(|&b| b == j as u8));
+            }
+            assert!(unsafe { std::slice::from_raw_parts(tail, 64) }.iter().all(|&b| b == 3));
+        }
+
+        let blocks = allocator.blocks();
+        assert_eq!(blocks.len(), 2, "order {order:?}");
+        assert!(blocks[0].free && blocks[1].data == tail as usize);
+    }
+}
+
+#[test]
+fn merging_padded_blocks_covers_exactly_their_span() {
+    let allocator = mock_allocator();
+    let layouts = [(24, 8), (100, 128), (40, 8)].map(|(size, align)| Layout::from_size_align(size, align).unwrap());
+    let ptrs = layouts.map(|layout| unsafe { allocator.alloc(layout) });
+    let _tail = unsafe { allocator.alloc(Layout::from_size_align(8, 8).unwrap()) };
+    let before = allocator.blocks();
+    let used = |ptr: *mut u8| *before.iter().find(|block| block.data == ptr as usize).unwrap();
+    let (first, last) = (used(ptrs[0]), used(ptrs[2]));
+
+    for (&ptr, &layout) in ptrs.iter().zip(&layouts) {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    // the padding in front of the aligned blocks' data is merged along with
+    // their headers, but nothing past the last one's end is
+    let merged = allocator.blocks()[0];
+    assert!(merged.free);
+    assert_eq!(merged.data, first.data);
+    assert_eq!(merged.data + merged.size, last.data + last.size);
+    assert_eq!(allocator.total_free(), merged.size);
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn freed_neighbours_only_merge_with_coalescing_on() {
+    for coalesce in [false, true] {
+        let allocator = AllocatorBuilder::new(MockSource::new(1 << 16)).coalesce(coalesce).build();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let [a, b, _tail] = [(); 3].map(|_| unsafe { allocator.alloc(layout) });
+        unsafe { allocator.dealloc(a, layout) };
+        unsafe { allocator.dealloc(b, layout) };
+
+        let free = allocator.blocks().into_iter().filter(|block| block.free).count();
+        assert_eq!(free, if coalesce { 1 } else { 2 });
+        allocator.check_integrity().unwrap();
+        // and asking for it still merges them
+        assert_eq!(allocator.coalesce_all(), if coalesce { 0 } else { 1 });
+    }
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout};
+use common::{holds, mock_allocator};
+
+fn layout(i: usize) -> Layout {
+    Layout::from_size_align(32 + 48 * i, 8).unwrap()
+}
+
+#[test]
+fn compacting_closes_the_holes() {
+    let allocator = mock_allocator();
+    let mut ptrs: Vec<_> = (0..10).map(|i| unsafe { allocator.alloc(layout(i)) }).collect();
+    for (i, &ptr) in ptrs.iter().enumerate() {
+        unsafe { ptr.write_bytes(i as u8, layout(i).size()) };
+    }
+    for i in (0..10).step_by(2) {
+        unsafe { allocator.dealloc(ptrs[i], layout(i)) };
+    }
+    let live_bytes = allocator.stats().live_bytes;
+    let before = allocator.current_break();
+
+    let mut moves = 0;
+    unsafe {
+        allocator.compact(|old, new, size| {
+            let i = ptrs.iter().position(|&ptr| ptr == old).unwrap();
+            assert!(size >= layout(i).size());
+            ptrs[i] = new;
+            moves += 1;
+        })
+    };
+    // a block can stay put if its address happens to be more aligned than
+    // the hole in front of it leaves room for, but most of them move
+    assert!(moves >= 3, "only {moves} blocks moved");
+    assert!(allocator.current_break() < before);
+    assert_eq!(allocator.stats().live_bytes, live_bytes);
+    allocator.check_integrity().unwrap();
+    let blocks = allocator.blocks();
+    assert_eq!(blocks.iter().filter(|block| !block.free).count(), 5);
+    assert!(blocks.iter().filter(|block| block.free).count() <= 5 - moves);
+    assert!(!blocks.last().unwrap().free);
+    for i in (1..10).step_by(2) {
+        assert!(holds(ptrs[i], layout(i).size(), i as u8), "block {i}");
+        unsafe { allocator.dealloc(ptrs[i], layout(i)) };
+    }
+    assert!(allocator.blocks().is_empty());
+}
+
+#[test]
+fn aligned_blocks_stay_aligned() {
+    let allocator = mock_allocator();
+    let small = Layout::from_size_align(200, 8).unwrap();
+    let aligned = Layout::from_size_align(64, 256).unwrap();
+    let hole = unsafe { allocator.alloc(small) };
+    let mut ptr = unsafe { allocator.alloc(aligned) };
+    unsafe { ptr.write_bytes(0xaa, 64) };
+    unsafe { allocator.dealloc(hole, small) };
+
+    unsafe { allocator.compact(|_, new, _| ptr = new) };
+    assert!((ptr as usize).is_multiple_of(256));
+    assert!(holds(ptr, 64, 0xaa));
+    allocator.check_integrity().unwrap();
+    unsafe { allocator.dealloc(ptr, aligned) };
+}
+
+#[test]
+fn nothing_to_compact() {
+    let allocator = mock_allocator();
+    let a = unsafe { allocator.alloc(layout(1)) };
+    let b = unsafe { allocator.alloc(layout(2)) };
+    let blocks = allocator.blocks();
+    unsafe { allocator.compact(|_, _, _| panic!("nothing should move")) };
+    assert_eq!(allocator.blocks(), blocks);
+    unsafe { allocator.dealloc(a, layout(1)) };
+    unsafe { allocator.dealloc(b, layout(2)) };
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[test]
+fn debug_lists_every_block() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let ptrs: Vec<_> = (0..3).map(|_| unsafe { allocator.alloc(layout) }).collect();
+    unsafe { allocator.dealloc(ptrs[1], layout) };
+
+    let debug = format!("{allocator:?}");
+    assert!(debug.starts_with("Allocator (3 blocks)"), "{debug}");
+    assert_eq!(debug.lines().count(), 4);
+    assert_eq!(debug.matches(" free").count(), 1);
+    assert_eq!(debug.matches(" used").count(), 2);
+}
+
+#[test]
+fn debug_does_not_wait_for_the_lock() {
+    let allocator = mock_allocator();
+    let debug = allocator.inspect_source(|_| format!("{allocator:?}"));
+    assert_eq!(debug, "Allocator <locked>");
+}
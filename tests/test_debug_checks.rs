@@ -0,0 +1,138 @@
+#![cfg(feature = "debug-checks")]
+
+mod common;
+
+use allocator_speedrun::allocator::Allocator;
+use std::alloc::{GlobalAlloc, Layout};
+use common::{expect_abort, mock_allocator};
+
+#[test]
+fn detects_overrun_into_next_header() {
+    expect_abort("detects_overrun_into_next_header", "heap corruption detected at", || {
+        let allocator = mock_allocator();
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        let a = unsafe { allocator.alloc(layout) };
+        let _b = unsafe { allocator.alloc(layout) };
+        let next_header = allocator.blocks()[1].addr;
+
+        // scribble from a's data straight over b's header
+        let overrun = next_header + 8 - a as usize;
+        unsafe { a.write_bytes(0xaa, overrun) };
+
+        unsafe { allocator.alloc(Layout::from_size_align(64, 8).unwrap()) };
+    });
+}
+
+#[test]
+fn detects_interior_pointer_free() {
+    expect_abort(
+        "detects_interior_pointer_free",
+        "freeing interior pointer, expected",
+        || {
+            let allocator = mock_allocator();
+            let layout = Layout::from_size_align(8, 8).unwrap();
+            let ptr = unsafe { allocator.alloc(layout) };
+            unsafe { allocator.dealloc(ptr.add(4), layout) };
+        },
+    );
+}
+
+#[test]
+fn detects_dealloc_with_the_wrong_layout() {
+    expect_abort(
+        "detects_dealloc_with_the_wrong_layout",
+        "dealloc layout mismatch at",
+        || {
+            let allocator = mock_allocator();
+            let ptr = unsafe { allocator.alloc(Layout::from_size_align(32, 8).unwrap()) };
+            unsafe { allocator.dealloc(ptr, Layout::from_size_align(4096, 8).unwrap()) };
+        },
+    );
+}
+
+#[test]
+fn matching_layouts_pass() {
+    let allocator = mock_allocator();
+    for (size, align) in [(1, 1), (24, 8), (100, 64)] {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    assert_eq!(allocator.stats().live_bytes, 0);
+}
+
+#[test]
+fn detects_drop_with_blocks_left_in_a_shared_heap() {
+    expect_abort("detects_drop_with_blocks_left_in_a_shared_heap", "allocator dropped with 1 blocks", || {
+        let allocator = Allocator::from_static(Box::leak(vec![0; 1 << 16].into_boxed_slice()));
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let a = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(a, layout) };
+    });
+}
+
+#[test]
+fn detects_free_with_another_allocator() {
+    expect_abort("detects_free_with_another_allocator", "cross-allocator free of", || {
+        // both grow the real break, so the first one's block ends up inside
+        // the second one's heap
+        let first = Allocator::new();
+        let second = Allocator::new();
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        unsafe { second.alloc(layout) };
+        let ptr = unsafe { first.alloc(layout) };
+        unsafe { second.dealloc(ptr, layout) };
+    });
+}
+
+#[test]
+fn pointers_outside_the_heap_are_only_reported() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    // nothing in front of it is ours to read
+    let foreign = Box::into_raw(Box::new([0u64; 4]));
+    unsafe { allocator.dealloc(foreign.cast(), layout) };
+    drop(unsafe { Box::from_raw(foreign) });
+    unsafe { allocator.dealloc(ptr, layout) };
+}
+
+#[test]
+fn detects_handle_freed_with_another_allocator() {
+    expect_abort("detects_handle_freed_with_another_allocator", "cross-allocator free of", || {
+        let first = mock_allocator();
+        let second = mock_allocator();
+        unsafe { second.alloc(Layout::from_size_align(32, 8).unwrap()) };
+        let (_, handle) = first.allocate_handle(Layout::from_size_align(32, 8).unwrap()).unwrap();
+        unsafe { second.free_handle(handle) };
+    });
+}
+
+#[test]
+fn reuse_with_other_alignments_never_overlaps() {
+    let allocator = mock_allocator();
+    let aligns = [8, 64, 16, 256, 32];
+    let mut live = Vec::new();
+    for round in 0..200 {
+        let align = aligns[round % aligns.len()];
+        let layout = Layout::from_size_align(24 + round % 5 * 40, align).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert_eq!(ptr as usize % align, 0);
+        unsafe { ptr.write_bytes(round as u8, layout.size()) };
+        live.push((ptr, layout, round as u8));
+        // free every other one so the holes are reused at another alignment
+        if round % 2 == 1 {
+            let (ptr, layout, _) = live.remove(live.len() / 2);
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+    }
+
+    for &(ptr, layout, byte) in &live {
+        let data = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+        assert!(data.iter().all(|&b| b == byte));
+    }
+    for pair in allocator.blocks().windows(2) {
+        assert!(pair[0].data + pair[0].size <= pair[1].addr);
+    }
+}
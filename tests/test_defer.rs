@@ -0,0 +1,57 @@
+mod common;
+
+use allocator_speedrun::allocator::Allocator;
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+fn is_live(allocator: &Allocator<MockSource>, ptr: *mut u8) -> bool {
+    let blocks = allocator.blocks();
+    blocks.iter().any(|block| block.data == ptr as usize && !block.free)
+}
+
+#[test]
+fn deferred_frees_wait_for_a_flush() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptrs = [(); 4].map(|_| unsafe { allocator.alloc(layout) });
+    let _keep = unsafe { allocator.alloc(layout) };
+
+    for ptr in ptrs {
+        unsafe { ptr.write_bytes(0x5a, layout.size()) };
+        unsafe { allocator.defer_free(ptr, layout) };
+    }
+    // still live, and still holding what readers expect
+    for ptr in ptrs {
+        assert!(is_live(&allocator, ptr));
+        assert_eq!(unsafe { ptr.add(63).read() }, 0x5a);
+    }
+    assert_eq!(allocator.stats().live_bytes, 5 * 64);
+    assert_eq!(allocator.stats().total_frees, 0);
+
+    allocator.flush_deferred();
+    for ptr in ptrs {
+        assert!(!is_live(&allocator, ptr));
+    }
+    assert_eq!(allocator.stats().live_bytes, 64);
+    assert_eq!(allocator.stats().total_frees, 4);
+
+    // nothing is left over for the next flush
+    allocator.flush_deferred();
+    assert_eq!(allocator.stats().total_frees, 4);
+}
+
+#[test]
+fn deferred_frees_go_through_the_quarantine() {
+    let allocator = mock_allocator();
+    allocator.set_quarantine(1 << 10);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let [a, b] = [(); 2].map(|_| unsafe { allocator.alloc(layout) });
+    unsafe { allocator.defer_free(a, layout) };
+    unsafe { allocator.defer_free(b, layout) };
+    allocator.flush_deferred();
+
+    // quarantined, so neither is handed out again yet
+    let c = unsafe { allocator.alloc(layout) };
+    assert!(c != a && c != b);
+}
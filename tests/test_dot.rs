@@ -0,0 +1,19 @@
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[test]
+fn dot_output_describes_block_chain() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let ptrs: Vec<_> = (0..3).map(|_| unsafe { allocator.alloc(layout) }).collect();
+    unsafe { allocator.dealloc(ptrs[1], layout) };
+
+    let dot = allocator.to_dot();
+    assert!(dot.starts_with("digraph blocks {"));
+    assert_eq!(dot.matches("[label=").count(), 3);
+    assert_eq!(dot.matches(" -> ").count(), 2);
+    assert!(dot.contains("free=true\", style=filled"));
+    assert!(dot.contains(&format!("{:#x}", ptrs[1] as usize)));
+}
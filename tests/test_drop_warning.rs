@@ -0,0 +1,56 @@
+#![cfg(debug_assertions)]
+#![feature(allocator_api)]
+
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::env;
+use std::process::Command;
+use common::mock_allocator;
+
+const CHILD_VAR: &str = "DROP_WARNING_CHILD";
+const WARNING: &str = "allocator dropped with";
+
+// runs `body` in a child process and returns what it printed to stderr
+fn stderr_of(test: &str, body: impl FnOnce()) -> Option<String> {
+    if env::var_os(CHILD_VAR).is_some() {
+        body();
+        return None;
+    }
+
+    let output = Command::new(env::current_exe().unwrap())
+        .args([test, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD_VAR, "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    Some(String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+#[test]
+fn dropping_with_live_blocks_warns() {
+    let stderr = stderr_of("dropping_with_live_blocks_warns", || {
+        let allocator = mock_allocator();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let a = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.alloc(layout) };
+        unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(a, layout) };
+    });
+    if let Some(stderr) = stderr {
+        assert!(stderr.contains(&format!("{WARNING} 2 blocks still in use")), "stderr: {stderr}");
+    }
+}
+
+#[test]
+fn dropping_empty_is_quiet() {
+    let stderr = stderr_of("dropping_empty_is_quiet", || {
+        let allocator = mock_allocator();
+        let mut v = Vec::new_in(&allocator);
+        v.extend(0..1000u64);
+        drop(v);
+    });
+    if let Some(stderr) = stderr {
+        assert!(!stderr.contains(WARNING), "stderr: {stderr}");
+    }
+}
@@ -0,0 +1,23 @@
+use std::process::Command;
+
+// runs examples/global.rs, which uses the allocator as the global allocator
+#[test]
+fn global_example_runs() {
+    let output = Command::new(env!("CARGO"))
+        .args(["run", "--quiet", "--example", "global"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stdout: {stdout}\nstderr: {stderr}");
+
+    let stat = |name: &str| -> u64 {
+        let line = stdout.lines().find_map(|line| line.strip_prefix(name)).unwrap();
+        line.trim_start_matches(": ").parse().unwrap()
+    };
+    let allocations = stat("allocations");
+    assert!(allocations > 1000);
+    assert!(stat("frees") <= allocations);
+    assert!(stat("live bytes") < stat("peak bytes"));
+}
@@ -0,0 +1,116 @@
+#![feature(allocator_api)]
+
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{Allocator as _, GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[test]
+fn extends_into_freed_neighbour() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let [a, b, _c] = [(); 3].map(|_| unsafe { allocator.alloc(layout) });
+
+    assert!(!allocator.try_extend(a, layout, 128));
+    unsafe { allocator.dealloc(b, layout) };
+    assert!(allocator.try_extend(a, layout, 128));
+
+    let block = allocator.blocks()[0];
+    assert_eq!(block.data, a as usize);
+    assert!(block.size >= 128);
+    assert_eq!(allocator.stats().live_bytes, 128 + 64);
+}
+
+#[test]
+fn extends_last_block_by_growing_the_heap() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = unsafe { allocator.alloc(layout) };
+
+    assert!(allocator.try_extend(a, layout, 4096));
+    assert_eq!(allocator.blocks()[0].size, 4096);
+    assert!(!allocator.try_extend(a, layout, 1 << 20));
+}
+
+#[test]
+fn grow_keeps_the_pointer_when_it_can() {
+    let allocator = mock_allocator();
+    let mut v: Vec<u64, _> = Vec::with_capacity_in(8, &allocator);
+    v.extend(0..8);
+    let before = v.as_ptr();
+    v.reserve(100);
+    assert_eq!(v.as_ptr(), before);
+
+    // with something in the way it has to move
+    let blocker = allocator.allocate(Layout::new::<u64>()).unwrap();
+    v.reserve(v.capacity() * 2);
+    assert_ne!(v.as_ptr(), before);
+    assert!(v.iter().copied().eq(0..8));
+    unsafe { allocator.deallocate(blocker.cast(), Layout::new::<u64>()) };
+}
+
+#[test]
+fn realloc_resizes_in_place() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    let grown = unsafe { allocator.realloc(ptr, layout, 4096) };
+    assert_eq!(grown, ptr);
+
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+    let shrunk = unsafe { allocator.realloc(grown, layout, 32) };
+    assert_eq!(shrunk, ptr);
+    assert_eq!(allocator.stats().live_bytes, 32);
+}
+
+#[test]
+fn grow_takes_what_it_needs_of_a_free_neighbour() {
+    let allocator = mock_allocator();
+    let small = Layout::from_size_align(64, 8).unwrap();
+    let big = Layout::from_size_align(512, 8).unwrap();
+    let a = allocator.allocate(small).unwrap().cast::<u8>();
+    let b = allocator.allocate(big).unwrap().cast::<u8>();
+    let c = allocator.allocate(small).unwrap().cast::<u8>();
+    unsafe { c.write_bytes(0xcc, 64) };
+    unsafe { allocator.deallocate(b, big) };
+
+    let grown = unsafe { allocator.grow(a, small, Layout::from_size_align(256, 8).unwrap()) }.unwrap();
+    assert_eq!(grown.cast::<u8>(), a);
+
+    // the rest of b is still free, and c hasn't moved or changed
+    let blocks = allocator.blocks();
+    assert_eq!(blocks.len(), 3);
+    assert!(blocks[0].size >= 256 && blocks[0].size < 64 + 512);
+    assert!(blocks[1].free);
+    assert_eq!(blocks[1].data + blocks[1].size, b.as_ptr() as usize + 512);
+    assert_eq!(blocks[2].data, c.as_ptr() as usize);
+    assert!(unsafe { std::slice::from_raw_parts(c.as_ptr(), 64) }.iter().all(|&byte| byte == 0xcc));
+}
+
+#[test]
+// a million pushes take far too long under Miri
+#[cfg_attr(miri, ignore)]
+fn a_vec_pushed_to_one_byte_at_a_time_hardly_moves() {
+    let allocator = Allocator::with_source(MockSource::new(4 << 20), FitStrategy::FirstFit);
+    let mut v: Vec<u8, _> = Vec::new_in(&allocator);
+    let mut buffers = Vec::new();
+    for i in 0..1 << 20 {
+        v.push(i as u8);
+        if buffers.last() != Some(&v.as_ptr()) {
+            buffers.push(v.as_ptr());
+        }
+    }
+
+    // doubling from 8 bytes would mean 17 moves, but the Vec sits at the
+    // end of the heap and every grow just pushes the break further
+    let doublings = (1usize << 20).ilog2() - 8usize.ilog2();
+    assert_eq!(buffers.len(), 1, "{} buffers for {doublings} doublings", buffers.len());
+    let blocks = allocator.blocks();
+    let used: Vec<_> = blocks.iter().filter(|block| !block.free).collect();
+    assert_eq!(used.len(), 1);
+    assert_eq!(used[0].data, v.as_ptr() as usize);
+    assert!(used[0].size >= v.capacity());
+    assert!(v.iter().enumerate().all(|(i, &byte)| byte == i as u8));
+}
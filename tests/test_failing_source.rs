@@ -0,0 +1,71 @@
+#![cfg(feature = "test-util")]
+#![feature(allocator_api)]
+
+mod common;
+
+use allocator_speedrun::allocator::{AllocFailure, Allocator, FitStrategy};
+use allocator_speedrun::source::{FailingSource, MockSource, SbrkSource};
+use std::alloc::{Allocator as _, GlobalAlloc, Layout};
+use common::holds;
+
+fn failing_after(grows: usize) -> Allocator<FailingSource<MockSource>> {
+    Allocator::with_source(FailingSource::fail_after(MockSource::new(1 << 20), grows), FitStrategy::FirstFit)
+}
+
+#[test]
+fn allocations_fail_once_the_source_does() {
+    let allocator = failing_after(3);
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+    let mut live = Vec::new();
+    loop {
+        let ptr = unsafe { allocator.alloc(layout) };
+        if ptr.is_null() {
+            break;
+        }
+        unsafe { ptr.write_bytes(live.len() as u8, 4096) };
+        live.push(ptr);
+    }
+    assert_eq!(live.len(), 3);
+    assert_eq!(allocator.inspect_source(|source| source.inner().grows()), 3);
+    assert_eq!(allocator.allocate_checked(layout), Err(AllocFailure::OutOfMemory));
+    assert!(allocator.allocate(layout).is_err());
+    assert_eq!(allocator.stats().total_allocations, 3);
+    allocator.check_integrity().unwrap();
+
+    // what's there is untouched, and freed blocks are still reused
+    for (i, &ptr) in live.iter().enumerate() {
+        assert!(holds(ptr, 4096, i as u8));
+    }
+    unsafe { allocator.dealloc(live[0], layout) };
+    assert_eq!(unsafe { allocator.alloc(layout) }, live[0]);
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn a_failed_grow_leaves_the_block_alone() {
+    let allocator = failing_after(1);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    unsafe { ptr.write_bytes(0xab, 64) };
+
+    // neither growing in place nor moving can get more memory
+    assert!(unsafe { allocator.realloc(ptr, layout, 8192) }.is_null());
+    assert!(holds(ptr, 64, 0xab));
+    assert_eq!(allocator.stats().live_bytes, 64);
+    allocator.check_integrity().unwrap();
+    unsafe { allocator.dealloc(ptr, layout) };
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn a_break_that_cant_grow_falls_back_to_mapping() {
+    let allocator = Allocator::with_source(FailingSource::fail_after(SbrkSource, 0), FitStrategy::FirstFit);
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(!ptr.is_null());
+    unsafe { ptr.write_bytes(0xcd, 100) };
+    assert!(allocator.blocks().is_empty());
+    unsafe { allocator.dealloc(ptr, layout) };
+    allocator.check_integrity().unwrap();
+    assert_eq!(allocator.stats().live_bytes, 0);
+}
@@ -0,0 +1,80 @@
+#![feature(allocator_api)]
+
+mod common;
+
+use allocator_speedrun::allocator::Allocator;
+use allocator_speedrun::fallback::FallbackAllocator;
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout, System};
+use common::{holds, mock_allocator};
+
+// the mock caps the primary's heap at 64KiB
+fn fallback() -> FallbackAllocator<Allocator<MockSource>, System> {
+    FallbackAllocator::new(mock_allocator(), System)
+}
+
+#[test]
+fn what_the_primary_cant_hold_goes_to_the_secondary() {
+    let allocator = fallback();
+    let small = Layout::from_size_align(64, 8).unwrap();
+    let big = Layout::from_size_align(1 << 20, 16).unwrap();
+
+    let a = unsafe { allocator.alloc(small) };
+    let b = unsafe { allocator.alloc(big) };
+    assert!(!b.is_null());
+    assert!(allocator.primary().owns(a));
+    assert!(!allocator.primary().owns(b));
+    unsafe { b.write_bytes(0xab, big.size()) };
+    assert!(holds(b, big.size(), 0xab));
+    assert_eq!(allocator.primary().stats().total_allocations, 1);
+
+    // each is freed by the one that made it
+    unsafe { allocator.dealloc(b, big) };
+    assert_eq!(allocator.primary().stats().total_frees, 0);
+    unsafe { allocator.dealloc(a, small) };
+    assert_eq!(allocator.primary().stats().total_frees, 1);
+    assert_eq!(allocator.primary().stats().live_bytes, 0);
+    allocator.primary().check_integrity().unwrap();
+}
+
+#[test]
+fn growing_past_the_primary_moves_to_the_secondary() {
+    let allocator = fallback();
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    unsafe { ptr.write_bytes(0xcd, 1024) };
+
+    let grown = unsafe { allocator.realloc(ptr, layout, 1 << 20) };
+    assert!(!grown.is_null());
+    assert!(!allocator.primary().owns(grown));
+    assert!(holds(grown, 1024, 0xcd));
+    assert_eq!(allocator.primary().stats().live_bytes, 0);
+
+    // and stays there when it shrinks again
+    let shrunk = unsafe { allocator.realloc(grown, Layout::from_size_align(1 << 20, 8).unwrap(), 512) };
+    assert!(!allocator.primary().owns(shrunk));
+    assert!(holds(shrunk, 512, 0xcd));
+    unsafe { allocator.dealloc(shrunk, Layout::from_size_align(512, 8).unwrap()) };
+    allocator.primary().check_integrity().unwrap();
+}
+
+#[test]
+// far too many pushes for Miri
+#[cfg_attr(miri, ignore)]
+fn collections_spill_over_into_the_secondary() {
+    let allocator = fallback();
+    let mut small: Vec<u32, _> = Vec::new_in(&allocator);
+    small.extend(0..100);
+    let mut vec: Vec<u64, _> = Vec::new_in(&allocator);
+    for i in 0..100_000 {
+        vec.push(i);
+    }
+    assert!(allocator.primary().owns(small.as_mut_ptr().cast()));
+    assert!(!allocator.primary().owns(vec.as_mut_ptr().cast()));
+    assert!(vec.iter().copied().eq(0..100_000));
+    drop(vec);
+    assert_eq!(allocator.primary().stats().live_bytes, 400);
+    drop(small);
+    assert_eq!(allocator.primary().stats().live_bytes, 0);
+    allocator.primary().check_integrity().unwrap();
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use allocator_speedrun::allocator::FreePolicy;
+use std::alloc::{GlobalAlloc, Layout};
+use std::env;
+use std::process::{Command, Output};
+use common::mock_allocator;
+
+const CHILD_VAR: &str = "FREE_POLICY_CHILD";
+const WARNING: &str = "wasn't allocated here";
+
+// frees a pointer into the stack under `policy`, in a child process so
+// its stderr and exit status can be looked at; returns None in the child
+fn free_stack_pointer(test: &str, policy: FreePolicy) -> Option<Output> {
+    if env::var_os(CHILD_VAR).is_none() {
+        let output = Command::new(env::current_exe().unwrap())
+            .args([test, "--exact", "--nocapture", "--test-threads=1"])
+            .env(CHILD_VAR, "1")
+            .output()
+            .unwrap();
+        return Some(output);
+    }
+
+    let allocator = mock_allocator();
+    allocator.set_free_policy(policy);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let kept = unsafe { allocator.alloc(layout) };
+    let mut local = [0u8; 64];
+    unsafe { allocator.dealloc(local.as_mut_ptr(), layout) };
+
+    // the heap is left alone either way
+    assert_eq!(allocator.stats().total_frees, 0);
+    assert_eq!(allocator.blocks().len(), 1);
+    unsafe { allocator.dealloc(kept, layout) };
+    None
+}
+
+#[test]
+fn ignore_says_nothing() {
+    let Some(output) = free_stack_pointer("ignore_says_nothing", FreePolicy::Ignore) else {
+        return;
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+    assert!(!stderr.contains(WARNING), "stderr: {stderr}");
+}
+
+#[test]
+fn warn_prints_and_carries_on() {
+    let Some(output) = free_stack_pointer("warn_prints_and_carries_on", FreePolicy::Warn) else {
+        return;
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+    assert!(stderr.contains(WARNING), "stderr: {stderr}");
+}
+
+#[test]
+fn abort_stops_the_process() {
+    let Some(output) = free_stack_pointer("abort_stops_the_process", FreePolicy::Abort) else {
+        return;
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "stderr: {stderr}");
+    assert!(stderr.contains(WARNING), "stderr: {stderr}");
+}
+
+#[test]
+fn warn_is_the_default() {
+    assert_eq!(FreePolicy::default(), FreePolicy::Warn);
+}
@@ -0,0 +1,49 @@
+// the global allocator sits on the real break, which Miri can't grow
+#![cfg(not(miri))]
+
+use allocator_speedrun::allocator::Allocator;
+use std::collections::BTreeMap;
+use std::thread;
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator::new();
+
+const THREADS: usize = 8;
+// trace captures a backtrace for every allocation, which makes the full
+// run take minutes
+const ROUNDS: usize = if cfg!(feature = "trace") { 100 } else { 2000 };
+
+// every thread builds and drops a mix of collections, checking its own
+// data survived everyone else's churn
+fn churn(t: usize) {
+    let mut kept = BTreeMap::new();
+    for round in 0..ROUNDS {
+        let v: Vec<usize> = (0..round % 100).map(|i| i * t).collect();
+        let s = format!("thread {t} round {round}");
+        let b = Box::new([t as u8; 48]);
+        if round % 7 == 0 {
+            kept.insert(round, (v.clone(), s.clone()));
+        }
+        if round % 13 == 0 {
+            kept.pop_first();
+        }
+        assert!(v.iter().enumerate().all(|(i, &x)| x == i * t));
+        assert!(s.starts_with(&format!("thread {t} ")));
+        assert!(b.iter().all(|&x| x == t as u8));
+    }
+    for (round, (v, s)) in kept {
+        assert_eq!(v.len(), round % 100);
+        assert_eq!(s, format!("thread {t} round {round}"));
+    }
+}
+
+#[test]
+fn threads_share_the_global_heap() {
+    let handles: Vec<_> = (0..THREADS).map(|t| thread::spawn(move || churn(t))).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    ALLOCATOR.check_integrity().unwrap();
+    let stats = ALLOCATOR.stats();
+    assert!(stats.total_frees <= stats.total_allocations);
+}
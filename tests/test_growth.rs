@@ -0,0 +1,28 @@
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout};
+
+// grows the heap with ever larger allocations, none of them freed
+fn grows_for(factor: f64) -> usize {
+    let allocator = Allocator::with_source(MockSource::new(1 << 22), FitStrategy::FirstFit);
+    allocator.set_growth_factor(factor);
+    for i in 1..=200 {
+        let layout = Layout::from_size_align(64 * i, 8).unwrap();
+        assert!(!unsafe { allocator.alloc(layout) }.is_null());
+    }
+    allocator.inspect_source(|source| source.grows())
+}
+
+#[test]
+fn doubling_grows_a_logarithmic_number_of_times() {
+    // the 200 allocations add up to about 1.3 MiB, i.e. 2^21
+    let grows = grows_for(2.0);
+    assert!(grows <= 24, "{grows} grows");
+    assert_eq!(grows_for(1.0), 200);
+}
+
+#[test]
+fn factors_below_one_grow_by_what_is_needed() {
+    assert_eq!(grows_for(0.5), 200);
+    assert_eq!(grows_for(f64::NAN), 200);
+}
@@ -0,0 +1,63 @@
+#![feature(allocator_api)]
+
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::guard::DebugAllocator;
+use allocator_speedrun::source::MockSource;
+use std::alloc::{Allocator as _, Layout};
+use common::expect_abort;
+
+// bigger than the shared mock, for the vectors clean_frees_pass grows
+fn mock_allocator() -> Allocator<MockSource> {
+    Allocator::with_source(MockSource::new(1 << 20), FitStrategy::FirstFit)
+}
+
+#[test]
+fn clean_frees_pass() {
+    let allocator = mock_allocator();
+    let guarded = DebugAllocator::new(&allocator);
+    let mut v = Vec::new_in(&guarded);
+    let mut s = Vec::new_in(&guarded);
+    for i in 0..10_000u32 {
+        v.push(i);
+        s.push(i as u8);
+    }
+    assert!(v.iter().enumerate().all(|(i, &x)| x == i as u32));
+    drop((v, s));
+
+    for align in [1, 8, 64, 4096] {
+        let layout = Layout::from_size_align(100, align).unwrap();
+        let ptr = guarded.allocate(layout).unwrap();
+        assert_eq!(ptr.len(), 100);
+        assert!((ptr.cast::<u8>().as_ptr() as usize).is_multiple_of(align));
+        unsafe { ptr.cast::<u8>().write_bytes(0xaa, 100) };
+        unsafe { guarded.deallocate(ptr.cast(), layout) };
+    }
+
+    // everything went back to the allocator underneath, guards included
+    assert_eq!(allocator.stats().live_bytes, 0);
+    assert!(allocator.blocks().is_empty());
+}
+
+#[test]
+fn detects_overflow() {
+    expect_abort("detects_overflow", "heap overflow detected at", || {
+        let guarded = DebugAllocator::new(mock_allocator());
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let ptr = guarded.allocate(layout).unwrap().cast::<u8>();
+        unsafe { ptr.add(24).write(0) };
+        unsafe { guarded.deallocate(ptr, layout) };
+    });
+}
+
+#[test]
+fn detects_underflow() {
+    expect_abort("detects_underflow", "heap underflow detected at", || {
+        let guarded = DebugAllocator::new(mock_allocator());
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let ptr = guarded.allocate(layout).unwrap().cast::<u8>();
+        unsafe { ptr.sub(1).write(0) };
+        unsafe { guarded.deallocate(ptr, layout) };
+    });
+}
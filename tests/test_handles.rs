@@ -0,0 +1,71 @@
+mod common;
+
+use allocator_speedrun::source::MemorySource;
+use std::alloc::{GlobalAlloc, Layout};
+use common::{holds, mock_allocator};
+
+#[test]
+fn freeing_by_handle_skips_the_lookup() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let lookups = allocator.pointer_lookups();
+    let mut live: Vec<_> = (0..8u8)
+        .map(|i| {
+            let (ptr, handle) = allocator.allocate_handle(layout).unwrap();
+            assert_eq!(handle.ptr(), ptr);
+            unsafe { ptr.write_bytes(i, 64) };
+            (i, handle)
+        })
+        .collect();
+    // keeps the freed blocks from being trimmed away
+    let guard = unsafe { allocator.alloc(layout) };
+    assert_eq!(allocator.pointer_lookups(), lookups);
+    for i in [5, 1, 6, 0, 3, 7, 2, 4] {
+        let at = live.iter().position(|&(j, _)| j == i).unwrap();
+        let (_, handle) = live.remove(at);
+        unsafe { allocator.free_handle(handle) };
+        for &(j, handle) in &live {
+            assert!(holds(handle.ptr().as_ptr(), 64, j));
+        }
+    }
+    assert_eq!(allocator.pointer_lookups(), lookups);
+    assert_eq!(allocator.stats().live_bytes, 64);
+    assert_eq!(allocator.stats().total_frees, 8);
+
+    // blocks freed after the one in front of them were left beside it
+    assert!(allocator.coalesce_all() > 0);
+    allocator.check_integrity().unwrap();
+    assert_eq!(allocator.block_counts(), (1, 1));
+    unsafe { allocator.dealloc(guard, layout) };
+}
+
+#[test]
+fn handle_blocks_are_reused() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(128, 16).unwrap();
+    let (first, handle) = allocator.allocate_handle(layout).unwrap();
+    let guard = unsafe { allocator.alloc(layout) };
+    unsafe { allocator.free_handle(handle) };
+
+    let (again, handle) = allocator.allocate_handle(layout).unwrap();
+    assert_eq!(again, first);
+    unsafe { allocator.free_handle(handle) };
+    unsafe { allocator.dealloc(guard, layout) };
+    allocator.check_integrity().unwrap();
+    assert_eq!(allocator.stats().live_bytes, 0);
+}
+
+#[test]
+fn freeing_the_last_block_by_handle_trims_it() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    let brk = allocator.inspect_source(|source| source.current_break());
+    let (_, handle) = allocator.allocate_handle(layout).unwrap();
+    assert!(allocator.inspect_source(|source| source.current_break()) > brk);
+
+    unsafe { allocator.free_handle(handle) };
+    assert_eq!(allocator.inspect_source(|source| source.current_break()), brk);
+    unsafe { allocator.dealloc(first, layout) };
+    allocator.check_integrity().unwrap();
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy, IntegrityError};
+use allocator_speedrun::source::MemorySource;
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+use std::ptr::NonNull;
+use std::rc::Rc;
+use common::mock_allocator;
+
+#[test]
+fn healthy_heap_passes() {
+    let allocator = mock_allocator();
+    assert_eq!(allocator.check_integrity(), Ok(()));
+    let layout = Layout::from_size_align(48, 16).unwrap();
+    let ptrs = [(); 8].map(|_| unsafe { allocator.alloc(layout) });
+    for ptr in ptrs.into_iter().step_by(3) {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    assert_eq!(allocator.check_integrity(), Ok(()));
+}
+
+#[test]
+fn overwritten_header_is_corrupted() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let [_a, _b] = [(); 2].map(|_| unsafe { allocator.alloc(layout) });
+    let b = allocator.blocks()[1];
+    unsafe { (b.addr as *mut u8).write_bytes(0xaa, b.data - b.addr) };
+    assert_eq!(allocator.check_integrity(), Err(IntegrityError::Corrupted(b.addr)));
+}
+
+// a break that the test can move behind the allocator's back
+struct SharedBreak {
+    buf: Vec<u64>,
+    len: Rc<Cell<usize>>,
+}
+
+unsafe impl MemorySource for SharedBreak {
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+        let old_break = self.current_break();
+        if self.len.get() + increment > self.buf.len() * 8 {
+            return None;
+        }
+        self.len.set(self.len.get() + increment);
+        NonNull::new(old_break as *mut u8)
+    }
+
+    fn current_break(&self) -> usize {
+        self.buf.as_ptr() as usize + self.len.get()
+    }
+}
+
+#[test]
+fn block_past_the_break() {
+    let len = Rc::new(Cell::new(0));
+    let source = SharedBreak {
+        buf: vec![0; 1024],
+        len: len.clone(),
+    };
+    let allocator = Allocator::with_source(source, FitStrategy::FirstFit);
+    let ptr = unsafe { allocator.alloc(Layout::from_size_align(256, 8).unwrap()) };
+    assert!(!ptr.is_null());
+    len.set(len.get() - 16);
+    let block = allocator.blocks()[0];
+    assert_eq!(allocator.check_integrity(), Err(IntegrityError::PastBreak(block.addr)));
+}
@@ -0,0 +1,131 @@
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use proptest::prelude::*;
+use std::alloc::{GlobalAlloc, Layout};
+use std::collections::BTreeMap;
+use std::mem::align_of;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Alloc { size: usize, align_shift: u32 },
+    Free { index: usize },
+    Realloc { index: usize, size: usize },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (1usize..=512, 0u32..=6).prop_map(|(size, align_shift)| Op::Alloc { size, align_shift }),
+        any::<usize>().prop_map(|index| Op::Free { index }),
+        (any::<usize>(), 1usize..=512).prop_map(|(index, size)| Op::Realloc { index, size }),
+    ]
+}
+
+// every live allocation is filled with a byte derived from its address, so
+// overlapping or moved allocations show up as corrupted contents
+fn pattern(ptr: usize) -> u8 {
+    (ptr >> 3) as u8
+}
+
+fn fill(ptr: *mut u8, size: usize) {
+    unsafe { ptr.write_bytes(pattern(ptr as usize), size) }
+}
+
+fn check_contents(ptr: *mut u8, size: usize, expected: u8) {
+    let bytes = unsafe { std::slice::from_raw_parts(ptr, size) };
+    assert!(bytes.iter().all(|&b| b == expected), "contents of {ptr:?} were clobbered");
+}
+
+fn check_invariants(allocator: &Allocator<MockSource>, model: &BTreeMap<usize, Layout>) {
+    // no two live allocations overlap
+    let mut end = 0;
+    for (&addr, layout) in model {
+        assert!(addr >= end, "allocation at {addr:#x} overlaps the previous one");
+        assert_eq!(addr % layout.align(), 0, "allocation at {addr:#x} is misaligned");
+        end = addr + layout.size();
+    }
+
+    let live: usize = model.values().map(Layout::size).sum();
+    assert_eq!(allocator.stats().live_bytes, live);
+
+    allocator.check_integrity().unwrap();
+    let blocks = allocator.blocks();
+    for block in &blocks {
+        assert!(block.data > block.addr, "data overlaps the header");
+        if !block.free {
+            let layout = model.get(&block.data).expect("used block isn't a live allocation");
+            assert!(block.size >= layout.size());
+        }
+    }
+    assert_eq!(blocks.iter().filter(|b| !b.free).count(), model.len());
+
+    // sorted headers also rule out cycles in the chain
+    for pair in blocks.windows(2) {
+        let (prev, next) = (pair[0], pair[1]);
+        assert!(prev.addr < next.addr, "blocks out of order");
+        assert!(prev.data + prev.size <= next.addr, "block at {:#x} overruns the next header", prev.addr);
+        let adjacent = (prev.data + prev.size).next_multiple_of(align_of::<usize>()) == next.addr;
+        assert!(!(prev.free && next.free && adjacent), "adjacent free blocks weren't coalesced");
+    }
+}
+
+fn run(strategy: FitStrategy, ops: &[Op]) {
+    let allocator = Allocator::with_source(MockSource::new(1 << 20), strategy);
+    let mut model = BTreeMap::new();
+    let mut live: Vec<(*mut u8, Layout)> = Vec::new();
+
+    for op in ops {
+        match *op {
+            Op::Alloc { size, align_shift } => {
+                let layout = Layout::from_size_align(size, 1 << align_shift).unwrap();
+                let ptr = unsafe { allocator.alloc(layout) };
+                assert!(!ptr.is_null());
+                fill(ptr, size);
+                model.insert(ptr as usize, layout);
+                live.push((ptr, layout));
+            }
+            Op::Free { index } if !live.is_empty() => {
+                let (ptr, layout) = live.swap_remove(index % live.len());
+                check_contents(ptr, layout.size(), pattern(ptr as usize));
+                unsafe { allocator.dealloc(ptr, layout) };
+                model.remove(&(ptr as usize));
+            }
+            Op::Realloc { index, size } if !live.is_empty() => {
+                let index = index % live.len();
+                let (ptr, layout) = live[index];
+                let new = unsafe { allocator.realloc(ptr, layout, size) };
+                assert!(!new.is_null());
+                check_contents(new, layout.size().min(size), pattern(ptr as usize));
+                let new_layout = Layout::from_size_align(size, layout.align()).unwrap();
+                fill(new, size);
+                model.remove(&(ptr as usize));
+                model.insert(new as usize, new_layout);
+                live[index] = (new, new_layout);
+            }
+            _ => {}
+        }
+        check_invariants(&allocator, &model);
+    }
+
+    for (ptr, layout) in live {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    model.clear();
+    check_invariants(&allocator, &model);
+}
+
+proptest! {
+    #[test]
+    fn first_fit_invariants(ops in prop::collection::vec(op(), 1..200)) {
+        run(FitStrategy::FirstFit, &ops);
+    }
+
+    #[test]
+    fn best_fit_invariants(ops in prop::collection::vec(op(), 1..200)) {
+        run(FitStrategy::BestFit, &ops);
+    }
+
+    #[test]
+    fn next_fit_invariants(ops in prop::collection::vec(op(), 1..200)) {
+        run(FitStrategy::NextFit, &ops);
+    }
+}
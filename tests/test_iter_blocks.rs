@@ -0,0 +1,72 @@
+// the global allocator sits on the real break, which Miri can't grow
+#![cfg(not(miri))]
+
+mod common;
+
+use allocator_speedrun::allocator::Allocator;
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator::new();
+
+#[test]
+fn iterates_mock_heap() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let ptrs: Vec<_> = (0..4).map(|_| unsafe { allocator.alloc(layout) }).collect();
+    unsafe { allocator.dealloc(ptrs[1], layout) };
+
+    assert_eq!(allocator.iter_blocks().filter(|block| block.free).count(), 1);
+    assert_eq!(allocator.iter_blocks().filter(|block| !block.free).count(), 3);
+    let blocks = allocator.blocks();
+    assert!(allocator.iter_blocks().eq(blocks));
+}
+
+#[test]
+fn iterates_global_heap_without_allocating() {
+    let v = vec![1u64; 100];
+
+    // allocating through the global allocator in here would spin forever
+    // on its own lock
+    let mut found = false;
+    for block in ALLOCATOR.iter_blocks() {
+        found |= block.data == v.as_ptr() as usize && !block.free;
+    }
+    assert!(found);
+}
+
+#[test]
+fn counts_blocks_by_state() {
+    let allocator = mock_allocator();
+    assert_eq!(allocator.block_counts(), (0, 0));
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let ptrs: Vec<_> = (0..3).map(|_| unsafe { allocator.alloc(layout) }).collect();
+    unsafe { allocator.dealloc(ptrs[1], layout) };
+
+    // the sentinel isn't counted
+    assert_eq!(allocator.block_counts(), (2, 1));
+    assert_eq!(allocator.blocks().len(), 3);
+}
+
+#[test]
+fn iterates_only_free_blocks() {
+    let allocator = mock_allocator();
+    let sizes = [32, 64, 48, 128, 80];
+    let ptrs: Vec<_> = sizes
+        .iter()
+        .map(|&size| unsafe { allocator.alloc(Layout::from_size_align(size, 8).unwrap()) })
+        .collect();
+    // a block stays in use after the last hole so it isn't trimmed away
+    unsafe { allocator.alloc(Layout::from_size_align(32, 8).unwrap()) };
+    for i in [3, 1] {
+        unsafe { allocator.dealloc(ptrs[i], Layout::from_size_align(sizes[i], 8).unwrap()) };
+    }
+
+    let holes: Vec<_> = allocator.iter_free_blocks().map(|block| (block.data, block.size)).collect();
+    let tail = allocator.blocks().last().copied().filter(|block| block.free);
+    let mut expected = vec![(ptrs[1] as usize, 64), (ptrs[3] as usize, 128)];
+    expected.extend(tail.map(|block| (block.data, block.size)));
+    assert_eq!(holes, expected);
+    assert!(allocator.iter_free_blocks().all(|block| block.free));
+}
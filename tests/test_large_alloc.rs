@@ -0,0 +1,93 @@
+#![feature(allocator_api)]
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::{MemorySource, SbrkSource};
+use std::alloc::{Allocator as _, Layout};
+use std::ptr::NonNull;
+
+// a heap that can never grow, so every allocation has to be mapped
+struct MapOnlySource;
+
+unsafe impl MemorySource for MapOnlySource {
+    fn grow(&mut self, _increment: usize) -> Option<NonNull<u8>> {
+        None
+    }
+
+    fn current_break(&self) -> usize {
+        0
+    }
+
+    fn map(&mut self, len: usize) -> Option<NonNull<u8>> {
+        SbrkSource.map(len)
+    }
+
+    unsafe fn unmap(&mut self, ptr: NonNull<u8>, len: usize) {
+        unsafe { SbrkSource.unmap(ptr, len) }
+    }
+}
+
+#[test]
+fn huge_allocation_fails_cleanly() {
+    let allocator = Allocator::new();
+    let layout = Layout::from_size_align(1 << 46, 8).unwrap();
+    if let Ok(ptr) = allocator.allocate(layout) {
+        unsafe { allocator.deallocate(ptr.cast(), layout) };
+    }
+}
+
+#[test]
+fn allocation_falls_back_to_mapped_region() {
+    let allocator = Allocator::with_source(MapOnlySource, FitStrategy::FirstFit);
+    let layout = Layout::from_size_align(1 << 20, 64).unwrap();
+
+    let ptr = allocator.allocate(layout).unwrap().cast::<u8>();
+    assert_eq!(ptr.as_ptr() as usize % 64, 0);
+    unsafe { ptr.write_bytes(0xab, layout.size()) };
+    assert!(allocator.blocks().is_empty());
+    assert_eq!(allocator.stats().live_bytes, 1 << 20);
+
+    unsafe { allocator.deallocate(ptr, layout) };
+    assert_eq!(allocator.stats().live_bytes, 0);
+}
+
+#[test]
+fn mapped_regions_are_tracked_independently() {
+    let allocator = Allocator::with_source(MapOnlySource, FitStrategy::FirstFit);
+    let layout = Layout::from_size_align(4096, 4096).unwrap();
+
+    let ptrs: Vec<_> = (0..3).map(|_| allocator.allocate(layout).unwrap().cast::<u8>()).collect();
+    for (i, ptr) in ptrs.iter().enumerate() {
+        assert_eq!(ptr.as_ptr() as usize % 4096, 0);
+        unsafe { ptr.write_bytes(i as u8, layout.size()) };
+    }
+
+    unsafe { allocator.deallocate(ptrs[1], layout) };
+    for i in [0, 2] {
+        let bytes = unsafe { std::slice::from_raw_parts(ptrs[i].as_ptr(), layout.size()) };
+        assert!(bytes.iter().all(|&b| b == i as u8));
+        unsafe { allocator.deallocate(ptrs[i], layout) };
+    }
+    assert_eq!(allocator.stats().live_bytes, 0);
+}
+
+#[test]
+fn mapped_regions_resize_in_place_while_they_fit() {
+    let allocator = Allocator::with_source(MapOnlySource, FitStrategy::FirstFit);
+    let mut v: Vec<u8, _> = Vec::with_capacity_in(1 << 16, &allocator);
+    v.resize(1 << 16, 0xab);
+    let ptr = v.as_ptr();
+
+    v.truncate((1 << 16) - 100);
+    v.shrink_to_fit();
+    assert_eq!(v.as_ptr(), ptr);
+    v.reserve_exact(50);
+    v.resize(v.capacity(), 0xab);
+    assert_eq!(v.as_ptr(), ptr);
+    assert_eq!(allocator.stats().live_bytes, v.capacity());
+
+    // but not down to a fraction of the region
+    v.truncate(64);
+    v.shrink_to_fit();
+    assert_ne!(v.as_ptr(), ptr);
+    assert!(v.iter().all(|&b| b == 0xab));
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, AllocatorBuilder};
+use allocator_speedrun::source::MockSource;
+use std::alloc::GlobalAlloc;
+use std::thread;
+use common::layout;
+
+fn lazy(len: usize, capacity: usize) -> Allocator<MockSource> {
+    AllocatorBuilder::new(MockSource::new(len)).lazy_frees(true).capacity(capacity).build()
+}
+
+#[test]
+fn frees_wait_until_the_space_is_needed() {
+    let allocator = lazy(1 << 16, 2560);
+    let ptrs: Vec<_> = (0..8).map(|_| unsafe { allocator.alloc(layout(200)) }).collect();
+    let locks = allocator.lock_acquisitions();
+    for &ptr in &ptrs {
+        unsafe { allocator.dealloc(ptr, layout(200)) };
+    }
+    assert_eq!(allocator.lock_acquisitions(), locks);
+    // still in use as far as the heap knows
+    assert_eq!(allocator.stats().live_bytes, 8 * 200);
+    assert_eq!(allocator.blocks().iter().filter(|block| !block.free).count(), 8);
+
+    // a small allocation fits in what's left of the capacity
+    let brk = allocator.current_break();
+    let small = unsafe { allocator.alloc(layout(16)) };
+    assert!(small as usize > ptrs[7] as usize);
+    assert_eq!(allocator.stats().total_frees, 0);
+
+    // a big one doesn't, so they're freed and coalesced to make room
+    let big = unsafe { allocator.alloc(layout(1000)) };
+    assert_eq!(big, ptrs[0]);
+    assert_eq!(allocator.current_break(), brk);
+    assert_eq!(allocator.stats().total_frees, 8);
+    assert_eq!(allocator.stats().live_bytes, 1016);
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn flushing_frees_whatever_is_pending() {
+    let allocator = lazy(1 << 16, 0);
+    let ptrs: Vec<_> = (0..4).map(|_| unsafe { allocator.alloc(layout(64)) }).collect();
+    for &ptr in &ptrs {
+        unsafe { allocator.dealloc(ptr, layout(64)) };
+    }
+    allocator.flush_deferred();
+    assert_eq!(allocator.stats().live_bytes, 0);
+    assert!(allocator.blocks().iter().all(|block| block.free));
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn a_freeing_thread_never_takes_the_lock() {
+    const BLOCKS: usize = 2000;
+    let allocator = lazy(1 << 20, 0);
+    let old: Vec<usize> = (0..BLOCKS).map(|_| unsafe { allocator.alloc(layout(64)) } as usize).collect();
+
+    let locks = allocator.lock_acquisitions();
+    let new = thread::scope(|scope| {
+        scope.spawn(|| {
+            for &ptr in &old {
+                unsafe { allocator.dealloc(ptr as *mut u8, layout(64)) };
+            }
+        });
+        let allocating = scope.spawn(|| {
+            let new: Vec<_> = (0..BLOCKS).map(|_| unsafe { allocator.alloc(layout(64)) } as usize).collect();
+            assert!(new.iter().all(|&ptr| ptr != 0));
+            new
+        });
+        allocating.join().unwrap()
+    });
+    // once per allocation, with any draining done under the same lock
+    assert_eq!(allocator.lock_acquisitions() - locks, BLOCKS as u64);
+
+    for ptr in new {
+        unsafe { allocator.dealloc(ptr as *mut u8, layout(64)) };
+    }
+    allocator.flush_deferred();
+    assert_eq!(allocator.stats().live_bytes, 0);
+    assert_eq!(allocator.stats().total_frees, 2 * BLOCKS as u64);
+    allocator.check_integrity().unwrap();
+}
@@ -0,0 +1,58 @@
+#![cfg(feature = "metrics")]
+
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::collections::HashMap;
+use common::mock_allocator;
+
+// the samples in Prometheus text output, checking every line is either a
+// comment or a well formed `name value` pair
+fn parse(text: &str) -> HashMap<String, f64> {
+    let mut samples = HashMap::new();
+    for line in text.lines() {
+        if let Some(comment) = line.strip_prefix("# ") {
+            assert!(comment.starts_with("HELP ") || comment.starts_with("TYPE "), "bad comment: {line}");
+            continue;
+        }
+        let (name, value) = line.split_once(' ').unwrap_or_else(|| panic!("bad sample: {line}"));
+        assert!(name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_'));
+        assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'), "bad name: {name}");
+        let value = value.parse().unwrap_or_else(|_| panic!("bad value: {line}"));
+        assert!(samples.insert(name.to_string(), value).is_none(), "{name} is there twice");
+    }
+    samples
+}
+
+#[test]
+fn metrics_match_the_stats() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptrs = [(); 5].map(|_| unsafe { allocator.alloc(layout) });
+    unsafe { allocator.dealloc(ptrs[1], layout) };
+    unsafe { allocator.dealloc(ptrs[3], layout) };
+
+    let samples = parse(&allocator.export_metrics());
+    let stats = allocator.stats();
+    assert_eq!(samples["allocator_live_bytes"], stats.live_bytes as f64);
+    assert_eq!(samples["allocator_peak_bytes"], stats.peak_bytes as f64);
+    assert_eq!(samples["allocator_free_bytes"], allocator.total_free() as f64);
+    assert_eq!(samples["allocator_total_allocations_total"], 5.0);
+    assert_eq!(samples["allocator_total_frees_total"], 2.0);
+
+    // two holes, so only part of the free space is in the largest one
+    let free: Vec<_> = allocator.blocks().into_iter().filter(|block| block.free).collect();
+    let largest = free.iter().map(|block| block.size).max().unwrap();
+    let expected = 1.0 - largest as f64 / allocator.total_free() as f64;
+    let fragmentation = samples["allocator_fragmentation_ratio"];
+    assert!(fragmentation > 0.0 && fragmentation < 1.0);
+    assert!((fragmentation - expected).abs() < 1e-9);
+}
+
+#[test]
+fn an_empty_heap_isnt_fragmented() {
+    let allocator = mock_allocator();
+    let samples = parse(&allocator.export_metrics());
+    assert_eq!(samples["allocator_live_bytes"], 0.0);
+    assert_eq!(samples["allocator_fragmentation_ratio"], 0.0);
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use allocator_speedrun::allocator::Allocator;
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[test]
+fn every_block_is_cache_line_aligned() {
+    let allocator = Allocator::with_min_alignment(64);
+    let layouts = [Layout::new::<u8>(), Layout::new::<u64>(), Layout::from_size_align(100, 4).unwrap()];
+    let ptrs: Vec<_> = (0..64)
+        .map(|i| {
+            let layout = layouts[i % layouts.len()];
+            let ptr = unsafe { allocator.alloc(layout) };
+            assert!((ptr as usize).is_multiple_of(64), "{ptr:p}");
+            (ptr, layout)
+        })
+        .collect();
+
+    // blocks reused after a free are aligned just the same
+    for &(ptr, layout) in ptrs.iter().step_by(2) {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    for _ in 0..32 {
+        let ptr = unsafe { allocator.alloc(Layout::new::<u8>()) };
+        assert!((ptr as usize).is_multiple_of(64), "{ptr:p}");
+        unsafe { allocator.dealloc(ptr, Layout::new::<u8>()) };
+    }
+    for &(ptr, layout) in ptrs.iter().skip(1).step_by(2) {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+}
+
+#[test]
+fn sizes_are_rounded_to_the_alignment() {
+    let allocator = mock_allocator();
+    allocator.set_min_alignment(64);
+    unsafe { allocator.alloc(Layout::new::<u8>()) };
+    unsafe { allocator.alloc(Layout::from_size_align(65, 1).unwrap()) };
+    let sizes: Vec<_> = allocator.blocks().iter().filter(|block| !block.free).map(|block| block.size).collect();
+    assert_eq!(sizes, [64, 128]);
+}
+
+#[test]
+#[should_panic(expected = "power of two")]
+fn rejects_alignments_that_arent_powers_of_two() {
+    Allocator::with_min_alignment(48);
+}
+
+#[test]
+#[should_panic(expected = "before allocating")]
+fn must_be_set_before_allocating() {
+    let allocator = mock_allocator();
+    unsafe { allocator.alloc(Layout::new::<u8>()) };
+    allocator.set_min_alignment(64);
+}
@@ -0,0 +1,32 @@
+#![feature(allocator_api)]
+
+mod common;
+
+use std::alloc::{Allocator as _, GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[test]
+fn only_reuses_free_blocks() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(128, 8).unwrap();
+    let a = unsafe { allocator.alloc(layout) };
+    let _b = unsafe { allocator.alloc(layout) };
+
+    // with nothing free, there's nothing to give
+    let grows = allocator.inspect_source(|source| source.grows());
+    assert!(allocator.allocate_no_grow(layout).is_none());
+
+    // a free block that fits is handed out as usual
+    unsafe { allocator.dealloc(a, layout) };
+    let reused = allocator.allocate_no_grow(layout).unwrap();
+    assert_eq!(reused.cast::<u8>().as_ptr(), a);
+    assert!(reused.len() >= 128);
+    assert!(allocator.allocate_no_grow(layout).is_none());
+    assert_eq!(allocator.inspect_source(|source| source.grows()), grows);
+
+    // while the ordinary path still grows the heap
+    assert!(allocator.allocate(layout).is_ok());
+    assert_eq!(allocator.inspect_source(|source| source.grows()), grows + 1);
+    assert_eq!(allocator.stats().total_allocations, 4);
+    allocator.check_integrity().unwrap();
+}
@@ -0,0 +1,66 @@
+// the global allocator sits on a static buffer rather than the break, but
+// the child process can't be spawned under Miri
+#![cfg(not(miri))]
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::StaticSource;
+use std::env;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHILD_VAR: &str = "OOM_CHILD";
+const HEAP_SIZE: usize = 4 << 20;
+
+static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+
+#[global_allocator]
+static ALLOCATOR: Allocator<StaticSource> =
+    Allocator::with_source(StaticSource::new(unsafe { (&raw mut HEAP).as_mut().unwrap() }), FitStrategy::FirstFit);
+
+// use up the heap bit by bit, then ask for more than it could ever hold
+fn run_out_of_memory() {
+    let mut kept: Vec<Vec<u8>> = Vec::with_capacity(1 << 12);
+    loop {
+        let mut chunk = Vec::new();
+        if chunk.try_reserve_exact(16 << 10).is_err() {
+            break;
+        }
+        kept.push(chunk);
+    }
+    eprintln!("heap full after {} chunks", kept.len());
+    let huge: Vec<u8> = Vec::with_capacity(2 * HEAP_SIZE);
+    drop(huge);
+}
+
+#[test]
+fn running_out_of_memory_aborts_instead_of_hanging() {
+    if env::var_os(CHILD_VAR).is_some() {
+        run_out_of_memory();
+        return;
+    }
+
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(["running_out_of_memory_aborts_instead_of_hanging", "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD_VAR, "1")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(60);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("child deadlocked");
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!status.success(), "stderr: {stderr}");
+    assert!(stderr.contains("heap full after"), "stderr: {stderr}");
+    assert!(stderr.contains(&format!("memory allocation of {} bytes failed", 2 * HEAP_SIZE)), "stderr: {stderr}");
+}
@@ -0,0 +1,91 @@
+#![cfg(feature = "poison")]
+#![feature(allocator_api)]
+
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, POISON};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{Allocator as _, GlobalAlloc};
+use std::ptr::NonNull;
+use common::{expect_abort, layout, mock_allocator};
+
+fn is_poisoned(ptr: *const u8, len: usize) -> bool {
+    unsafe { std::slice::from_raw_parts(ptr, len) }.iter().all(|&byte| byte == POISON)
+}
+
+// a block followed by a freed one to grow into, and one in use after that
+fn with_free_neighbour() -> (Allocator<MockSource>, *mut u8, *mut u8) {
+    let allocator = mock_allocator();
+    let a = unsafe { allocator.alloc(layout(64)) };
+    let b = unsafe { allocator.alloc(layout(512)) };
+    unsafe { allocator.alloc(layout(64)) };
+    unsafe { allocator.dealloc(b, layout(512)) };
+    assert!(is_poisoned(b, 512));
+    (allocator, a, b)
+}
+
+#[test]
+fn growing_into_a_poisoned_neighbour() {
+    let (allocator, a, b) = with_free_neighbour();
+    let grown = unsafe { allocator.realloc(a, layout(64), 256) };
+    assert_eq!(grown, a);
+    unsafe { a.write_bytes(0xaa, 256) };
+
+    // what's left of the neighbour is still poisoned, old header and all
+    let rest = allocator.blocks()[1];
+    assert!(rest.free);
+    assert!(is_poisoned(rest.data as *const u8, rest.size));
+    assert_eq!(rest.data + rest.size, b as usize + 512);
+
+    // and handing it out again finds nothing written to it
+    let c = unsafe { allocator.alloc(layout(200)) };
+    assert_eq!(c as usize, rest.data);
+}
+
+#[test]
+fn growing_zeroed_clears_the_poison() {
+    let (allocator, a, _) = with_free_neighbour();
+    let a = NonNull::new(a).unwrap();
+    unsafe { a.write_bytes(0xaa, 64) };
+    let grown = unsafe { allocator.grow_zeroed(a, layout(64), layout(256)) }.unwrap();
+    assert_eq!(grown.cast(), a);
+    let bytes = unsafe { grown.as_ref() };
+    assert!(bytes[..64].iter().all(|&byte| byte == 0xaa));
+    assert!(bytes[64..].iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn shrinking_poisons_the_tail() {
+    let allocator = mock_allocator();
+    let a = unsafe { allocator.alloc(layout(512)) };
+    unsafe { allocator.alloc(layout(64)) };
+    unsafe { a.write_bytes(0xaa, 512) };
+    let shrunk = unsafe { allocator.realloc(a, layout(512), 64) };
+    assert_eq!(shrunk, a);
+
+    let tail = allocator.blocks()[1];
+    assert!(tail.free);
+    assert!(is_poisoned(tail.data as *const u8, tail.size));
+}
+
+#[test]
+fn write_after_free_is_caught_when_grown_into() {
+    expect_abort(
+        "write_after_free_is_caught_when_grown_into",
+        "use after free detected",
+        || {
+            let (allocator, a, b) = with_free_neighbour();
+            unsafe { b.add(8).write(0) };
+            unsafe { allocator.realloc(a, layout(64), 256) };
+        },
+    );
+}
+
+#[test]
+fn write_after_free_is_caught_when_reused() {
+    expect_abort("write_after_free_is_caught_when_reused", "use after free detected", || {
+        let (allocator, _, b) = with_free_neighbour();
+        unsafe { b.write(0) };
+        unsafe { allocator.alloc(layout(512)) };
+    });
+}
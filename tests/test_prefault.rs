@@ -0,0 +1,75 @@
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MemorySource;
+use nix::libc::{mincore, mmap, munmap, MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr::{null_mut, NonNull};
+
+const PAGE: usize = 4096;
+const LEN: usize = 1 << 20;
+
+// a break inside a fresh mapping, so mincore shows which pages got touched
+struct MappedSource {
+    base: *mut u8,
+    len: usize,
+}
+
+impl MappedSource {
+    fn new() -> Self {
+        let base = unsafe { mmap(null_mut(), LEN, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0) };
+        assert!(base != MAP_FAILED);
+        Self { base: base.cast(), len: 0 }
+    }
+
+    fn resident_pages(&self) -> usize {
+        let mut pages = [0u8; LEN / PAGE];
+        assert_eq!(unsafe { mincore(self.base.cast(), LEN, pages.as_mut_ptr()) }, 0);
+        pages.iter().filter(|&&page| page & 1 == 1).count()
+    }
+}
+
+impl Drop for MappedSource {
+    fn drop(&mut self) {
+        unsafe { munmap(self.base.cast(), LEN) };
+    }
+}
+
+unsafe impl MemorySource for MappedSource {
+    fn grow(&mut self, increment: usize) -> Option<NonNull<u8>> {
+        if increment > LEN - self.len {
+            return None;
+        }
+        let old_break = self.base.wrapping_add(self.len);
+        self.len += increment;
+        NonNull::new(old_break)
+    }
+
+    fn current_break(&self) -> usize {
+        self.base as usize + self.len
+    }
+}
+
+fn resident_after_allocating(prefault: bool) -> usize {
+    let allocator = Allocator::with_source(MappedSource::new(), FitStrategy::FirstFit);
+    allocator.set_prefault(prefault);
+    let ptr = unsafe { allocator.alloc(Layout::from_size_align(64 * PAGE, 8).unwrap()) };
+    assert!(!ptr.is_null());
+    allocator.inspect_source(MappedSource::resident_pages)
+}
+
+#[test]
+fn prefault_touches_every_grown_page() {
+    assert!(resident_after_allocating(true) >= 64);
+    // only the header's page is touched otherwise
+    assert!(resident_after_allocating(false) <= 2);
+}
+
+#[test]
+fn prefaulting_leaves_fresh_memory_as_it_was() {
+    let allocator = Allocator::with_source(MappedSource::new(), FitStrategy::FirstFit);
+    allocator.set_prefault(true);
+    let layout = Layout::from_size_align(16 * PAGE, 8).unwrap();
+    // fresh mappings are zeroed, and prefaulting must not change that
+    let ptr = unsafe { allocator.alloc(layout) };
+    let bytes = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+    assert!(bytes.iter().all(|&byte| byte == 0));
+}
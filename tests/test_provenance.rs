@@ -0,0 +1,79 @@
+// Everything here sticks to a MockSource and pointers the allocator handed
+// out, so it runs under `cargo miri test --test test_provenance`, which
+// catches headers and data pointers made up from bare addresses.
+
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, AllocatorBuilder, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::GlobalAlloc;
+use common::{holds, layout};
+
+#[test]
+fn split_and_merged_blocks_stay_usable() {
+    for strategy in [FitStrategy::FirstFit, FitStrategy::NextFit, FitStrategy::BestFit] {
+        let allocator = Allocator::with_source(MockSource::new(1 << 14), strategy);
+        let mut live = Vec::new();
+        for i in 0..24 {
+            let size = 8 + i * 24;
+            let ptr = unsafe { allocator.alloc(layout(size)) };
+            unsafe { ptr.write_bytes(i as u8, size) };
+            live.push((ptr, size, i as u8));
+            if i % 3 == 2 {
+                let (ptr, size, _) = live.remove(i / 3);
+                unsafe { allocator.dealloc(ptr, layout(size)) };
+            }
+        }
+        for (ptr, size, byte) in &mut live {
+            assert!(holds(*ptr, *size, *byte));
+            *ptr = unsafe { allocator.realloc(*ptr, layout(*size), *size * 2) };
+            assert!(holds(*ptr, *size, *byte));
+            *size *= 2;
+            unsafe { ptr.write_bytes(*byte, *size) };
+        }
+        allocator.check_integrity().unwrap();
+        for (ptr, size, _) in live {
+            unsafe { allocator.dealloc(ptr, layout(size)) };
+        }
+        allocator.check_integrity().unwrap();
+    }
+}
+
+#[test]
+fn reserved_space_and_compaction_keep_their_pointers() {
+    let allocator = AllocatorBuilder::new(MockSource::new(1 << 14)).capacity(4096).build();
+    let mut ptrs: Vec<_> = (0..8).map(|i| unsafe { allocator.alloc(layout(32 + 16 * i)) }).collect();
+    for (i, &ptr) in ptrs.iter().enumerate() {
+        unsafe { ptr.write_bytes(i as u8, 32 + 16 * i) };
+    }
+    for i in (0..8).step_by(2) {
+        unsafe { allocator.dealloc(ptrs[i], layout(32 + 16 * i)) };
+    }
+    unsafe {
+        allocator.compact(|old, new, _| {
+            let i = ptrs.iter().position(|&ptr| ptr == old).unwrap();
+            ptrs[i] = new;
+        })
+    };
+    for i in (1..8).step_by(2) {
+        assert!(holds(ptrs[i], 32 + 16 * i, i as u8));
+    }
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn restored_and_recovered_heaps_hand_out_usable_blocks() {
+    let allocator = Allocator::with_source(MockSource::new(1 << 14), FitStrategy::FirstFit);
+    let ptrs: Vec<_> = (0..4).map(|_| unsafe { allocator.alloc(layout(64)) }).collect();
+    unsafe { allocator.dealloc(ptrs[1], layout(64)) };
+    let snap = allocator.snapshot();
+    unsafe { allocator.alloc(layout(64)) };
+
+    unsafe { allocator.restore(snap) };
+    unsafe { allocator.recover() };
+    let again = unsafe { allocator.alloc(layout(64)) };
+    assert_eq!(again, ptrs[1]);
+    unsafe { again.write_bytes(0xaa, 64) };
+    assert!(holds(again, 64, 0xaa));
+    allocator.check_integrity().unwrap();
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use allocator_speedrun::allocator::Allocator;
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+fn quarantined_allocator(bytes: usize) -> Allocator<MockSource> {
+    let allocator = mock_allocator();
+    allocator.set_quarantine(bytes);
+    allocator
+}
+
+#[test]
+fn freed_block_is_not_reused_within_budget() {
+    let allocator = quarantined_allocator(1024);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    let a = unsafe { allocator.alloc(layout) };
+    unsafe { allocator.dealloc(a, layout) };
+    let b = unsafe { allocator.alloc(layout) };
+    assert_ne!(a, b);
+    assert_eq!(allocator.stats().live_bytes, 64);
+}
+
+#[test]
+fn oldest_block_is_released_past_budget() {
+    let allocator = quarantined_allocator(128);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    let ptrs: Vec<_> = (0..4).map(|_| unsafe { allocator.alloc(layout) }).collect();
+    for &ptr in &ptrs[..3] {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    // three 64 byte blocks don't fit in 128 bytes, so the first one is out
+    let reused = unsafe { allocator.alloc(layout) };
+    assert_eq!(reused, ptrs[0]);
+    let fresh = unsafe { allocator.alloc(layout) };
+    assert!(!ptrs.contains(&fresh));
+}
+
+#[test]
+fn lowering_budget_releases_quarantine() {
+    let allocator = quarantined_allocator(1024);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    let a = unsafe { allocator.alloc(layout) };
+    unsafe { allocator.dealloc(a, layout) };
+    assert!(allocator.blocks().iter().all(|block| !block.free));
+
+    allocator.set_quarantine(0);
+    assert!(allocator.blocks().iter().all(|block| block.free));
+    assert_eq!(unsafe { allocator.alloc(layout) }, a);
+}
@@ -0,0 +1,75 @@
+#![feature(allocator_api)]
+
+mod common;
+
+use allocator_speedrun::allocator::Allocator;
+use allocator_speedrun::source::MockSource;
+use std::alloc::{Allocator as _, Layout};
+use std::ptr::NonNull;
+use common::mock_allocator;
+
+// the last allocation on the heap, so it could grow in place, but one that
+// isn't 64 aligned
+fn misaligned(allocator: &Allocator<MockSource>, layout: Layout) -> NonNull<u8> {
+    loop {
+        let ptr = allocator.allocate(layout).unwrap().cast::<u8>();
+        if !(ptr.as_ptr() as usize).is_multiple_of(64) {
+            return ptr;
+        }
+    }
+}
+
+fn fill(ptr: NonNull<u8>, len: usize) {
+    for i in 0..len {
+        unsafe { ptr.as_ptr().add(i).write(i as u8) };
+    }
+}
+
+fn check(ptr: NonNull<u8>, len: usize) {
+    for i in 0..len {
+        assert_eq!(unsafe { ptr.as_ptr().add(i).read() }, i as u8);
+    }
+}
+
+#[test]
+fn grow_to_stricter_alignment_moves() {
+    let allocator = mock_allocator();
+    let old = Layout::from_size_align(16, 4).unwrap();
+    let ptr = misaligned(&allocator, old);
+    fill(ptr, 16);
+
+    let new = Layout::from_size_align(128, 64).unwrap();
+    let grown = unsafe { allocator.grow(ptr, old, new) }.unwrap().cast::<u8>();
+    assert_eq!(grown.as_ptr() as usize % 64, 0);
+    check(grown, 16);
+}
+
+#[test]
+fn shrink_to_stricter_alignment_moves() {
+    let allocator = mock_allocator();
+    let old = Layout::from_size_align(256, 4).unwrap();
+    let ptr = misaligned(&allocator, old);
+    fill(ptr, 256);
+
+    let new = Layout::from_size_align(32, 64).unwrap();
+    let shrunk = unsafe { allocator.shrink(ptr, old, new) }.unwrap().cast::<u8>();
+    assert_eq!(shrunk.as_ptr() as usize % 64, 0);
+    check(shrunk, 32);
+}
+
+#[test]
+fn shrink_in_place_hands_back_the_tail() {
+    let allocator = mock_allocator();
+    let old = Layout::from_size_align(1024, 8).unwrap();
+    let ptr = allocator.allocate(old).unwrap().cast::<u8>();
+    let _after = allocator.allocate(Layout::new::<u64>()).unwrap();
+    fill(ptr, 64);
+
+    let new = Layout::from_size_align(64, 8).unwrap();
+    let shrunk = unsafe { allocator.shrink(ptr, old, new) }.unwrap().cast::<u8>();
+    assert_eq!(shrunk, ptr);
+    check(shrunk, 64);
+    assert_eq!(allocator.blocks()[0].size, 64);
+    assert!(allocator.blocks()[1].free);
+    assert_eq!(allocator.stats().live_bytes, 64 + 8);
+}
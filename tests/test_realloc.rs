@@ -0,0 +1,120 @@
+#![feature(allocator_api)]
+
+mod common;
+
+use allocator_speedrun::arena::ArenaAllocator;
+use std::alloc::GlobalAlloc;
+use common::{layout, mock_allocator};
+
+fn fill(ptr: *mut u8, len: usize) {
+    for i in 0..len {
+        unsafe { ptr.add(i).write(i as u8) };
+    }
+}
+
+fn check(ptr: *mut u8, len: usize) {
+    for i in 0..len {
+        assert_eq!(unsafe { ptr.add(i).read() }, i as u8, "byte {i}");
+    }
+}
+
+#[test]
+fn grows_the_tail_in_place() {
+    let allocator = mock_allocator();
+    let ptr = unsafe { allocator.alloc(layout(64)) };
+    fill(ptr, 64);
+    let grown = unsafe { allocator.realloc(ptr, layout(64), 1024) };
+    assert_eq!(grown, ptr);
+    check(grown, 64);
+}
+
+#[test]
+fn grows_into_a_free_neighbour() {
+    let allocator = mock_allocator();
+    let [a, b, _c] = [(); 3].map(|_| unsafe { allocator.alloc(layout(128)) });
+    unsafe { allocator.dealloc(b, layout(128)) };
+    fill(a, 128);
+    let grown = unsafe { allocator.realloc(a, layout(128), 200) };
+    assert_eq!(grown, a);
+    check(grown, 128);
+}
+
+#[test]
+fn moves_when_boxed_in() {
+    let allocator = mock_allocator();
+    let [a, _b] = [(); 2].map(|_| unsafe { allocator.alloc(layout(64)) });
+    fill(a, 64);
+    let moved = unsafe { allocator.realloc(a, layout(64), 512) };
+    assert_ne!(moved, a);
+    check(moved, 64);
+
+    // and a's old block went back to the heap
+    let blocks = allocator.blocks();
+    assert!(blocks.iter().any(|block| block.data == a as usize && block.free));
+}
+
+#[test]
+fn shrinks_in_place() {
+    let allocator = mock_allocator();
+    let [a, _b] = [(); 2].map(|_| unsafe { allocator.alloc(layout(512)) });
+    fill(a, 512);
+    let shrunk = unsafe { allocator.realloc(a, layout(512), 100) };
+    assert_eq!(shrunk, a);
+    check(shrunk, 100);
+    assert_eq!(allocator.stats().live_bytes, 612);
+}
+
+#[test]
+fn realloc_to_zero_frees() {
+    let allocator = mock_allocator();
+    let ptr = unsafe { allocator.alloc(layout(64)) };
+    let empty = unsafe { allocator.realloc(ptr, layout(64), 0) };
+    assert!(!empty.is_null());
+    assert_eq!(allocator.stats().live_bytes, 0);
+    assert_eq!(allocator.stats().total_frees, 1);
+    unsafe { allocator.dealloc(empty, layout(0)) };
+    assert_eq!(allocator.stats().total_frees, 1);
+}
+
+#[test]
+fn c_realloc_idioms() {
+    let allocator = mock_allocator();
+    // realloc(NULL, 64) is malloc(64)
+    let ptr = unsafe { allocator.realloc(std::ptr::null_mut(), layout(0), 64) };
+    assert!(!ptr.is_null());
+    fill(ptr, 64);
+    check(ptr, 64);
+    assert_eq!(allocator.stats().total_allocations, 1);
+
+    // realloc(p, 0) is free(p), and freeing what it returns is harmless
+    let empty = unsafe { allocator.realloc(ptr, layout(64), 0) };
+    unsafe { allocator.dealloc(empty, layout(0)) };
+    assert_eq!(allocator.stats().total_frees, 1);
+    assert_eq!(allocator.stats().live_bytes, 0);
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn arena_reallocs_in_place() {
+    let arena: ArenaAllocator<8192> = ArenaAllocator::new();
+    let ptr = unsafe { arena.alloc(layout(64)) };
+    fill(ptr, 64);
+    let grown = unsafe { arena.realloc(ptr, layout(64), 2048) };
+    assert_eq!(grown, ptr);
+    check(grown, 64);
+}
+
+#[test]
+fn same_block_size_keeps_the_pointer() {
+    let allocator = mock_allocator();
+    let mut v: Vec<u64, _> = Vec::with_capacity_in(33, &allocator);
+    v.extend(0..33);
+    let ptr = v.as_ptr();
+    // one element less still needs the same block
+    v.pop();
+    v.shrink_to_fit();
+    assert_eq!(v.as_ptr(), ptr);
+    v.reserve_exact(1);
+    assert_eq!(v.as_ptr(), ptr);
+    assert!(v.iter().copied().eq(0..32));
+}
@@ -0,0 +1,51 @@
+#![cfg(feature = "record")]
+#![feature(allocator_api)]
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::record::{replay, TraceEvent, TraceOp};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{Allocator as _, Layout};
+
+fn record_workload() -> Vec<TraceEvent> {
+    let allocator = Allocator::with_source(MockSource::new(1 << 20), FitStrategy::FirstFit);
+    let mut live = Vec::new();
+    let mut seed = 7u64;
+    for _ in 0..500 {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let size = (seed >> 33) as usize % 300 + 1;
+        let align = 1 << ((seed >> 20) % 6);
+        if seed.is_multiple_of(3) && !live.is_empty() {
+            let (ptr, layout) = live.swap_remove((seed >> 40) as usize % live.len());
+            unsafe { allocator.deallocate(ptr, layout) };
+        } else {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            live.push((allocator.allocate(layout).unwrap().cast::<u8>(), layout));
+        }
+    }
+    let (ptr, layout) = live.pop().unwrap();
+    let new_layout = Layout::from_size_align(layout.size() * 3, layout.align()).unwrap();
+    let ptr = unsafe { allocator.grow(ptr, layout, new_layout) }.unwrap();
+    let shrunk = Layout::from_size_align(1, layout.align()).unwrap();
+    unsafe { allocator.shrink(ptr.cast(), new_layout, shrunk) }.unwrap();
+    allocator.recorded_trace()
+}
+
+#[test]
+fn recorded_trace_replays() {
+    let trace = record_workload();
+    assert!(trace.iter().filter(|event| event.op == TraceOp::Alloc).count() >= 300);
+    assert!(trace.iter().any(|event| event.op == TraceOp::Shrink));
+
+    // the same workload lands in the same places every time
+    assert_eq!(record_workload(), trace);
+    replay(&trace);
+}
+
+#[test]
+#[should_panic(expected = "different offset")]
+fn replay_catches_a_moved_allocation() {
+    let mut trace = record_workload();
+    let alloc = trace.iter_mut().rfind(|event| event.op == TraceOp::Alloc).unwrap();
+    alloc.offset += 16;
+    replay(&trace);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, IntegrityError, RecoveryReport};
+use allocator_speedrun::source::MockSource;
+use std::alloc::GlobalAlloc;
+use common::{layout, mock_allocator};
+
+// five blocks with the middle one freed
+fn five_blocks() -> (Allocator<MockSource>, Vec<*mut u8>) {
+    let allocator = mock_allocator();
+    let ptrs: Vec<_> = (0..5).map(|_| unsafe { allocator.alloc(layout(64)) }).collect();
+    unsafe { allocator.dealloc(ptrs[2], layout(64)) };
+    (allocator, ptrs)
+}
+
+#[test]
+fn recovers_from_a_bad_next_pointer() {
+    let (allocator, ptrs) = five_blocks();
+    let before = allocator.blocks();
+    let (a, b) = (before[1], before[2]);
+    // the word in a's header that links to b
+    let words = (a.addr..a.data).step_by(8).map(|addr| addr as *mut usize);
+    let next: Vec<_> = words.filter(|&word| unsafe { word.read() } == b.addr).collect();
+    assert_eq!(next.len(), 1);
+    unsafe { next[0].write(b.addr + 4) };
+    assert_eq!(allocator.check_integrity(), Err(IntegrityError::MisalignedHeader(b.addr + 4)));
+
+    let report = unsafe { allocator.recover() };
+    assert_eq!(
+        report,
+        RecoveryReport {
+            recovered_blocks: 5,
+            relinked_blocks: 1,
+            lost_blocks: 0,
+            lost_bytes: 0,
+        }
+    );
+    assert_eq!(allocator.check_integrity(), Ok(()));
+    assert_eq!(allocator.blocks(), before);
+    assert_eq!(allocator.total_free(), b.size);
+    assert_eq!(unsafe { allocator.alloc(layout(64)) }, ptrs[2]);
+}
+
+#[test]
+fn leaves_out_a_block_with_a_smashed_header() {
+    let (allocator, _) = five_blocks();
+    let before = allocator.blocks();
+    let lost = before[3];
+    unsafe { (lost.addr as *mut u8).write_bytes(0xaa, lost.data - lost.addr) };
+    assert_eq!(allocator.check_integrity(), Err(IntegrityError::Corrupted(lost.addr)));
+
+    let report = unsafe { allocator.recover() };
+    assert_eq!(report.recovered_blocks, 4);
+    assert_eq!(report.relinked_blocks, 1);
+    assert_eq!(report.lost_blocks, 1);
+    assert_eq!(report.lost_bytes, lost.data + lost.size - lost.addr);
+    assert_eq!(allocator.check_integrity(), Ok(()));
+    let after = allocator.blocks();
+    assert!(!after.contains(&lost));
+    assert_eq!(after.len(), before.len() - 1);
+
+    // what's left still works
+    let ptr = unsafe { allocator.alloc(layout(64)) };
+    assert_eq!(ptr as usize, before[2].data);
+    unsafe { allocator.dealloc(ptr, layout(64)) };
+    assert_eq!(allocator.check_integrity(), Ok(()));
+}
@@ -0,0 +1,52 @@
+// the global allocator sits on the real break, which Miri can't grow
+#![cfg(not(miri))]
+
+use allocator_speedrun::allocator::Allocator;
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator::new();
+
+// each of these allocates through ALLOCATOR while holding its lock
+
+#[test]
+fn blocks_of_the_global_allocator() {
+    let v = vec![1u64; 100];
+    let blocks = ALLOCATOR.blocks();
+    assert!(blocks.iter().any(|block| block.data == v.as_ptr() as usize));
+}
+
+#[test]
+fn debug_of_the_global_allocator() {
+    let debug = format!("{ALLOCATOR:?}");
+    assert!(debug.starts_with("Allocator ("), "{debug}");
+}
+
+#[test]
+fn allocating_while_inspecting_the_source() {
+    let v = ALLOCATOR.inspect_source(|_| vec![7u8; 64]);
+    assert!(v.iter().all(|&b| b == 7));
+}
+
+#[test]
+fn dump_blocks_of_the_global_allocator() {
+    ALLOCATOR.dump_blocks();
+}
+
+#[test]
+fn freeing_while_inspecting_the_source() {
+    let v = vec![1u32; 32];
+    ALLOCATOR.inspect_source(|_| drop(v));
+}
+
+#[test]
+fn growing_a_vec_while_inspecting_the_source() {
+    // reallocating in place keeps this well within the scratch buffer
+    let len = ALLOCATOR.inspect_source(|_| {
+        let mut v = Vec::new();
+        for i in 0..100_000u32 {
+            v.push(i as u8);
+        }
+        v.len()
+    });
+    assert_eq!(len, 100_000);
+}
@@ -0,0 +1,20 @@
+// the real break, so this is on its own: other tests in the same binary
+// could move it with their own allocations
+use allocator_speedrun::allocator::Allocator;
+use allocator_speedrun::source::{MemorySource, SbrkSource};
+use std::alloc::{GlobalAlloc, Layout};
+
+#[test]
+fn freeing_the_only_block_puts_the_break_back() {
+    // leave the break unaligned, so the header needs padding in front
+    let start = SbrkSource.current_break();
+    SbrkSource.grow(align_of::<usize>() - start % align_of::<usize>() + 3).unwrap();
+
+    let allocator = Allocator::new();
+    let before = SbrkSource.current_break();
+    let layout = Layout::from_size_align(1000, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(SbrkSource.current_break() > before);
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(SbrkSource.current_break(), before);
+}
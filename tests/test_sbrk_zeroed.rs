@@ -0,0 +1,24 @@
+#![feature(allocator_api)]
+
+// the real break, so this is on its own like test_sbrk_trim
+use allocator_speedrun::allocator::Allocator;
+use allocator_speedrun::source::{MemorySource, SbrkSource};
+use std::alloc::{Allocator as _, Layout};
+
+#[test]
+fn memory_someone_else_gave_back_is_wiped() {
+    // another user of the break writes to the end of a page and gives it
+    // back, which leaves the page mapped with whatever was written
+    let page = 4096;
+    let start = SbrkSource.current_break();
+    SbrkSource.grow(page - start % page + 100).unwrap();
+    let dirty = SbrkSource.grow(2 * page).unwrap();
+    unsafe { dirty.write_bytes(0xff, 2 * page) };
+    assert!(SbrkSource.shrink(2 * page));
+
+    let allocator = Allocator::new();
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let ptr = allocator.allocate_zeroed(layout).unwrap();
+    assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == 0));
+    unsafe { allocator.deallocate(ptr.cast(), layout) };
+}
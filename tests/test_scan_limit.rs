@@ -0,0 +1,56 @@
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, AllocatorBuilder, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::GlobalAlloc;
+use common::layout;
+
+// lots of small holes, and one big enough for 512 bytes after them
+fn fragment(allocator: &Allocator<MockSource>) -> *mut u8 {
+    let holes: Vec<_> = (0..64)
+        .map(|_| {
+            let hole = unsafe { allocator.alloc(layout(64)) };
+            unsafe { allocator.alloc(layout(16)) };
+            hole
+        })
+        .collect();
+    let big = unsafe { allocator.alloc(layout(1024)) };
+    unsafe { allocator.alloc(layout(16)) };
+    for hole in holes {
+        unsafe { allocator.dealloc(hole, layout(64)) };
+    }
+    unsafe { allocator.dealloc(big, layout(1024)) };
+    allocator.check_integrity().unwrap();
+    big
+}
+
+#[test]
+fn a_scan_limit_grows_the_heap_instead_of_searching_on() {
+    let allocator = AllocatorBuilder::new(MockSource::new(1 << 20)).scan_limit(4).build();
+    assert_eq!(allocator.config().scan_limit, 4);
+    let big = fragment(&allocator);
+
+    let grows = allocator.inspect_source(|source| source.grows());
+    let ptr = unsafe { allocator.alloc(layout(512)) };
+    assert_ne!(ptr, big);
+    assert_eq!(allocator.inspect_source(|source| source.grows()), grows + 1);
+    // small enough to fit in one of the first few holes
+    assert!(unsafe { allocator.alloc(layout(32)) } < big);
+
+    // with the limit off, the big hole is found after all
+    allocator.set_scan_limit(0);
+    assert_eq!(unsafe { allocator.alloc(layout(512)) }, big);
+    assert_eq!(allocator.inspect_source(|source| source.grows()), grows + 1);
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn without_a_limit_every_hole_is_searched() {
+    for strategy in [FitStrategy::FirstFit, FitStrategy::BestFit, FitStrategy::NextFit] {
+        let allocator = Allocator::with_source(MockSource::new(1 << 20), strategy);
+        let big = fragment(&allocator);
+        let grows = allocator.inspect_source(|source| source.grows());
+        assert_eq!(unsafe { allocator.alloc(layout(512)) }, big);
+        assert_eq!(allocator.inspect_source(|source| source.grows()), grows);
+    }
+}
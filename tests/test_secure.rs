@@ -0,0 +1,138 @@
+mod common;
+
+use allocator_speedrun::allocator::AllocatorBuilder;
+use allocator_speedrun::source::MockSource;
+use std::alloc::GlobalAlloc;
+use common::{layout, mock_allocator};
+
+const SECRET: u8 = 0x5e;
+
+fn bytes<'a>(ptr: *mut u8, len: usize) -> &'a [u8] {
+    unsafe { std::slice::from_raw_parts(ptr, len) }
+}
+
+// what a freed block is left holding
+fn wiped(bytes: &[u8]) -> bool {
+    #[cfg(feature = "poison")]
+    return bytes.iter().all(|&byte| byte == allocator_speedrun::allocator::POISON);
+    #[cfg(not(feature = "poison"))]
+    bytes.iter().all(|&byte| byte == 0)
+}
+
+#[test]
+fn secrets_are_wiped_when_freed() {
+    let allocator = mock_allocator();
+    let secret = allocator.allocate_secure(layout(256));
+    assert!(bytes(secret, 256).iter().all(|&byte| byte == 0));
+    unsafe { secret.write_bytes(SECRET, 256) };
+    // keeps the freed block from going back to the source
+    unsafe { allocator.alloc(layout(64)) };
+    unsafe { allocator.dealloc(secret, layout(256)) };
+
+    let reused = unsafe { allocator.alloc(layout(256)) };
+    assert_eq!(reused, secret);
+    assert!(wiped(bytes(reused, 256)));
+}
+
+#[test]
+fn secrets_freed_with_the_thread_cache_on_are_wiped() {
+    let allocator = Box::leak(Box::new(mock_allocator()));
+    allocator.enable_thread_cache();
+    let secret = allocator.allocate_secure(layout(64));
+    unsafe { secret.write_bytes(SECRET, 64) };
+    // too big for the cache, and keeps the freed block from being trimmed
+    unsafe { allocator.alloc(layout(4096)) };
+    unsafe { allocator.dealloc(secret, layout(64)) };
+
+    // the cache refills a handful at a time, and the secret's block is
+    // among the first of them
+    let reused: Vec<_> = (0..8).map(|_| unsafe { allocator.alloc(layout(64)) }).collect();
+    assert!(reused.contains(&secret));
+    assert!(wiped(bytes(secret, 64)));
+}
+
+#[test]
+fn secrets_are_wiped_when_shrunk() {
+    let allocator = mock_allocator();
+    let secret = allocator.allocate_secure(layout(512));
+    unsafe { secret.write_bytes(SECRET, 512) };
+    unsafe { allocator.alloc(layout(64)) };
+    let shrunk = unsafe { allocator.realloc(secret, layout(512), 64) };
+    assert_eq!(shrunk, secret);
+    assert!(bytes(shrunk, 64).iter().all(|&byte| byte == SECRET));
+
+    let tail = allocator.blocks()[1];
+    assert!(tail.free);
+    assert!(wiped(bytes(tail.data as *mut u8, tail.size)));
+}
+
+#[test]
+fn moving_a_secret_wipes_the_old_copy_and_keeps_it_secure() {
+    let allocator = mock_allocator();
+    let secret = allocator.allocate_secure(layout(128));
+    unsafe { secret.write_bytes(SECRET, 128) };
+    // boxed in, so growing has to move
+    unsafe { allocator.alloc(layout(64)) };
+    let moved = unsafe { allocator.realloc(secret, layout(128), 1024) };
+    assert_ne!(moved, secret);
+    assert!(bytes(moved, 128).iter().all(|&byte| byte == SECRET));
+    assert!(wiped(bytes(secret, 128)));
+
+    // and the new block is wiped when it's freed in turn
+    unsafe { moved.write_bytes(SECRET, 1024) };
+    unsafe { allocator.alloc(layout(64)) };
+    unsafe { allocator.dealloc(moved, layout(1024)) };
+    assert!(wiped(bytes(moved, 1024)));
+
+    // the thread cache doesn't keep either copy
+    let allocator = Box::leak(Box::new(mock_allocator()));
+    allocator.enable_thread_cache();
+    let secret = allocator.allocate_secure(layout(128));
+    unsafe { secret.write_bytes(SECRET, 128) };
+    unsafe { allocator.alloc(layout(4096)) };
+    let moved = unsafe { allocator.realloc(secret, layout(128), 1024) };
+    assert_ne!(moved, secret);
+    assert!(wiped(bytes(secret, 128)));
+    unsafe { moved.write_bytes(SECRET, 1024) };
+    unsafe { allocator.alloc(layout(4096)) };
+    unsafe { allocator.dealloc(moved, layout(1024)) };
+    assert!(wiped(bytes(moved, 1024)));
+}
+
+#[test]
+fn a_moved_secret_is_wiped_even_with_lazy_frees() {
+    let allocator = AllocatorBuilder::new(MockSource::new(1 << 16)).lazy_frees(true).build();
+    let secret = allocator.allocate_secure(layout(128));
+    unsafe { secret.write_bytes(SECRET, 128) };
+    unsafe { allocator.alloc(layout(64)) };
+    let moved = unsafe { allocator.realloc(secret, layout(128), 1024) };
+    assert_ne!(moved, secret);
+    // frees aren't put off while there's a secret live
+    assert!(wiped(bytes(secret, 128)));
+}
+
+// poisoning would overwrite them anyway
+#[cfg(not(feature = "poison"))]
+#[test]
+fn plain_allocations_are_left_alone() {
+    let allocator = mock_allocator();
+    let ptr = unsafe { allocator.alloc(layout(256)) };
+    unsafe { ptr.write_bytes(SECRET, 256) };
+    unsafe { allocator.alloc(layout(64)) };
+    unsafe { allocator.dealloc(ptr, layout(256)) };
+    assert!(bytes(ptr, 256)[64..].iter().all(|&byte| byte == SECRET));
+}
+
+#[test]
+fn restored_secrets_are_still_wiped() {
+    let allocator = mock_allocator();
+    let secret = allocator.allocate_secure(layout(128));
+    unsafe { allocator.alloc(layout(64)) };
+    let snap = allocator.snapshot();
+
+    unsafe { allocator.dealloc(secret, layout(128)) };
+    unsafe { allocator.restore(snap) };
+    unsafe { secret.write_bytes(SECRET, 128) };
+    unsafe { allocator.dealloc(secret, layout(128)) };
+    assert!(wiped(bytes(secret, 128)));
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "test-util")]
+
+use allocator_speedrun::allocator::{Allocator, BlockInfo};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout};
+
+// a fixed mix of allocations and frees driven by the allocator's seed,
+// returning the block layout relative to the start of the mock
+fn run(allocator: &Allocator<MockSource>) -> Vec<BlockInfo> {
+    let mut state = allocator.seed();
+    let mut live = Vec::new();
+    for _ in 0..2000 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        if state.is_multiple_of(3) && !live.is_empty() {
+            let (ptr, layout) = live.swap_remove(state as usize % live.len());
+            unsafe { allocator.dealloc(ptr, layout) };
+        } else {
+            let layout = Layout::from_size_align(1 + (state >> 32) as usize % 300, 1 << (state % 6)).unwrap();
+            live.push((unsafe { allocator.alloc(layout) }, layout));
+        }
+    }
+
+    let base = allocator.inspect_source(|source| source.base());
+    let blocks = allocator.blocks();
+    for (ptr, layout) in live {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    blocks
+        .into_iter()
+        .map(|block| BlockInfo {
+            addr: block.addr - base,
+            data: block.data - base,
+            ..block
+        })
+        .collect()
+}
+
+#[test]
+fn same_seed_same_heap() {
+    let (a, b) = (Allocator::new_seeded(42), Allocator::new_seeded(42));
+    assert_eq!(a.seed(), 42);
+    let layout = run(&a);
+    assert!(layout.len() > 10);
+    assert_eq!(layout, run(&b));
+    assert_ne!(layout, run(&Allocator::new_seeded(43)));
+}
@@ -0,0 +1,34 @@
+#![cfg(feature = "serde")]
+
+mod common;
+
+use allocator_speedrun::allocator::{AllocStats, BlockInfo};
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[test]
+fn stats_round_trip_through_json() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(48, 8).unwrap();
+    let a = unsafe { allocator.alloc(layout) };
+    let _b = unsafe { allocator.alloc(layout) };
+    unsafe { allocator.dealloc(a, layout) };
+
+    let stats: AllocStats = serde_json::from_str(&allocator.stats_json()).unwrap();
+    assert_eq!(stats, allocator.stats());
+    assert_eq!(stats.live_bytes, 48);
+    assert_eq!(stats.peak_bytes, 96);
+    assert_eq!(stats.total_allocations, 2);
+    assert_eq!(stats.total_frees, 1);
+}
+
+#[test]
+fn block_info_round_trips_through_json() {
+    let allocator = mock_allocator();
+    unsafe { allocator.alloc(Layout::new::<u64>()) };
+
+    let blocks = allocator.blocks();
+    let json = serde_json::to_string(&blocks).unwrap();
+    let decoded: Vec<BlockInfo> = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, blocks);
+}
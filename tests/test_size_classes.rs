@@ -0,0 +1,114 @@
+mod common;
+
+use allocator_speedrun::allocator::{
+    class_size, size_class, Allocator, AllocatorBuilder, FitStrategy, MIN_BLOCK_SIZE, SIZE_CLASSES,
+};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[test]
+fn size_class_boundaries() {
+    assert_eq!(size_class(0), 0);
+    assert_eq!(size_class(16), 0);
+    assert_eq!(size_class(17), 1);
+    assert_eq!(size_class(32), 1);
+    assert_eq!(size_class(4096), 8);
+    assert_eq!(size_class(4097), SIZE_CLASSES - 1);
+    assert_eq!(size_class(usize::MAX), SIZE_CLASSES - 1);
+}
+
+#[test]
+fn histogram_counts_allocations_per_class() {
+    let allocator = Allocator::with_source(MockSource::new(1 << 20), FitStrategy::FirstFit);
+    for size in [1, 8, 16, 17, 100, 128, 3000, 5000, 100_000] {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    assert_eq!(allocator.size_class_histogram(), [3, 1, 0, 2, 0, 0, 0, 0, 1, 2]);
+}
+
+#[test]
+fn class_size_boundaries() {
+    assert_eq!(class_size(0), Some(16));
+    assert_eq!(class_size(17), Some(32));
+    assert_eq!(class_size(128), Some(128));
+    assert_eq!(class_size(129), Some(160));
+    assert_eq!(class_size(257), Some(320));
+    assert_eq!(class_size(4097), Some(5120));
+    assert_eq!(class_size(usize::MAX), None);
+}
+
+// frees a 17 and a 33 byte block, then returns where a 26 byte one goes
+fn reuse_after_odd_sizes(allocator: &Allocator<MockSource>) -> (*mut u8, *mut u8) {
+    let layout = |size| Layout::from_size_align(size, 1).unwrap();
+    // a block in use after each one keeps them from merging
+    let a = unsafe { allocator.alloc(layout(17)) };
+    unsafe { allocator.alloc(layout(16)) };
+    let b = unsafe { allocator.alloc(layout(33)) };
+    unsafe { allocator.alloc(layout(16)) };
+    unsafe { allocator.dealloc(a, layout(17)) };
+    unsafe { allocator.dealloc(b, layout(33)) };
+    (a, unsafe { allocator.alloc(layout(26)) })
+}
+
+#[test]
+fn size_class_rounding_reuses_odd_sized_holes() {
+    let allocator = mock_allocator();
+    let (first_hole, ptr) = reuse_after_odd_sizes(&allocator);
+    assert_ne!(ptr, first_hole);
+
+    let allocator = mock_allocator();
+    allocator.set_size_class_rounding(true);
+    let (first_hole, ptr) = reuse_after_odd_sizes(&allocator);
+    assert_eq!(ptr, first_hole);
+    assert!(allocator.blocks().iter().all(|block| class_size(block.size) == Some(block.size)));
+}
+
+#[test]
+fn usable_size_matches_the_block() {
+    let sizes = [1, 15, 16, 17, 100, 129, 1000, 5000];
+    for rounding in [false, true] {
+        for min_align in [1, 8, 64] {
+            let allocator = mock_allocator();
+            allocator.set_size_class_rounding(rounding);
+            allocator.set_min_alignment(min_align);
+            let mut ptrs = Vec::new();
+            for size in sizes {
+                let layout = Layout::from_size_align(size, 8).unwrap();
+                let expected = allocator.usable_size(layout);
+                assert!(expected >= size);
+                let ptr = unsafe { allocator.alloc(layout) };
+                let block = allocator.blocks().into_iter().find(|block| block.data == ptr as usize).unwrap();
+                assert_eq!(block.size, expected, "size {size}, rounding {rounding}, min align {min_align}");
+                ptrs.push((ptr, layout));
+            }
+            for (ptr, layout) in ptrs {
+                unsafe { allocator.dealloc(ptr, layout) };
+            }
+        }
+    }
+}
+
+#[test]
+fn tiny_allocations_get_at_least_the_min_alloc_size() {
+    for min in [16, 32, 100] {
+        let allocator = AllocatorBuilder::new(MockSource::new(1 << 16)).min_alloc_size(min).build();
+        let layout = Layout::from_size_align(1, 1).unwrap();
+        let ptrs: Vec<_> = (0..64).map(|_| unsafe { allocator.alloc(layout) }).collect();
+        let used: Vec<_> = allocator.blocks().into_iter().filter(|block| !block.free).collect();
+        assert_eq!(used.len(), 64);
+        assert!(used.iter().all(|block| block.size >= min));
+        assert_eq!(allocator.usable_size(layout), used[0].size);
+        for ptr in ptrs {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+        allocator.check_integrity().unwrap();
+    }
+
+    // less than the default floor doesn't make blocks any smaller
+    let allocator = AllocatorBuilder::new(MockSource::new(1 << 16)).min_alloc_size(1).build();
+    assert_eq!(allocator.config().min_alloc_size, MIN_BLOCK_SIZE);
+}
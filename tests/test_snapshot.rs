@@ -0,0 +1,68 @@
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::{MemorySource, MockSource};
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[test]
+fn restore_undoes_allocations() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let [a, b, _c] = [(); 3].map(|_| unsafe { allocator.alloc(layout) });
+    unsafe { allocator.dealloc(b, layout) };
+
+    let snap = allocator.snapshot();
+    let blocks = allocator.blocks();
+    let stats = allocator.stats();
+    let brk = allocator.inspect_source(|source| source.current_break());
+
+    unsafe { allocator.dealloc(a, layout) };
+    for size in [16, 200, 3000] {
+        unsafe { allocator.alloc(Layout::from_size_align(size, 8).unwrap()) };
+    }
+    assert_ne!(allocator.blocks(), blocks);
+
+    unsafe { allocator.restore(snap.clone()) };
+    assert_eq!(allocator.blocks(), blocks);
+    assert_eq!(allocator.stats(), stats);
+    assert_eq!(allocator.inspect_source(|source| source.current_break()), brk);
+    assert_eq!(allocator.snapshot(), snap);
+
+    // and the restored heap hands out the same blocks again
+    assert_eq!(unsafe { allocator.alloc(layout) }, b);
+}
+
+#[test]
+fn restore_into_another_mock() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let first = mock_allocator();
+    let ptrs: Vec<_> = (0..4).map(|_| unsafe { first.alloc(layout) }).collect();
+    unsafe { first.dealloc(ptrs[2], layout) };
+
+    let second = mock_allocator();
+    unsafe { second.restore(first.snapshot()) };
+    assert_eq!(second.snapshot(), first.snapshot());
+
+    let offset = |allocator: &Allocator<MockSource>, ptr: *mut u8| ptr as usize - allocator.inspect_source(MockSource::base);
+    let reused = unsafe { second.alloc(layout) };
+    assert_eq!(offset(&second, reused), offset(&first, ptrs[2]));
+}
+
+#[test]
+fn restored_blocks_trim_back_to_where_the_heap_started() {
+    // an unaligned break leaves padding in front of the first header
+    let mut source = MockSource::new(1 << 16);
+    source.grow(3).unwrap();
+    let allocator = Allocator::with_source(source, FitStrategy::FirstFit);
+    let start = allocator.inspect_source(|source| source.current_break());
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    let snap = allocator.snapshot();
+
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(allocator.inspect_source(|source| source.current_break()), start);
+    unsafe { allocator.restore(snap) };
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(allocator.inspect_source(|source| source.current_break()), start);
+}
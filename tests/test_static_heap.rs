@@ -0,0 +1,40 @@
+use allocator_speedrun::allocator::Allocator;
+use std::alloc::{GlobalAlloc, Layout};
+
+const HEAP_SIZE: usize = 16384;
+
+static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+
+#[test]
+fn carves_only_from_the_buffer() {
+    // SAFETY: this is the only place HEAP is touched.
+    let buf = unsafe { (&raw mut HEAP).as_mut() }.unwrap();
+    let (start, end) = (buf.as_ptr() as usize, buf.as_ptr() as usize + HEAP_SIZE);
+    let allocator = Allocator::from_static(buf);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+
+    let mut ptrs = Vec::new();
+    loop {
+        let ptr = unsafe { allocator.alloc(layout) };
+        if ptr.is_null() {
+            break;
+        }
+        assert!(start <= ptr as usize && ptr as usize + 256 <= end);
+        ptrs.push(ptr);
+    }
+    assert!(ptrs.len() > HEAP_SIZE / 512);
+
+    // it stays exhausted, and never went past the end of the buffer
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+    assert!(allocator.current_break() <= end);
+
+    // and freeing makes room again
+    unsafe { allocator.dealloc(ptrs.pop().unwrap(), layout) };
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(!ptr.is_null());
+    allocator.check_integrity().unwrap();
+
+    for ptr in ptrs.into_iter().chain([ptr]) {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+}
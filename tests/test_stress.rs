@@ -0,0 +1,81 @@
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout};
+
+// xorshift, so every run does the same thing
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+struct Live {
+    ptr: *mut u8,
+    layout: Layout,
+    tag: u8,
+}
+
+fn check(live: &Live) {
+    let bytes = unsafe { std::slice::from_raw_parts(live.ptr, live.layout.size()) };
+    assert!(bytes.iter().all(|&b| b == live.tag), "allocation at {:?} was clobbered", live.ptr);
+}
+
+fn stress(strategy: FitStrategy, seed: u64) {
+    let allocator = Allocator::with_source(MockSource::new(16 << 20), strategy);
+    let mut rng = Rng(seed);
+    let mut live: Vec<Live> = Vec::new();
+
+    for i in 0..5000 {
+        if live.is_empty() || rng.below(5) < 3 {
+            let size = 1 + rng.below(2048) as usize;
+            let align = 1 << rng.below(13);
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let ptr = unsafe { allocator.alloc(layout) };
+            assert!(!ptr.is_null(), "{layout:?} failed");
+            assert!((ptr as usize).is_multiple_of(align), "{ptr:?} isn't aligned to {align}");
+            let tag = i as u8 | 1;
+            unsafe { ptr.write_bytes(tag, size) };
+            live.push(Live { ptr, layout, tag });
+        } else {
+            let victim = live.swap_remove(rng.below(live.len() as u64) as usize);
+            check(&victim);
+            unsafe { allocator.dealloc(victim.ptr, victim.layout) };
+        }
+
+        if i % 500 == 0 {
+            assert_eq!(allocator.check_integrity(), Ok(()));
+        }
+    }
+
+    live.iter().for_each(check);
+    assert_eq!(allocator.check_integrity(), Ok(()));
+    for victim in live {
+        unsafe { allocator.dealloc(victim.ptr, victim.layout) };
+    }
+    assert_eq!(allocator.check_integrity(), Ok(()));
+    assert_eq!(allocator.stats().live_bytes, 0);
+}
+
+#[test]
+fn first_fit_survives_random_alignments() {
+    stress(FitStrategy::FirstFit, 0x9e37_79b9_7f4a_7c15);
+}
+
+#[test]
+fn best_fit_survives_random_alignments() {
+    stress(FitStrategy::BestFit, 0xd1b5_4a32_d192_ed03);
+}
+
+#[test]
+fn next_fit_survives_random_alignments() {
+    stress(FitStrategy::NextFit, 0x8cb9_2ba7_2f3d_8dd7);
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, AllocatorBuilder, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::GlobalAlloc;
+use std::collections::HashMap;
+use common::{layout, mock_allocator};
+
+#[test]
+fn bytes_are_summed_per_tag() {
+    let allocator = mock_allocator();
+    let a = allocator.allocate_tagged(layout(64), 1);
+    let b = allocator.allocate_tagged(layout(128), 1);
+    let c = allocator.allocate_tagged(layout(256), 2);
+    let d = allocator.allocate_tagged(layout(32), 2);
+    let untagged = unsafe { allocator.alloc(layout(48)) };
+    assert_eq!(allocator.bytes_by_tag(), HashMap::from([(0, 48), (1, 192), (2, 288)]));
+
+    unsafe { allocator.dealloc(b, layout(128)) };
+    unsafe { allocator.dealloc(d, layout(32)) };
+    assert_eq!(allocator.bytes_by_tag(), HashMap::from([(0, 48), (1, 64), (2, 256)]));
+
+    // a freed block doesn't carry its tag over to whoever gets it next
+    let reused = unsafe { allocator.alloc(layout(128)) };
+    assert_eq!(reused, b);
+    assert_eq!(allocator.bytes_by_tag(), HashMap::from([(0, 176), (1, 64), (2, 256)]));
+
+    for (ptr, size) in [(a, 64), (c, 256), (untagged, 48), (reused, 128)] {
+        unsafe { allocator.dealloc(ptr, layout(size)) };
+    }
+    assert!(allocator.bytes_by_tag().is_empty());
+}
+
+#[test]
+fn failure_is_null() {
+    let allocator = Allocator::with_source(MockSource::new(4096), FitStrategy::FirstFit);
+    assert!(allocator.allocate_tagged(layout(8192), 1).is_null());
+    assert!(allocator.bytes_by_tag().is_empty());
+}
+
+#[test]
+fn realloc_keeps_the_tag_when_it_moves() {
+    let allocator = mock_allocator();
+    let a = allocator.allocate_tagged(layout(64), 7);
+    unsafe { a.write_bytes(0xaa, 64) };
+    let untagged = unsafe { allocator.alloc(layout(32)) };
+
+    let moved = unsafe { allocator.realloc(a, layout(64), 1024) };
+    assert_ne!(moved, a);
+    assert!(unsafe { std::slice::from_raw_parts(moved, 64) }.iter().all(|&byte| byte == 0xaa));
+    let tags = allocator.bytes_by_tag();
+    assert_eq!(tags.len(), 2);
+    assert_eq!(tags[&0], 32);
+    assert!(tags[&7] >= 1024);
+
+    unsafe { allocator.dealloc(moved, layout(1024)) };
+    unsafe { allocator.dealloc(untagged, layout(32)) };
+    assert!(allocator.bytes_by_tag().is_empty());
+}
+
+#[test]
+fn tagged_allocations_reuse_lazily_freed_blocks() {
+    let allocator = AllocatorBuilder::new(MockSource::new(1 << 16)).lazy_frees(true).capacity(1024).build();
+    let a = unsafe { allocator.alloc(layout(512)) };
+    unsafe { allocator.dealloc(a, layout(512)) };
+    assert_eq!(allocator.stats().total_frees, 0);
+
+    // too big for what's left, so the pending free goes back first
+    let tagged = allocator.allocate_tagged(layout(768), 3);
+    assert_eq!(tagged, a);
+    assert_eq!(allocator.stats().total_frees, 1);
+    assert_eq!(allocator.bytes_by_tag(), HashMap::from([(3, 768)]));
+    unsafe { allocator.dealloc(tagged, layout(768)) };
+}
+
+#[test]
+fn restore_keeps_tags() {
+    let allocator = mock_allocator();
+    let a = allocator.allocate_tagged(layout(64), 1);
+    allocator.allocate_tagged(layout(128), 2);
+    let snap = allocator.snapshot();
+    let tags = allocator.bytes_by_tag();
+
+    unsafe { allocator.dealloc(a, layout(64)) };
+    unsafe { allocator.restore(snap) };
+    assert_eq!(allocator.bytes_by_tag(), tags);
+}
@@ -0,0 +1,128 @@
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const THREADS: usize = 4;
+const ROUNDS: usize = 1000;
+
+// leaked for the threads, and bigger than the shared mock so they all fit
+fn leaked_allocator() -> &'static Allocator<MockSource> {
+    Box::leak(Box::new(Allocator::with_source(MockSource::new(1 << 20), FitStrategy::FirstFit)))
+}
+
+// every thread repeatedly allocates a handful of small blocks, checks
+// nobody else scribbled over them and frees them again
+fn churn(allocator: &'static Allocator<MockSource>) {
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            thread::spawn(move || {
+                for round in 0..ROUNDS {
+                    let layouts = [8, 24, 64, 200, 1000].map(|size| Layout::from_size_align(size, 8).unwrap());
+                    let ptrs = layouts.map(|layout| unsafe { allocator.alloc(layout) });
+                    let byte = (t * ROUNDS + round) as u8;
+                    for (&ptr, layout) in ptrs.iter().zip(&layouts) {
+                        assert!(!ptr.is_null());
+                        unsafe { ptr.write_bytes(byte, layout.size()) };
+                    }
+                    for (&ptr, &layout) in ptrs.iter().zip(&layouts) {
+                        let bytes = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+                        assert!(bytes.iter().all(|&b| b == byte));
+                        unsafe { allocator.dealloc(ptr, layout) };
+                    }
+                }
+            })
+        })
+        .collect();
+    // joining also waits for the exiting threads to flush their caches
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn cache_takes_the_lock_less_often() {
+    let uncached = leaked_allocator();
+    churn(uncached);
+
+    let cached = leaked_allocator();
+    cached.enable_thread_cache();
+    churn(cached);
+
+    assert!(
+        cached.lock_acquisitions() * 10 < uncached.lock_acquisitions(),
+        "{} locks with the cache, {} without",
+        cached.lock_acquisitions(),
+        uncached.lock_acquisitions()
+    );
+}
+
+#[test]
+fn caches_are_flushed_on_thread_exit() {
+    let allocator = leaked_allocator();
+    allocator.enable_thread_cache();
+    churn(allocator);
+
+    let stats = allocator.stats();
+    assert_eq!(stats.live_bytes, 0);
+    assert_eq!(stats.total_allocations, (THREADS * ROUNDS * 5) as u64);
+    assert_eq!(stats.total_frees, stats.total_allocations);
+    assert!(allocator.blocks().iter().all(|block| block.free));
+}
+
+#[test]
+fn large_allocations_skip_the_cache() {
+    let allocator = leaked_allocator();
+    allocator.enable_thread_cache();
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+
+    let before = allocator.lock_acquisitions();
+    let ptr = unsafe { allocator.alloc(layout) };
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(allocator.lock_acquisitions(), before + 2);
+    assert_eq!(allocator.stats().live_bytes, 0);
+}
+
+#[test]
+fn depot_neither_loses_nor_duplicates_blocks() {
+    let allocator = leaked_allocator();
+    allocator.enable_thread_cache();
+    let held = Arc::new(Mutex::new(HashSet::new()));
+    let layout = Layout::from_size_align(48, 8).unwrap();
+
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            let held = held.clone();
+            thread::spawn(move || {
+                let mut mine = Vec::new();
+                for round in 0..2000 {
+                    // swing between holding many blocks and none, so caches
+                    // keep overflowing into the depot and refilling from it
+                    if (round / 40 + t) % 2 == 0 {
+                        let ptr = unsafe { allocator.alloc(layout) };
+                        assert!(held.lock().unwrap().insert(ptr as usize), "{ptr:?} handed out twice");
+                        mine.push(ptr);
+                    } else if let Some(ptr) = mine.pop() {
+                        assert!(held.lock().unwrap().remove(&(ptr as usize)));
+                        unsafe { allocator.dealloc(ptr, layout) };
+                    }
+                }
+                for ptr in mine {
+                    assert!(held.lock().unwrap().remove(&(ptr as usize)));
+                    unsafe { allocator.dealloc(ptr, layout) };
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // running out of memory makes the heap take back everything the depot
+    // holds, after which no block may be left in use
+    let huge = Layout::from_size_align(2 << 20, 8).unwrap();
+    assert!(unsafe { allocator.alloc(huge) }.is_null());
+    assert!(allocator.blocks().iter().all(|block| block.free));
+}
@@ -0,0 +1,39 @@
+#![cfg(feature = "trace")]
+
+mod common;
+
+use allocator_speedrun::allocator::Allocator;
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[inline(never)]
+fn leak_one(allocator: &Allocator<MockSource>) {
+    unsafe { allocator.alloc(Layout::from_size_align(48, 8).unwrap()) };
+}
+
+#[test]
+fn leaks_are_reported_with_their_backtrace() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    let freed = unsafe { allocator.alloc(layout) };
+    unsafe { allocator.dealloc(freed, layout) };
+    leak_one(&allocator);
+
+    let report = allocator.leak_report();
+    assert_eq!(report.matches("leaked ").count(), 1, "{report}");
+    assert!(report.contains("leaked 48 bytes"), "{report}");
+    assert!(report.contains("leak_one"), "{report}");
+}
+
+#[test]
+fn nothing_is_traced_after_an_allocation_fails() {
+    let allocator = mock_allocator();
+    let too_big = Layout::from_size_align(1 << 20, 8).unwrap();
+    assert!(unsafe { allocator.alloc(too_big) }.is_null());
+    leak_one(&allocator);
+
+    let report = allocator.leak_report();
+    assert!(report.is_empty(), "{report}");
+    assert_eq!(allocator.stats().live_bytes, 48);
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::{MemorySource, MockSource};
+use std::alloc::{GlobalAlloc, Layout};
+use common::mock_allocator;
+
+#[test]
+fn coalesced_tail_is_trimmed_in_one_go() {
+    let allocator = mock_allocator();
+    let baseline = allocator.current_break();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let [a, b, c] = [(); 3].map(|_| unsafe { allocator.alloc(layout) });
+
+    // neither of these reaches the break on its own
+    unsafe { allocator.dealloc(b, layout) };
+    unsafe { allocator.dealloc(a, layout) };
+    assert!(allocator.current_break() > baseline);
+
+    // freeing c merges all three, which then end at the break
+    unsafe { allocator.dealloc(c, layout) };
+    assert_eq!(allocator.current_break(), baseline);
+    assert!(allocator.blocks().is_empty());
+}
+
+#[test]
+fn trims_only_behind_the_last_used_block() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let [a, b, c] = [(); 3].map(|_| unsafe { allocator.alloc(layout) });
+
+    unsafe { allocator.dealloc(b, layout) };
+    unsafe { allocator.dealloc(c, layout) };
+    let blocks = allocator.blocks();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].data, a as usize);
+    assert_eq!(allocator.current_break(), a as usize + 64);
+}
+
+#[test]
+fn reserved_capacity_is_kept() {
+    let allocator = Allocator::with_capacity_in(4096, MockSource::new(1 << 16));
+    let reserved = allocator.current_break();
+    let layout = Layout::from_size_align(8192, 8).unwrap();
+
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(allocator.current_break() > reserved);
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(allocator.current_break(), reserved);
+    assert_eq!(allocator.blocks().len(), 1);
+}
+
+#[test]
+fn freeing_the_only_block_restores_the_break() {
+    // the chain starts at a sentinel, so the first real block is freed and
+    // trimmed like any other
+    for strategy in [FitStrategy::FirstFit, FitStrategy::BestFit, FitStrategy::NextFit] {
+        let allocator = Allocator::with_source(MockSource::new(1 << 16), strategy);
+        let baseline = allocator.current_break();
+        let layout = Layout::from_size_align(100, 16).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(ptr, layout) };
+        assert_eq!(allocator.current_break(), baseline);
+        assert!(allocator.blocks().is_empty());
+        assert_eq!(allocator.check_integrity(), Ok(()));
+
+        // and the heap picks up again from there
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(allocator.blocks().len(), 1);
+    }
+}
+
+#[test]
+fn bytes_from_os_returns_to_where_it_started() {
+    let allocator = mock_allocator();
+    assert_eq!(allocator.bytes_from_os(), 0);
+    let small = Layout::from_size_align(64, 8).unwrap();
+    let first = unsafe { allocator.alloc(small) };
+    let before = allocator.bytes_from_os();
+    assert!(before > 0);
+
+    let big = Layout::from_size_align(8192, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(big) };
+    assert!(allocator.bytes_from_os() >= before + 8192);
+    unsafe { allocator.dealloc(ptr, big) };
+    assert_eq!(allocator.bytes_from_os(), before);
+
+    unsafe { allocator.dealloc(first, small) };
+    assert_eq!(allocator.bytes_from_os(), 0);
+}
+
+#[test]
+fn small_tails_stay_below_the_decommit_threshold() {
+    let small = Layout::from_size_align(64, 8).unwrap();
+    let big = Layout::from_size_align(8192, 8).unwrap();
+
+    // a tail bigger than the threshold is still given back
+    let allocator = mock_allocator();
+    allocator.set_decommit_threshold(1024);
+    let first = unsafe { allocator.alloc(small) };
+    let before = allocator.bytes_from_os();
+    let ptr = unsafe { allocator.alloc(big) };
+    unsafe { allocator.dealloc(ptr, big) };
+    assert_eq!(allocator.bytes_from_os(), before);
+
+    // but with a bigger threshold it's kept, and reused by the next request
+    allocator.set_decommit_threshold(1 << 14);
+    let ptr = unsafe { allocator.alloc(big) };
+    let grown = allocator.bytes_from_os();
+    unsafe { allocator.dealloc(ptr, big) };
+    assert_eq!(allocator.bytes_from_os(), grown);
+    assert!(allocator.blocks().last().unwrap().free);
+    let again = unsafe { allocator.alloc(big) };
+    assert_eq!(again, ptr);
+    assert_eq!(allocator.bytes_from_os(), grown);
+
+    unsafe { allocator.dealloc(again, big) };
+    unsafe { allocator.dealloc(first, small) };
+    allocator.check_integrity().unwrap();
+}
+
+#[test]
+fn the_tail_moves_back_when_the_last_block_goes() {
+    let allocator = mock_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let [a, b] = [(); 2].map(|_| unsafe { allocator.alloc(layout) });
+    assert!(!allocator.is_tail(a));
+    assert!(allocator.is_tail(b));
+
+    unsafe { allocator.dealloc(b, layout) };
+    assert!(!allocator.is_tail(b));
+    assert!(allocator.is_tail(a));
+    let mut local = 0u8;
+    assert!(!allocator.is_tail(&mut local));
+}
+
+#[test]
+fn padding_in_front_of_the_first_header_is_given_back() {
+    for skew in 1..8 {
+        // something else left the break unaligned
+        let mut source = MockSource::new(1 << 16);
+        source.grow(skew).unwrap();
+        let allocator = Allocator::with_source(source, FitStrategy::FirstFit);
+        let baseline = allocator.current_break();
+        let layout = Layout::from_size_align(100, 16).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(ptr, layout) };
+        assert_eq!(allocator.current_break(), baseline, "skewed by {skew}");
+        assert_eq!(allocator.bytes_from_os(), 0);
+    }
+}
@@ -1,12 +1,17 @@
+// the global allocator sits on the real break, which Miri can't grow
+#![cfg(not(miri))]
 #![feature(allocator_api)]
 
-use std::alloc::{GlobalAlloc, Layout};
-use allocator_speedrun::allocator::Allocator;
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{Allocator as _, Layout};
+use std::time::{Duration, Instant};
 
 #[global_allocator]
 static ALLOCATOR: Allocator = Allocator::new();
 
 #[test]
+#[allow(clippy::needless_range_loop)]
 pub fn test_vec_alloc() {
     let allocator = Allocator::new();
     let mut v = Vec::with_capacity_in(1000000, &allocator);
@@ -18,3 +23,38 @@ pub fn test_vec_alloc() {
         assert_eq!(i, v[i]);
     }
 }
+
+#[test]
+pub fn test_vec_growth_reuses_its_block() {
+    // a mock, so nothing else moves the break while the Vec grows
+    let allocator = Allocator::with_source(MockSource::new(16 << 20), FitStrategy::FirstFit);
+    let mut v = Vec::new_in(&allocator);
+    let start = Instant::now();
+    for i in 0..1000000 {
+        v.push(i);
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+    for (i, &x) in v.iter().enumerate() {
+        assert_eq!(i, x);
+    }
+
+    // growing in place leaves no abandoned buffers behind
+    let blocks = allocator.blocks();
+    assert_eq!(blocks.iter().filter(|block| !block.free).count(), 1);
+    assert!(blocks.len() <= 3, "{blocks:#?}");
+}
+
+#[test]
+pub fn test_allocate_returns_the_whole_block() {
+    let allocator = Allocator::with_source(MockSource::new(1 << 20), FitStrategy::FirstFit);
+    let ptr = allocator.allocate(Layout::from_size_align(10, 1).unwrap()).unwrap();
+    assert_eq!(ptr.len(), 16);
+
+    // the slack is the Vec's to use, and it gets freed with the larger size
+    let mut v = unsafe { Vec::from_raw_parts_in(ptr.cast::<u8>().as_ptr(), 0, ptr.len(), &allocator) };
+    assert_eq!(v.capacity(), 16);
+    v.extend(0..16);
+    assert_eq!(allocator.blocks().iter().filter(|block| !block.free).count(), 1);
+    drop(v);
+    assert_eq!(allocator.stats().live_bytes, 0);
+}
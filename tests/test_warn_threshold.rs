@@ -0,0 +1,51 @@
+use allocator_speedrun::allocator::{Allocator, FitStrategy};
+use allocator_speedrun::source::MockSource;
+use std::alloc::{GlobalAlloc, Layout};
+use std::env;
+use std::process::{Command, Output};
+
+const CHILD_VAR: &str = "WARN_THRESHOLD_CHILD";
+const WARNING: &str = "more than the warn threshold";
+
+// allocates `size` bytes under a 1 MiB warn threshold, in a child process
+// so its stderr can be looked at; returns None in the child
+fn allocate_in_child(test: &str, size: usize) -> Option<Output> {
+    if env::var_os(CHILD_VAR).is_none() {
+        let output = Command::new(env::current_exe().unwrap())
+            .args([test, "--exact", "--nocapture", "--test-threads=1"])
+            .env(CHILD_VAR, "1")
+            .output()
+            .unwrap();
+        return Some(output);
+    }
+
+    let allocator = Allocator::with_source(MockSource::new(4 << 20), FitStrategy::FirstFit);
+    allocator.set_warn_threshold(1 << 20);
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    // the allocation goes ahead either way
+    assert!(!ptr.is_null());
+    unsafe { ptr.write_bytes(0xaa, size) };
+    unsafe { allocator.dealloc(ptr, layout) };
+    None
+}
+
+#[test]
+fn large_allocations_warn_and_succeed() {
+    let Some(output) = allocate_in_child("large_allocations_warn_and_succeed", 2 << 20) else {
+        return;
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+    assert!(stderr.contains(&format!("allocating {} bytes, {WARNING}", 2 << 20)), "stderr: {stderr}");
+}
+
+#[test]
+fn allocations_under_the_threshold_say_nothing() {
+    let Some(output) = allocate_in_child("allocations_under_the_threshold_say_nothing", 1 << 20) else {
+        return;
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+    assert!(!stderr.contains(WARNING), "stderr: {stderr}");
+}
@@ -0,0 +1,70 @@
+#![feature(allocator_api)]
+
+mod common;
+
+use allocator_speedrun::arena::ArenaAllocator;
+use std::alloc::{Allocator as _, GlobalAlloc};
+use std::ptr::NonNull;
+use common::{layout, mock_allocator};
+
+fn zeroed(ptr: NonNull<[u8]>) -> bool {
+    unsafe { ptr.as_ref() }.iter().all(|&byte| byte == 0)
+}
+
+#[test]
+fn fresh_and_reused_blocks_come_back_zeroed() {
+    let allocator = mock_allocator();
+    let fresh = allocator.allocate_zeroed(layout(256)).unwrap();
+    assert!(zeroed(fresh));
+    unsafe { fresh.cast::<u8>().write_bytes(0xaa, fresh.len()) };
+    // keeps the freed block from being trimmed away
+    unsafe { allocator.alloc(layout(64)) };
+    unsafe { allocator.deallocate(fresh.cast(), layout(256)) };
+
+    let reused = allocator.allocate_zeroed(layout(256)).unwrap();
+    assert_eq!(reused.cast::<u8>(), fresh.cast::<u8>());
+    assert!(zeroed(reused));
+}
+
+#[test]
+fn heap_grown_back_after_a_trim_is_cleared() {
+    let allocator = mock_allocator();
+    let brk = allocator.current_break();
+    let ptr = unsafe { allocator.alloc(layout(4096)) };
+    unsafe { ptr.write_bytes(0xbb, 4096) };
+    unsafe { allocator.dealloc(ptr, layout(4096)) };
+    assert_eq!(allocator.current_break(), brk);
+
+    // the same memory again, but it's been written to since the mock gave it out
+    let again = allocator.allocate_zeroed(layout(4096)).unwrap();
+    assert_eq!(again.cast::<u8>().as_ptr(), ptr);
+    assert!(zeroed(again));
+}
+
+#[test]
+fn zeroed_boxes_and_global_alloc_zeroed() {
+    let allocator = mock_allocator();
+    for _ in 0..3 {
+        let mut boxed = unsafe { Box::<[u8], _>::new_zeroed_slice_in(300, &allocator).assume_init() };
+        assert!(boxed.iter().all(|&byte| byte == 0));
+        boxed.fill(0xcc);
+        let ptr = unsafe { allocator.alloc_zeroed(layout(100)) };
+        assert!(unsafe { std::slice::from_raw_parts(ptr, 100) }.iter().all(|&byte| byte == 0));
+        unsafe { ptr.write_bytes(0xdd, 100) };
+        unsafe { allocator.dealloc(ptr, layout(100)) };
+    }
+}
+
+#[test]
+fn arenas_clear_reused_blocks() {
+    let arena = ArenaAllocator::<4096>::new();
+    let first = arena.allocate_zeroed(layout(128)).unwrap();
+    assert!(zeroed(first));
+    unsafe { first.cast::<u8>().write_bytes(0xee, first.len()) };
+    arena.allocate(layout(16)).unwrap();
+    unsafe { arena.deallocate(first.cast(), layout(128)) };
+
+    let reused = arena.allocate_zeroed(layout(128)).unwrap();
+    assert_eq!(reused.cast::<u8>(), first.cast::<u8>());
+    assert!(zeroed(reused));
+}